@@ -7,6 +7,7 @@ var ctx;
 var width;
 var height;
 var image_data;
+var pixel_view;
 var ASPECT_RATIO = 2;
 
 // Control variables
@@ -18,20 +19,38 @@ let last_frame = 0;
 let fps = 0;
 
 const mem_size = 50;
-const memory = new WebAssembly.Memory({initial: mem_size, shared: false});
-var import_object = {
-    env: {memory}
-};
+// The threads build (make threads) imports shared memory instead of making its own, has to be
+// at most the --max-memory it was linked with
+const max_mem_size = 16384;
+const worker_stack_size = 1 << 20;
+var memory;
+var threaded = false;
 
-let wasm_request = WebAssembly.instantiateStreaming(fetch('m3d_wasm.wasm'), import_object);
+let wasm_request = WebAssembly.compileStreaming(fetch('m3d_wasm.wasm'));
 let image_request = fetch('images.txt');
 Promise.all([wasm_request, image_request]).then(async (promised_values) => {
-    let wasm_instance = promised_values[0];
+    let wasm_module = promised_values[0];
     let fetched_images = promised_values[1];
 
-    if (test_wasm(wasm_instance.instance)) {
+    threaded = WebAssembly.Module.exports(wasm_module).some((entry) => entry.name == 'worker_entry');
+    if (threaded && !crossOriginIsolated) {
+        alert('The threads build needs the page served with COOP/COEP headers');
+        return;
+    }
+    memory = new WebAssembly.Memory({initial: mem_size, maximum: max_mem_size, shared: threaded});
+    let wasm_instance = await WebAssembly.instantiate(wasm_module, {
+        env: {
+            memory,
+            performance_now: () => performance.now()
+        }
+    });
+
+    if (test_wasm(wasm_instance)) {
         console.log('WASM PASSED');
-        instance = wasm_instance.instance;
+        instance = wasm_instance;
+        if (threaded) {
+            start_workers(wasm_module);
+        }
         await launch_init(fetched_images);
     }
     else {
@@ -39,6 +58,22 @@ Promise.all([wasm_request, image_request]).then(async (promised_values) => {
     }
 });
 
+// One worker per core past this one, each with its own stack and thread locals. They don't
+// need to be up yet when rendering starts, frames are drawn with whichever have
+function start_workers(wasm_module) {
+    let exports = instance.exports;
+    for (let k = 1; k < navigator.hardwareConcurrency; k++) {
+        let worker = new Worker('worker.js');
+        worker.postMessage({
+            module: wasm_module,
+            memory,
+            stack: exports.alloc_worker_memory(worker_stack_size, 16),
+            stack_size: worker_stack_size,
+            tls: exports.alloc_worker_memory(exports.__tls_size.value, exports.__tls_align.value)
+        });
+    }
+}
+
 function test_wasm(input_instance) {
     // Sanity check
     if (input_instance.exports.test_return_5() != 5) {
@@ -46,7 +81,9 @@ function test_wasm(input_instance) {
     }
 
     // Return Uint8Array test
-    let returned_arr = unwrapUint8Array(input_instance, input_instance.exports.test_return_arr());
+    let returned_handle = input_instance.exports.test_return_arr();
+    let returned_arr = Array.from(unwrapUint8Array(input_instance, returned_handle));
+    input_instance.exports.free_uint8_arr(returned_handle);
     if (returned_arr.length != 5) {
         return false;
     }
@@ -125,12 +162,27 @@ async function launch_init(img_response) {
 
     ctx = gameCanvas.getContext('2d');
 
+    // make_game_state only reads the textures, so they're ours to free
     gs_manager = instance.exports.make_game_state(width, height, boxed_images);
     instance.exports.free_uint8_arr(boxed_images);
+    if (gs_manager == 0) {
+        let message_handle = instance.exports.get_last_error();
+        // TextDecoder won't take a view of shared memory
+        let message = new TextDecoder().decode(unwrapUint8Array(instance, message_handle).slice());
+        instance.exports.free_uint8_arr(message_handle);
+        alert('GAME STATE FAILED: ' + message);
+        return;
+    }
 
-    let raw_data = instance.exports.get_pixel_data(gs_manager)
-
-    image_data = new ImageData(unwrapUint8ClampedArray(instance, raw_data), width);
+    // Borrowed handle, the view stays valid after the handle is freed. ImageData can't be
+    // made over shared memory, so the threads build copies every frame into its own
+    let raw_data = instance.exports.get_pixel_data(gs_manager);
+    pixel_view = unwrapUint8ClampedArray(instance, raw_data);
+    image_data = threaded ? new ImageData(width, height) : new ImageData(pixel_view, width);
+    instance.exports.free_uint8_arr(raw_data);
+    if (threaded) {
+        instance.exports.set_raster_threads(gs_manager, navigator.hardwareConcurrency);
+    }
 
     requestAnimationFrame(renderLoop);
 }
@@ -151,6 +203,9 @@ function renderLoop(curr_time) {
     requestAnimationFrame(renderLoop);
     instance.exports.render_game(gs_manager, curr_time);
 
+    if (threaded) {
+        image_data.data.set(pixel_view);
+    }
     ctx.putImageData(image_data, 0, 0);
 }
 
@@ -0,0 +1,15 @@
+// Tile rendering worker for the threads build (make threads). launcher.js sends the compiled
+// module, the shared memory and a stack and thread local block it allocated for this worker
+onmessage = function(e) {
+    let instance = new WebAssembly.Instance(e.data.module, {
+        env: {
+            memory: e.data.memory,
+            performance_now: () => performance.now()
+        }
+    });
+    // The stack grows down from the end of its block
+    instance.exports.__stack_pointer.value = e.data.stack + e.data.stack_size;
+    instance.exports.__wasm_init_tls(e.data.tls);
+    // Never returns, the worker sleeps in here until there are tiles to draw
+    instance.exports.worker_entry();
+};
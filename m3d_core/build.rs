@@ -1,19 +1,24 @@
 use std::fs;
 
-pub fn main() {
+// io errors don't say which file they're about
+fn read(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|err| format!("Image packaging failed, {}: {}", path, err))
+}
+
+pub fn main() -> Result<(), String> {
     println!("cargo:rerun-if-changed=./img");
 
-    let images_names = fs::read_to_string("./img/index.txt").expect("Image packaging failed");
+    let images_names = read("./img/index.txt")?;
     let images_split = images_names.split("\n").collect::<Vec<&str>>();
     let mut acc = String::new();
 
     for (i, img_name) in images_split.iter().enumerate() {
-        let contents =
-            fs::read_to_string("./img/".to_string() + img_name).expect("Couldn't open image file");
+        let contents = read(&("./img/".to_string() + img_name))?;
         acc += &contents;
         if i + 1 < images_split.len() {
             acc += "\n";
         }
     }
-    fs::write("./images.txt", acc).unwrap();
+    fs::write("./images.txt", acc)
+        .map_err(|err| format!("Image packaging failed, ./images.txt: {}", err))
 }
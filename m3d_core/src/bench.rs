@@ -0,0 +1,517 @@
+use m3d_core::cli::{self, parse_number, read};
+use m3d_core::rendering::gfx::{Color, Light, NearLight};
+use m3d_core::rendering::raster::RasterMode;
+use m3d_core::rendermath::Point3D;
+use m3d_core::world::{Block, BlockData, Material, Shape, WorldData};
+use m3d_core::GameState;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::{fs, process};
+
+const USAGE: &str = "usage: m3d_bench [options]
+  --scene <name>         only run this scene, can be given more than once
+  --frames <n>           frames timed per scene (200)
+  --warmup <n>           frames rendered before timing starts (10)
+  --size <W>x<H>         frame size (1265x632)
+  --threads <n>          render threads (all of them)
+  --occlusion <on|off>   cull bundles hidden behind nearer ones (on)
+  --textures <file>      texture pack (images.txt)
+  --save <file>          write the results as a baseline
+  --baseline <file>      compare against a saved baseline
+  --threshold <ratio>    slowdown in median ms/frame that counts as a regression (0.1)
+Exits with 1 if anything regressed";
+
+// Fixed camera setups over worlds built in code, so runs are comparable between commits.
+// The camera turns a little every frame so the percentiles cover more than one view
+struct Scene {
+    name: &'static str,
+    // None is GameState::new's floor
+    world: Option<fn() -> WorldData>,
+    position: (f32, f32, f32),
+    look: (f32, f32),
+    turn_per_frame: f32,
+}
+
+const SCENES: [Scene; 4] = [
+    Scene {
+        name: "flat_floor",
+        world: None,
+        position: (290.0, -50.0, 35.0),
+        look: (0.0, -1.0),
+        turn_per_frame: 0.002,
+    },
+    Scene {
+        name: "dense_terrain",
+        world: Some(dense_terrain),
+        position: (40.0, -40.0, 40.0),
+        look: (0.8, -0.5),
+        turn_per_frame: 0.004,
+    },
+    Scene {
+        name: "many_lights",
+        world: Some(many_lights),
+        position: (20.0, -30.0, 20.0),
+        look: (0.8, -0.7),
+        turn_per_frame: 0.002,
+    },
+    Scene {
+        name: "close_up_walls",
+        world: Some(close_up_walls),
+        position: (36.0, 20.0, 8.0),
+        look: (0.0, 0.0),
+        turn_per_frame: 0.003,
+    },
+];
+
+fn block(material: Material) -> Block {
+    Block::Full(BlockData::new(Shape::Block, material))
+}
+
+// Rolling hills, solid down to the bottom of the world so every column has a few exposed faces
+fn dense_terrain() -> WorldData {
+    let mut world_data = WorldData::new(6);
+    let size = 6 * 16;
+    for x in 0..size {
+        for z in 0..size {
+            let height = 20.0
+                + 6.0 * (x as f32 / 9.0).sin()
+                + 5.0 * (z as f32 / 7.0).cos()
+                + 3.0 * ((x + z) as f32 / 4.0).sin();
+            let top = height.max(0.0) as usize;
+            world_data.set(x, top, z, block(Material::Grass)).unwrap();
+            for y in (top + 1)..size {
+                world_data.set(x, y, z, block(Material::Dirt)).unwrap();
+            }
+        }
+    }
+    world_data
+}
+
+// A floor under a grid of 64 colored lights
+fn many_lights() -> WorldData {
+    let mut world_data = WorldData::new(6);
+    for x in 0..96 {
+        for z in 0..96 {
+            world_data.set(x, 1, z, block(Material::Grass)).unwrap();
+        }
+    }
+    for indx in 0..64 {
+        let (x, z) = (indx % 8, indx / 8);
+        let color = Color::new(
+            (64 + 24 * x) as u8,
+            (64 + 24 * z) as u8,
+            (255 - 16 * indx) as u8,
+            255,
+        );
+        world_data.lights.push(Light::Near(NearLight::new(
+            color,
+            1500,
+            Point3D::from_euc_coords_float(8.0 + 40.0 * x as f32, -6.0, 8.0 + 40.0 * z as f32),
+        )));
+    }
+    world_data
+}
+
+// A corridor the camera stands in, the walls cover most of the screen with large triangles
+fn close_up_walls() -> WorldData {
+    let mut world_data = WorldData::new(4);
+    for z in 0..64 {
+        for y in 0..12 {
+            world_data.set(7, y, z, block(Material::Dirt)).unwrap();
+            world_data.set(11, y, z, block(Material::Dirt)).unwrap();
+        }
+        for x in 8..11 {
+            world_data.set(x, 8, z, block(Material::Grass)).unwrap();
+        }
+    }
+    world_data.lights.push(Light::Near(NearLight::new(
+        Color::new(255, 220, 180, 255),
+        3000,
+        Point3D::from_euc_coords_float(38.0, 20.0, 60.0),
+    )));
+    world_data
+}
+
+struct Options {
+    scenes: Vec<String>,
+    frames: usize,
+    warmup: usize,
+    width: usize,
+    height: usize,
+    threads: usize,
+    occlusion_culling: bool,
+    textures: String,
+    save: Option<String>,
+    baseline: Option<String>,
+    threshold: f64,
+}
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut to_return = Self {
+            scenes: Vec::new(),
+            frames: 200,
+            warmup: 10,
+            width: 1265,
+            height: 632,
+            threads: cli::default_threads(),
+            occlusion_culling: true,
+            textures: "images.txt".to_string(),
+            save: None,
+            baseline: None,
+            threshold: 0.1,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("{} is missing a value", arg))?;
+            match arg.as_str() {
+                "--scene" => {
+                    if !SCENES.iter().any(|scene| scene.name == value) {
+                        return Err(format!("unknown scene {}", value));
+                    }
+                    to_return.scenes.push(value.clone());
+                }
+                "--frames" => to_return.frames = parse_number(arg, value)?,
+                "--warmup" => to_return.warmup = parse_number(arg, value)?,
+                "--size" => (to_return.width, to_return.height) = cli::parse_size(arg, value)?,
+                "--threads" => to_return.threads = parse_number(arg, value)?,
+                "--occlusion" => {
+                    to_return.occlusion_culling = match value.as_str() {
+                        "on" => true,
+                        "off" => false,
+                        _ => return Err(format!("{} has to be on or off", arg)),
+                    }
+                }
+                "--textures" => to_return.textures = value.clone(),
+                "--save" => to_return.save = Some(value.clone()),
+                "--baseline" => to_return.baseline = Some(value.clone()),
+                "--threshold" => to_return.threshold = parse_number(arg, value)?,
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+        cli::check_frames_and_threads(to_return.frames, to_return.threads)?;
+        Ok(to_return)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct SceneResult {
+    frames: usize,
+    mean_ms: f64,
+    p50_ms: f64,
+    p90_ms: f64,
+    p99_ms: f64,
+    max_ms: f64,
+    triangles_per_sec: f64,
+}
+impl SceneResult {
+    const FIELDS: [&'static str; 7] = [
+        "frames",
+        "mean_ms",
+        "p50_ms",
+        "p90_ms",
+        "p99_ms",
+        "max_ms",
+        "triangles_per_sec",
+    ];
+    fn new(mut frame_ms: Vec<f64>, triangles: u64) -> Self {
+        frame_ms.sort_by(|a, b| a.total_cmp(b));
+        let total_ms: f64 = frame_ms.iter().sum();
+        // Nearest rank
+        let percentile = |p: f64| {
+            let rank = (p * frame_ms.len() as f64).ceil() as usize;
+            frame_ms[rank.clamp(1, frame_ms.len()) - 1]
+        };
+        Self {
+            frames: frame_ms.len(),
+            mean_ms: total_ms / frame_ms.len() as f64,
+            p50_ms: percentile(0.5),
+            p90_ms: percentile(0.9),
+            p99_ms: percentile(0.99),
+            max_ms: frame_ms[frame_ms.len() - 1],
+            triangles_per_sec: triangles as f64 / (total_ms / 1000.0).max(f64::MIN_POSITIVE),
+        }
+    }
+    // Same order as FIELDS
+    fn values(&self) -> [f64; 7] {
+        [
+            self.frames as f64,
+            self.mean_ms,
+            self.p50_ms,
+            self.p90_ms,
+            self.p99_ms,
+            self.max_ms,
+            self.triangles_per_sec,
+        ]
+    }
+    fn from_values(values: &BTreeMap<String, f64>) -> Option<Self> {
+        let get = |field: &str| values.get(field).copied();
+        Some(Self {
+            frames: get("frames")? as usize,
+            mean_ms: get("mean_ms")?,
+            p50_ms: get("p50_ms")?,
+            p90_ms: get("p90_ms")?,
+            p99_ms: get("p99_ms")?,
+            max_ms: get("max_ms")?,
+            triangles_per_sec: get("triangles_per_sec")?,
+        })
+    }
+}
+
+fn run_scene(scene: &Scene, options: &Options, texture_array: &[u8]) -> SceneResult {
+    let mut game_state = match scene.world {
+        Some(build) => GameState::with_world(options.width, options.height, texture_array, build()),
+        None => GameState::new(options.width, options.height, texture_array),
+    }
+    .unwrap_or_else(|err| panic!("{}: {}", scene.name, err));
+    game_state.set_raster_mode(RasterMode::Tiled {
+        threads: options.threads,
+    });
+    game_state.set_occlusion_culling(options.occlusion_culling);
+    game_state.set_day_length(0);
+    let (x, y, z) = scene.position;
+    game_state.set_camera_position(x, y, z);
+    game_state.set_camera_look(scene.look.0, scene.look.1);
+
+    let mut frame_ms = Vec::with_capacity(options.frames);
+    let mut triangles = 0;
+    for frame in 0..(options.warmup + options.frames) {
+        game_state.rotate_camera(scene.turn_per_frame, 0.0);
+        game_state.render(frame);
+        if frame >= options.warmup {
+            let stats = game_state.frame_stats();
+            frame_ms.push(stats.timings.total);
+            triangles += stats.faces_emitted as u64;
+        }
+    }
+    SceneResult::new(frame_ms, triangles)
+}
+
+// Baselines are {"scene": {"field": number, ...}, ...}
+fn to_json(results: &[(&str, SceneResult)]) -> String {
+    let mut to_return = String::from("{\n");
+    for (indx, (name, result)) in results.iter().enumerate() {
+        write!(to_return, "  \"{}\": {{", name).unwrap();
+        for (field_indx, (field, value)) in
+            SceneResult::FIELDS.iter().zip(result.values()).enumerate()
+        {
+            let separator = if field_indx == 0 { "" } else { "," };
+            write!(to_return, "{} \"{}\": {}", separator, field, value).unwrap();
+        }
+        let separator = if indx + 1 == results.len() { "" } else { "," };
+        writeln!(to_return, " }}{}", separator).unwrap();
+    }
+    to_return.push_str("}\n");
+    to_return
+}
+
+// Reads back what to_json writes: objects of objects of numbers, any whitespace
+fn from_json(input: &str) -> Option<BTreeMap<String, SceneResult>> {
+    let mut reader = JsonReader {
+        input: input.as_bytes(),
+        indx: 0,
+    };
+    let mut to_return = BTreeMap::new();
+    reader.expect(b'{')?;
+    while !reader.next_is(b'}') {
+        let name = reader.string()?;
+        reader.expect(b':')?;
+        reader.expect(b'{')?;
+        let mut values = BTreeMap::new();
+        while !reader.next_is(b'}') {
+            let field = reader.string()?;
+            reader.expect(b':')?;
+            values.insert(field, reader.number()?);
+            reader.next_is(b',');
+        }
+        to_return.insert(name, SceneResult::from_values(&values)?);
+        reader.next_is(b',');
+    }
+    Some(to_return)
+}
+struct JsonReader<'a> {
+    input: &'a [u8],
+    indx: usize,
+}
+impl<'a> JsonReader<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.indx < self.input.len() && self.input[self.indx].is_ascii_whitespace() {
+            self.indx += 1;
+        }
+    }
+    // Consumes the byte if it's next
+    fn next_is(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.input.get(self.indx) == Some(&byte);
+        if found {
+            self.indx += 1;
+        }
+        found
+    }
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        self.next_is(byte).then_some(())
+    }
+    // No escapes, scene and field names don't need them
+    fn string(&mut self) -> Option<String> {
+        self.expect(b'"')?;
+        let start = self.indx;
+        while *self.input.get(self.indx)? != b'"' {
+            self.indx += 1;
+        }
+        self.indx += 1;
+        String::from_utf8(self.input[start..self.indx - 1].to_vec()).ok()
+    }
+    fn number(&mut self) -> Option<f64> {
+        self.skip_whitespace();
+        let start = self.indx;
+        while self
+            .input
+            .get(self.indx)
+            .is_some_and(|byte| b"+-.eE0123456789".contains(byte))
+        {
+            self.indx += 1;
+        }
+        std::str::from_utf8(&self.input[start..self.indx])
+            .ok()?
+            .parse()
+            .ok()
+    }
+}
+
+// Scenes whose median frame time went up by more than threshold, with the ratio
+fn regressions(
+    results: &[(&str, SceneResult)],
+    baseline: &BTreeMap<String, SceneResult>,
+    threshold: f64,
+) -> Vec<(String, f64)> {
+    results
+        .iter()
+        .filter_map(|(name, result)| {
+            let ratio = result.p50_ms / baseline.get(*name)?.p50_ms;
+            (ratio > 1.0 + threshold).then(|| (name.to_string(), ratio))
+        })
+        .collect()
+}
+
+fn run(options: &Options) -> Result<bool, String> {
+    let texture_array = cli::load_textures(&options.textures)?;
+    let baseline = match &options.baseline {
+        Some(path) => {
+            Some(from_json(&read(path)?).ok_or(format!("{}: not a benchmark baseline", path))?)
+        }
+        None => None,
+    };
+
+    println!(
+        "{:<16}{:>8}{:>10}{:>10}{:>10}{:>10}{:>10}{:>14}{:>10}",
+        "scene", "frames", "mean ms", "p50 ms", "p90 ms", "p99 ms", "max ms", "Mtris/s", "vs base"
+    );
+    let mut results = Vec::new();
+    for scene in SCENES.iter().filter(|scene| {
+        options.scenes.is_empty() || options.scenes.iter().any(|name| name == scene.name)
+    }) {
+        let result = run_scene(scene, options, &texture_array);
+        let versus = baseline
+            .as_ref()
+            .and_then(|baseline| baseline.get(scene.name))
+            .map_or("-".to_string(), |base| {
+                format!("{:+.1}%", 100.0 * (result.p50_ms / base.p50_ms - 1.0))
+            });
+        println!(
+            "{:<16}{:>8}{:>10.2}{:>10.2}{:>10.2}{:>10.2}{:>10.2}{:>14.2}{:>10}",
+            scene.name,
+            result.frames,
+            result.mean_ms,
+            result.p50_ms,
+            result.p90_ms,
+            result.p99_ms,
+            result.max_ms,
+            result.triangles_per_sec / 1_000_000.0,
+            versus
+        );
+        results.push((scene.name, result));
+    }
+
+    if let Some(path) = &options.save {
+        fs::write(path, to_json(&results)).map_err(|err| format!("{}: {}", path, err))?;
+        println!("saved baseline to {}", path);
+    }
+    let baseline = match baseline {
+        Some(baseline) => baseline,
+        None => return Ok(true),
+    };
+    let regressed = regressions(&results, &baseline, options.threshold);
+    for (name, ratio) in regressed.iter() {
+        println!(
+            "REGRESSION {}: median frame time is {:.1}% over the baseline",
+            name,
+            100.0 * (ratio - 1.0)
+        );
+    }
+    Ok(regressed.is_empty())
+}
+
+pub fn main() {
+    let options = cli::parse_args(USAGE, false, Options::parse);
+    match run(&options) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(err) => cli::fail("m3d_bench", &err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_baselines_and_regressions() {
+        let result = SceneResult::new((1..=100).rev().map(|ms| ms as f64).collect(), 5050);
+        assert_eq!(
+            (result.p50_ms, result.p90_ms, result.p99_ms),
+            (50.0, 90.0, 99.0)
+        );
+        assert_eq!(result.max_ms, 100.0);
+        assert_eq!(result.mean_ms, 50.5);
+        assert_eq!(result.triangles_per_sec, 1000.0);
+
+        let results = [("flat_floor", result), ("many_lights", result)];
+        let baseline = from_json(&to_json(&results)).unwrap();
+        assert_eq!(baseline.get("flat_floor"), Some(&result));
+        assert!(from_json("{ \"flat_floor\": { \"frames\": 1 } }").is_none());
+
+        let slower = SceneResult {
+            p50_ms: 56.0,
+            ..result
+        };
+        let regressed = regressions(&[("flat_floor", slower)], &baseline, 0.1);
+        assert_eq!(regressed.len(), 1);
+        assert!(regressions(&[("flat_floor", slower)], &baseline, 0.15).is_empty());
+        assert!(regressions(&[("dense_terrain", slower)], &baseline, 0.1).is_empty());
+    }
+    #[test]
+    fn scenes_render() {
+        let texture_array = cli::load_textures("images.txt").unwrap();
+        let options = Options::parse(&[
+            "--frames".to_string(),
+            "2".to_string(),
+            "--warmup".to_string(),
+            "0".to_string(),
+            "--size".to_string(),
+            "224x112".to_string(),
+            "--occlusion".to_string(),
+            "off".to_string(),
+        ])
+        .unwrap();
+        for scene in SCENES.iter() {
+            let result = run_scene(scene, &options, &texture_array);
+            assert_eq!(result.frames, 2);
+            assert!(
+                result.triangles_per_sec > 0.0,
+                "{} drew nothing",
+                scene.name
+            );
+        }
+    }
+}
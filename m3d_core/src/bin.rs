@@ -1,115 +1,109 @@
+use m3d_core::camerapath::CameraPath;
+use m3d_core::rendering::raster::RasterMode;
+use m3d_core::rendering::stats::FrameStats;
+use m3d_core::rendering::texturepack;
 use m3d_core::GameState;
-use std::fs;
+use std::{cmp, env, fs, process, thread};
 
-struct Base16Reader<'a> {
-    to_read: &'a [u8],
-    index: usize,
+pub fn load_textures() -> Vec<u8> {
+    let texture_string = fs::read_to_string("images.txt").expect("Cannot open images.txt");
+    texturepack::decode(&texture_string).unwrap_or_else(|err| panic!("{}", err))
 }
-impl<'a> Base16Reader<'a> {
-    pub fn new(input: &'a str) -> Self {
-        Self {
-            to_read: input.as_bytes(),
-            index: 0,
+
+pub fn render_threads() -> usize {
+    thread::available_parallelism().map_or(1, |threads| threads.get())
+}
+
+pub fn test_manager(n: usize, camera_path: Option<CameraPath>) {
+    let texture_array = load_textures();
+    let mut gs_manager = GameState::new(1265, 632, &texture_array).unwrap();
+    gs_manager.set_raster_mode(RasterMode::Tiled {
+        threads: render_threads(),
+    });
+    gs_manager.translate_camera(290, -50, 35);
+    gs_manager.rotate_camera(0.0, -1.0);
+    let mut totals = StatTotals::new();
+    //let mut gs_manager = GameState::new(1266, 633);
+    match camera_path {
+        Some(path) => {
+            // Spread the frames evenly over the path so runs are comparable regardless of
+            // how long the recording was
+            let duration = path.duration();
+            let steps = cmp::max(1, n - 1);
+            gs_manager.play_camera_path(path, 0, false);
+            for j in 0..n {
+                gs_manager.render(j * duration / steps);
+                totals.add(gs_manager.frame_stats());
+            }
         }
-    }
-    fn lookup_char(input: u8) -> Option<u8> {
-        let base16_chars = [
-            '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'A', 'B', 'C', 'D', 'E', 'F',
-        ];
-        for (i, val) in base16_chars.iter().enumerate() {
-            if *val == input as char {
-                return Some(i as u8);
+        None => {
+            for j in 0..n {
+                gs_manager.render(j);
+                totals.add(gs_manager.frame_stats());
             }
         }
-        None
     }
-    pub fn read_u8(&mut self) -> Option<u8> {
-        if self.index + 2 <= self.to_read.len() {
-            let to_return = 16
-                * Self::lookup_char(self.to_read[self.index]).expect("Cannot lookup base16 digit")
-                + Self::lookup_char(self.to_read[self.index + 1])
-                    .expect("Cannot lookup base16 digit");
-            self.index += 2;
-            Some(to_return)
-        } else {
-            None
+    totals.print();
+}
+
+// Running sums of FrameStats, printed as per frame averages
+struct StatTotals {
+    frames: usize,
+    sums: [f64; FrameStats::NAMES.len()],
+}
+impl StatTotals {
+    fn new() -> Self {
+        Self {
+            frames: 0,
+            sums: [0.0; FrameStats::NAMES.len()],
         }
     }
-    pub fn read_u16(&mut self) -> Option<u16> {
-        let first = self.read_u8()? as u16;
-        let second = self.read_u8()? as u16;
-        Some(first * 256 + second)
+    fn add(&mut self, stats: &FrameStats) {
+        self.frames += 1;
+        for (sum, value) in self.sums.iter_mut().zip(stats.values()) {
+            *sum += value;
+        }
     }
-    pub fn has_next(&self) -> bool {
-        self.index + 1 < self.to_read.len()
+    fn print(&self) {
+        println!("average over {} frames:", self.frames);
+        for (name, sum) in FrameStats::NAMES.iter().zip(self.sums.iter()) {
+            println!(
+                "  {:<28}{:>14.3}",
+                name,
+                sum / cmp::max(1, self.frames) as f64
+            );
+        }
     }
 }
 
-pub fn test_manager(n: usize) {
-    let texture_string = fs::read_to_string("images.txt").expect("Cannot open images.txt");
-    let mut texture_array: Vec<u8> = Vec::new();
-
-    for texture_line in texture_string.split("\n").collect::<Vec<&str>>() {
-        let mut texture_reader = Base16Reader::new(texture_line);
-        let colors = {
-            let num_colors = texture_reader
-                .read_u16()
-                .expect("Could not read number of colors");
-            let mut tentative_colors: Vec<(u8, u8, u8, u8)> = Vec::new();
-
-            for _ in 0..num_colors {
-                let r = texture_reader.read_u8().unwrap();
-                let g = texture_reader.read_u8().unwrap();
-                let b = texture_reader.read_u8().unwrap();
-                let a = texture_reader.read_u8().unwrap();
-                tentative_colors.push((r, g, b, a));
-            }
-            tentative_colors
-        };
-
-        while texture_reader.has_next() {
-            let run_length = {
-                let first = texture_reader.read_u8().unwrap();
-                if first == 255 {
-                    texture_reader
-                        .read_u16()
-                        .expect("Could not read run length")
-                } else {
-                    first as u16
-                }
-            };
-            let run_color = texture_reader.read_u8().expect("Could not read run color") as usize;
-
-            for _ in 0..run_length {
-                let selected_color = colors[run_color];
-                texture_array.push(selected_color.0);
-                texture_array.push(selected_color.1);
-                texture_array.push(selected_color.2);
-                texture_array.push(selected_color.3);
-            }
+// Exits instead of panicking on a bad file, it's the user's mistake rather than a bug
+fn load_camera_path() -> Option<CameraPath> {
+    let path_file = env::args().nth(1)?;
+    let loaded = fs::read_to_string(&path_file)
+        .map_err(|err| err.to_string())
+        .and_then(|contents| {
+            CameraPath::import(&contents).ok_or_else(|| "not a camera path".to_string())
+        });
+    match loaded {
+        Ok(camera_path) => Some(camera_path),
+        Err(err) => {
+            eprintln!("m3d_test: {}: {}", path_file, err);
+            process::exit(1);
         }
     }
-
-    let mut gs_manager = GameState::new(1265, 632, &texture_array);
-    gs_manager.translate_camera(290, -50, 35);
-    gs_manager.rotate_camera(0.0, -1.0);
-    //let mut gs_manager = GameState::new(1266, 633);
-    for j in 0..n {
-        gs_manager.render(j);
-    }
 }
 
 pub fn main() {
     #[cfg(feature = "callgrind")]
     {
         println!("callgrind starting...");
-        test_manager(5); // 500
+        test_manager(5, load_camera_path()); // 500
         println!("callgrind finished.");
     }
     #[cfg(not(feature = "callgrind"))]
     {
         println!("moo3d_core test starting...");
-        test_manager(500); // 500
+        test_manager(500, load_camera_path()); // 500
         println!("moo3d_core test finished.");
     }
 }
@@ -124,6 +118,6 @@ mod tests {
     }
     #[test]
     fn test_main() {
-        test_manager(1);
+        test_manager(1, None);
     }
 }
@@ -0,0 +1,49 @@
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    // Width or height of 0
+    InvalidSize { width: usize, height: usize },
+    // Texture bytes that aren't a whole number of RGBA textures
+    TextureLength { length: usize },
+    // A material uses a texture id past the end of the ones loaded
+    MissingTexture { id: u16, loaded: usize },
+    // Block coordinates outside the world's bundles
+    OutOfBounds { x: usize, y: usize, z: usize },
+    // Malformed texture pack, line counts from 1
+    TextureDecode { line: usize, reason: &'static str },
+    // Malformed world file, line counts from 1
+    WorldParse { line: usize, reason: &'static str },
+    // Malformed OBJ mesh, line counts from 1
+    MeshParse { line: usize, reason: &'static str },
+}
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidSize { width, height } => {
+                write!(f, "invalid frame size {}x{}", width, height)
+            }
+            Self::TextureLength { length } => write!(
+                f,
+                "texture data is {} bytes, not a whole number of textures",
+                length
+            ),
+            Self::MissingTexture { id, loaded } => write!(
+                f,
+                "texture {} is used but only {} textures are loaded",
+                id, loaded
+            ),
+            Self::OutOfBounds { x, y, z } => {
+                write!(f, "block ({}, {}, {}) is outside the world", x, y, z)
+            }
+            Self::TextureDecode { line, reason } => {
+                write!(f, "texture pack line {}: {}", line, reason)
+            }
+            Self::WorldParse { line, reason } => write!(f, "world file line {}: {}", line, reason),
+            Self::MeshParse { line, reason } => write!(f, "OBJ line {}: {}", line, reason),
+        }
+    }
+}
+impl std::error::Error for Error {}
@@ -1,9 +1,16 @@
-use crate::rendermath::{Matrix, Point3D, RenderMatrices};
+use crate::rendermath::{Mat4, Point3D, Quaternion, RenderMatrices, Vec3};
 
 pub const BLOCKS_PER_WIDTH: usize = 7;
 pub const UNITS_PER_BLOCK: usize = 4;
 const UNITS_PER_WIDTH: usize = BLOCKS_PER_WIDTH * UNITS_PER_BLOCK;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective,
+    // extent is the number of world units visible across the screen horizontally
+    Orthographic { extent: f32 },
+}
+
 pub struct Camera {
     pub data: CameraData,
     pub cache: CameraCache,
@@ -11,18 +18,19 @@ pub struct Camera {
 // ugly as hell but whatever
 pub struct CameraData {
     pub position: Point3D,
-    pub target: (f32, f32), // rotation, inclination
+    pub orientation: Quaternion, // camera space -> world space
     pub near: f32,
     pub far: f32,
     pub scale: usize,
     pub center_x: f32,
     pub center_y: f32,
+    pub projection: Projection,
 }
 pub struct CameraCache {
-    pub scale: Option<Matrix>,
-    pub reverse_frustum: Option<Matrix>,
-    pub center: Option<Matrix>,
-    pub to_screen_space: Option<Matrix>,
+    pub scale: Option<Mat4>,
+    pub reverse_frustum: Option<Mat4>,
+    pub center: Option<Mat4>,
+    pub to_screen_space: Option<Mat4>,
 }
 
 impl Camera {
@@ -40,16 +48,32 @@ impl Camera {
         Self {
             data: CameraData {
                 position,
-                target,
+                orientation: Quaternion::from_yaw_pitch(target.0, target.1),
                 near,
                 far: (render_distance * UNITS_PER_BLOCK) as f32 + near,
                 scale,
                 center_x: width as f32 / 2.0,
                 center_y: height as f32 / 2.0,
+                projection: Projection::Perspective,
             },
             cache: CameraCache::new(),
         }
     }
+    // Keeps the field of view, only the mapping onto the screen changes. The frame is
+    // render_scale times the output size on each axis, and the scale goes up by exactly that
+    // much so a supersampled frame lines up with one drawn at the output size
+    pub fn set_resolution(
+        &mut self,
+        output_width: usize,
+        output_height: usize,
+        render_scale: usize,
+    ) {
+        self.data.scale = output_width / UNITS_PER_WIDTH * render_scale;
+        self.data.center_x = (output_width * render_scale) as f32 / 2.0;
+        self.data.center_y = (output_height * render_scale) as f32 / 2.0;
+
+        self.cache.invalidate();
+    }
     pub fn translate(&mut self, dx: isize, dy: isize, dz: isize) {
         let data = &mut self.data;
         data.position.set(0, data.position.get(0) + dx as f32);
@@ -58,12 +82,53 @@ impl Camera {
 
         self.cache.invalidate();
     }
+    pub fn set_position(&mut self, x: f32, y: f32, z: f32) {
+        let data = &mut self.data;
+        data.position.set(0, x);
+        data.position.set(1, y);
+        data.position.set(2, z);
+
+        self.cache.invalidate();
+    }
+    pub fn set_look(&mut self, rotation: f32, inclination: f32) {
+        self.set_orientation(Quaternion::from_yaw_pitch(rotation, inclination));
+    }
+    pub fn set_orientation(&mut self, orientation: Quaternion) {
+        self.data.orientation = orientation.normalize();
+
+        self.cache.invalidate();
+    }
+    // Yaw is around the world's vertical axis, pitch around the camera's own x axis,
+    // so this behaves the same as adding to the (rotation, inclination) pair
     pub fn translate_look(&mut self, d_rotation: f32, d_inclination: f32) {
-        self.data.target.0 += d_rotation;
-        self.data.target.1 += d_inclination;
+        let yaw = Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), d_rotation);
+        let pitch = Quaternion::from_axis_angle(Vec3::new(1.0, 0.0, 0.0), d_inclination);
+        self.set_orientation(yaw.mul(&self.data.orientation).mul(&pitch));
+    }
+    pub fn roll(&mut self, d_roll: f32) {
+        let roll = Quaternion::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), d_roll);
+        self.set_orientation(self.data.orientation.mul(&roll));
+    }
+    // (rotation, inclination)
+    pub fn look(&self) -> (f32, f32) {
+        self.data.orientation.to_yaw_pitch()
+    }
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.data.projection = projection;
 
         self.cache.invalidate();
     }
+    // Orthographic, looking down the diagonal of a block
+    pub fn set_isometric(&mut self, extent: f32) {
+        self.set_look(
+            std::f32::consts::FRAC_PI_4,
+            -(1.0 / std::f32::consts::SQRT_2).atan(),
+        );
+        self.set_projection(Projection::Orthographic { extent });
+    }
+    pub fn projection(&self) -> Projection {
+        self.data.projection
+    }
     pub fn scale(&self) -> usize {
         self.data.scale
     }
@@ -79,6 +144,22 @@ impl Camera {
     pub fn screen_far(&self) -> f32 {
         self.data.scale as f32 * self.data.far
     }
+    // World position of a point on screen, depth uses the z buffer's mapping (0 at the near
+    // plane, 1 at the far plane). None if the camera's matrices can't be inverted
+    pub fn unproject(&mut self, screen_x: f32, screen_y: f32, depth: f32) -> Option<Point3D> {
+        let view_z = self.data.near + depth * (self.data.far - self.data.near);
+        let to_screen_space =
+            *CameraCache::to_screen_space(&mut self.cache.to_screen_space, &self.data);
+        let reverse_frustum =
+            *CameraCache::reverse_frustum(&mut self.cache.reverse_frustum, &self.data);
+
+        // Perspective squashes depth, so work out where view_z ends up first
+        let screen_z = Point3D::from_euc_coords_float(0.0, 0.0, view_z)
+            .transform(&to_screen_space)
+            .get(2);
+        let inverse = (to_screen_space * reverse_frustum).inverse()?;
+        Some(Point3D::from_euc_coords_float(screen_x, screen_y, screen_z).transform(&inverse))
+    }
     pub fn reverse_frustum(point: &Point3D, cache: &mut CameraCache, data: &CameraData) -> Point3D {
         point.transform(CameraCache::reverse_frustum(
             &mut cache.reverse_frustum,
@@ -88,9 +169,22 @@ impl Camera {
     pub fn in_frustum(point: &Point3D, cache: &mut CameraCache, data: &CameraData) -> bool {
         let transformed = Self::reverse_frustum(point, cache, data);
 
-        let z_factor = (transformed.get(2) / data.near).abs();
-        let x_cutoff = (z_factor * data.center_x / (data.scale as f32)).ceil();
-        let y_cutoff = (z_factor * data.center_y / (data.scale as f32)).ceil();
+        let (x_cutoff, y_cutoff) = match data.projection {
+            Projection::Perspective => {
+                let z_factor = (transformed.get(2) / data.near).abs();
+                (
+                    (z_factor * data.center_x / (data.scale as f32)).ceil(),
+                    (z_factor * data.center_y / (data.scale as f32)).ceil(),
+                )
+            }
+            Projection::Orthographic { extent } => {
+                let half_width = extent / 2.0;
+                (
+                    half_width.ceil(),
+                    (half_width * data.center_y / data.center_x).ceil(),
+                )
+            }
+        };
 
         if transformed.get(0).abs() > x_cutoff {
             false
@@ -102,6 +196,75 @@ impl Camera {
             true
         }
     }
+    // Whether all the corners of a box are past the same side of the frustum, in which case
+    // none of it can be seen. A box bigger than the view can have every corner outside it
+    // and still cross it, so testing corners one at a time isn't enough
+    pub fn box_outside_frustum(
+        corners: &[Point3D; 8],
+        cache: &mut CameraCache,
+        data: &CameraData,
+    ) -> bool {
+        let reverse_frustum = *CameraCache::reverse_frustum(&mut cache.reverse_frustum, data);
+        let views = corners.map(|corner| corner.transform(&reverse_frustum));
+
+        // The sides widen with depth for a perspective camera and are fixed for an
+        // orthographic one
+        let (x_slope, y_slope, x_extent, y_extent) = match data.projection {
+            Projection::Perspective => (
+                data.center_x / (data.scale as f32 * data.near),
+                data.center_y / (data.scale as f32 * data.near),
+                0.0,
+                0.0,
+            ),
+            Projection::Orthographic { extent } => (
+                0.0,
+                0.0,
+                extent / 2.0,
+                extent / 2.0 * data.center_y / data.center_x,
+            ),
+        };
+        // How far past each side a point in view space is, positive is outside
+        let sides: [&dyn Fn(&Point3D) -> f32; 6] = [
+            &|view| data.near - view.get(2),
+            &|view| view.get(2) - data.far,
+            &|view| view.get(0) - x_extent - x_slope * view.get(2),
+            &|view| -view.get(0) - x_extent - x_slope * view.get(2),
+            &|view| view.get(1) - y_extent - y_slope * view.get(2),
+            &|view| -view.get(1) - y_extent - y_slope * view.get(2),
+        ];
+        sides
+            .iter()
+            .any(|past| views.iter().all(|view| past(view) > 0.0))
+    }
+}
+impl Camera {
+    // Screen space bounding rect of a set of points as (min_x, min_y, max_x, max_y, min_z),
+    // with min_z mapped the same way as the renderer's z buffer. None if any point is in
+    // front of the near plane, since then the points can't be bounded on screen
+    pub fn screen_bounds(
+        points: &[Point3D],
+        cache: &mut CameraCache,
+        data: &CameraData,
+    ) -> Option<(f32, f32, f32, f32, f32)> {
+        let reverse_frustum = *CameraCache::reverse_frustum(&mut cache.reverse_frustum, data);
+        let to_screen_space = CameraCache::to_screen_space(&mut cache.to_screen_space, data);
+
+        let mut bounds = (f32::MAX, f32::MAX, f32::MIN, f32::MIN, f32::MAX);
+        for point in points {
+            let view = point.transform(&reverse_frustum);
+            if view.z_coord_float() < data.near {
+                return None;
+            }
+            let projected = view.transform(to_screen_space);
+            bounds.0 = bounds.0.min(projected.get(0));
+            bounds.1 = bounds.1.min(projected.get(1));
+            bounds.2 = bounds.2.max(projected.get(0));
+            bounds.3 = bounds.3.max(projected.get(1));
+            bounds.4 = bounds.4.min(view.z_coord_float());
+        }
+        bounds.4 = (bounds.4 - data.near) / (data.far - data.near);
+        Some(bounds)
+    }
 }
 impl CameraCache {
     pub fn new() -> Self {
@@ -119,26 +282,29 @@ impl CameraCache {
         self.reverse_frustum = None;
     }
     pub fn to_screen_space<'a>(
-        to_screen_space: &'a mut Option<Matrix>,
+        to_screen_space: &'a mut Option<Mat4>,
         camera_data: &CameraData,
-    ) -> &'a Matrix {
+    ) -> &'a Mat4 {
         if to_screen_space.is_none() {
+            let (scale, projection) = match camera_data.projection {
+                Projection::Perspective => (
+                    camera_data.scale as f32,
+                    RenderMatrices::projection(camera_data.near, camera_data.far),
+                ),
+                Projection::Orthographic { extent } => (
+                    2.0 * camera_data.center_x / extent,
+                    RenderMatrices::orthographic(camera_data.near, camera_data.far),
+                ),
+            };
             *to_screen_space = Some(
                 RenderMatrices::translation(camera_data.center_x, camera_data.center_y, 0.0)
-                    .matrix_mul(&RenderMatrices::scale(
-                        camera_data.scale as f32,
-                        camera_data.scale as f32,
-                        1.0,
-                    ))
-                    .matrix_mul(&RenderMatrices::projection(
-                        camera_data.near,
-                        camera_data.far,
-                    )),
+                    * RenderMatrices::scale(scale, scale, 1.0)
+                    * projection,
             );
         }
         to_screen_space.as_ref().unwrap()
     }
-    pub fn scale<'a>(scale: &'a mut Option<Matrix>, camera_data: &CameraData) -> &'a Matrix {
+    pub fn scale<'a>(scale: &'a mut Option<Mat4>, camera_data: &CameraData) -> &'a Mat4 {
         if scale.is_none() {
             *scale = Some(RenderMatrices::scale(
                 camera_data.scale as f32,
@@ -149,20 +315,120 @@ impl CameraCache {
         scale.as_ref().unwrap()
     }
     pub fn reverse_frustum<'a>(
-        reverse_frustum: &'a mut Option<Matrix>,
+        reverse_frustum: &'a mut Option<Mat4>,
         camera_data: &CameraData,
-    ) -> &'a Matrix {
+    ) -> &'a Mat4 {
         if reverse_frustum.is_none() {
             *reverse_frustum = Some(
-                RenderMatrices::rotation_x(camera_data.target.1)
-                    .matrix_mul(&RenderMatrices::rotation_y(camera_data.target.0))
-                    .matrix_mul(&RenderMatrices::translation(
-                        -camera_data.position.position.get(0),
-                        -camera_data.position.position.get(1),
-                        -camera_data.position.position.get(2),
-                    )),
+                camera_data.orientation.conjugate().to_matrix()
+                    * RenderMatrices::translation(
+                        -camera_data.position.get(0),
+                        -camera_data.position.get(1),
+                        -camera_data.position.get(2),
+                    ),
             );
         }
         reverse_frustum.as_ref().unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supersampling_scales_by_the_whole_factor() {
+        let mut camera = Camera::new(
+            Point3D::from_euc_coords(0, 0, 0),
+            (0.0, 0.0),
+            std::f32::consts::FRAC_PI_2,
+            100,
+            300,
+            150,
+        );
+        assert_eq!(camera.data.scale, 10);
+        // 600 / UNITS_PER_WIDTH alone would give 21
+        camera.set_resolution(300, 150, 2);
+        assert_eq!(camera.data.scale, 20);
+        assert_eq!((camera.data.center_x, camera.data.center_y), (300.0, 150.0));
+        camera.set_resolution(300, 150, 1);
+        assert_eq!(camera.data.scale, 10);
+    }
+
+    #[test]
+    fn boxes_are_only_culled_past_a_single_side() {
+        let mut camera = Camera::new(
+            Point3D::from_euc_coords(0, 0, 0),
+            (0.0, 0.0),
+            std::f32::consts::FRAC_PI_2,
+            100,
+            300,
+            150,
+        );
+        let mut outside = |min: (f32, f32, f32), max: (f32, f32, f32)| {
+            let mut corners = [Point3D::from_euc_coords(0, 0, 0); 8];
+            for (indx, corner) in corners.iter_mut().enumerate() {
+                let pick = |bit: usize, min: f32, max: f32| if indx & bit == 0 { min } else { max };
+                *corner = Point3D::from_euc_coords_float(
+                    pick(1, min.0, max.0),
+                    pick(2, min.1, max.1),
+                    pick(4, min.2, max.2),
+                );
+            }
+            Camera::box_outside_frustum(&corners, &mut camera.cache, &camera.data)
+        };
+
+        assert!(!outside((-5.0, -5.0, 50.0), (5.0, 5.0, 60.0)));
+        // Behind the camera, past the far plane and off to one side
+        assert!(outside((-5.0, -5.0, -60.0), (5.0, 5.0, -50.0)));
+        assert!(outside((-5.0, -5.0, 500.0), (5.0, 5.0, 510.0)));
+        assert!(outside((200.0, -5.0, 50.0), (210.0, 5.0, 60.0)));
+        // A wall across the whole view and a box around the camera have no corners inside
+        // the frustum but still cover it
+        assert!(!outside((-500.0, -500.0, 50.0), (500.0, 500.0, 60.0)));
+        assert!(!outside(
+            (-1000.0, -1000.0, -1000.0),
+            (1000.0, 1000.0, 1000.0)
+        ));
+    }
+
+    #[test]
+    fn unproject_inverts_projection() {
+        let mut camera = Camera::new(
+            Point3D::from_euc_coords(30, -12, 5),
+            (0.7, -0.4),
+            std::f32::consts::PI * (135.0 / 180.0),
+            100,
+            640,
+            320,
+        );
+        camera.roll(0.2);
+        for projection in [
+            Projection::Perspective,
+            Projection::Orthographic { extent: 60.0 },
+        ] {
+            camera.set_projection(projection);
+            for &(x, y, z) in &[(40.0, -8.0, 30.0), (12.0, 3.5, 40.0), (70.0, -30.0, 90.0)] {
+                let point = Point3D::from_euc_coords_float(x, y, z);
+                let view = Camera::reverse_frustum(&point, &mut camera.cache, &camera.data);
+                let depth = (view.z_coord_float() - camera.near()) / (camera.far() - camera.near());
+                let screen = view.transform(CameraCache::to_screen_space(
+                    &mut camera.cache.to_screen_space,
+                    &camera.data,
+                ));
+
+                let back = camera
+                    .unproject(screen.get(0), screen.get(1), depth)
+                    .unwrap();
+                for indx in 0..3 {
+                    assert!(
+                        (back.get(indx) - point.get(indx)).abs() < 0.01,
+                        "{:?} came back as {}",
+                        projection,
+                        back
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use crate::camera::Camera;
+use std::f32::consts::PI;
+
+const EXPORT_HEADER: &str = "m3d_camera_path 1";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    pub time: usize,
+    pub position: (f32, f32, f32),
+    pub target: (f32, f32), // rotation, inclination
+}
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+}
+pub struct CameraPathPlayback {
+    pub path: CameraPath,
+    pub start_time: usize,
+    pub looping: bool,
+}
+
+impl Keyframe {
+    pub fn new(time: usize, position: (f32, f32, f32), target: (f32, f32)) -> Self {
+        Self {
+            time,
+            position,
+            target,
+        }
+    }
+    pub fn from_camera(time: usize, camera: &Camera) -> Self {
+        let position = &camera.data.position;
+        Self {
+            time,
+            position: (position.get(0), position.get(1), position.get(2)),
+            target: camera.look(),
+        }
+    }
+}
+
+impl CameraPath {
+    pub fn new() -> Self {
+        Self {
+            keyframes: Vec::new(),
+        }
+    }
+    pub fn with_keyframes(mut keyframes: Vec<Keyframe>) -> Self {
+        keyframes.sort_by_key(|keyframe| keyframe.time);
+        Self { keyframes }
+    }
+    // Keyframes are kept sorted by time, a keyframe with the same time as an existing
+    // one replaces it
+    pub fn add_keyframe(&mut self, keyframe: Keyframe) {
+        match self
+            .keyframes
+            .binary_search_by_key(&keyframe.time, |existing| existing.time)
+        {
+            Ok(indx) => self.keyframes[indx] = keyframe,
+            Err(indx) => self.keyframes.insert(indx, keyframe),
+        }
+    }
+    pub fn record(&mut self, time: usize, camera: &Camera) {
+        self.add_keyframe(Keyframe::from_camera(time, camera));
+    }
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+    pub fn start_time(&self) -> usize {
+        self.keyframes.first().map_or(0, |keyframe| keyframe.time)
+    }
+    pub fn end_time(&self) -> usize {
+        self.keyframes.last().map_or(0, |keyframe| keyframe.time)
+    }
+    pub fn duration(&self) -> usize {
+        self.end_time() - self.start_time()
+    }
+    // Where the camera is at the given time, clamped to the ends of the path
+    pub fn sample(&self, time: usize) -> Option<Keyframe> {
+        let last = self.keyframes.len().checked_sub(1)?;
+        if time <= self.keyframes[0].time {
+            let first = &self.keyframes[0];
+            return Some(Keyframe::new(time, first.position, first.target));
+        }
+        if time >= self.keyframes[last].time {
+            let end = &self.keyframes[last];
+            return Some(Keyframe::new(time, end.position, end.target));
+        }
+
+        // Index of the keyframe starting the segment containing time
+        let segment = match self
+            .keyframes
+            .binary_search_by_key(&time, |keyframe| keyframe.time)
+        {
+            Ok(indx) => return Some(self.keyframes[indx]),
+            Err(indx) => indx - 1,
+        };
+
+        let k0 = &self.keyframes[segment.saturating_sub(1)];
+        let k1 = &self.keyframes[segment];
+        let k2 = &self.keyframes[segment + 1];
+        let k3 = &self.keyframes[std::cmp::min(segment + 2, last)];
+
+        let t = (time - k1.time) as f32 / (k2.time - k1.time) as f32;
+
+        let position = (
+            Self::catmull_rom(
+                k0.position.0,
+                k1.position.0,
+                k2.position.0,
+                k3.position.0,
+                t,
+            ),
+            Self::catmull_rom(
+                k0.position.1,
+                k1.position.1,
+                k2.position.1,
+                k3.position.1,
+                t,
+            ),
+            Self::catmull_rom(
+                k0.position.2,
+                k1.position.2,
+                k2.position.2,
+                k3.position.2,
+                t,
+            ),
+        );
+        let target = (
+            Self::interp_angle(k1.target.0, k2.target.0, t),
+            Self::interp_angle(k1.target.1, k2.target.1, t),
+        );
+        Some(Keyframe::new(time, position, target))
+    }
+    fn catmull_rom(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+        let t2 = t * t;
+        let t3 = t2 * t;
+        0.5 * (2.0 * p1
+            + (p2 - p0) * t
+            + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+            + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+    }
+    // Goes the short way around the circle, so 350deg -> 10deg passes through 0deg
+    fn interp_angle(from: f32, to: f32, t: f32) -> f32 {
+        let mut delta = (to - from) % (2.0 * PI);
+        if delta > PI {
+            delta -= 2.0 * PI;
+        } else if delta < -PI {
+            delta += 2.0 * PI;
+        }
+        from + delta * t
+    }
+
+    // One keyframe per line: time x y z rotation inclination
+    // f32's Display gives the shortest string that round-trips, so export -> import is exact
+    pub fn export(&self) -> String {
+        let mut to_return = String::from(EXPORT_HEADER);
+        for keyframe in self.keyframes.iter() {
+            to_return += &format!(
+                "\n{} {} {} {} {} {}",
+                keyframe.time,
+                keyframe.position.0,
+                keyframe.position.1,
+                keyframe.position.2,
+                keyframe.target.0,
+                keyframe.target.1
+            );
+        }
+        to_return
+    }
+    pub fn import(input: &str) -> Option<Self> {
+        let mut lines = input.lines().map(str::trim).filter(|line| !line.is_empty());
+        if lines.next()? != EXPORT_HEADER {
+            return None;
+        }
+
+        let mut keyframes = Vec::new();
+        for line in lines {
+            let fields = line.split_whitespace().collect::<Vec<&str>>();
+            if fields.len() != 6 {
+                return None;
+            }
+            let mut floats = [0.0; 5];
+            for (float, field) in floats.iter_mut().zip(fields[1..].iter()) {
+                *float = field.parse::<f32>().ok()?;
+            }
+            keyframes.push(Keyframe::new(
+                fields[0].parse::<usize>().ok()?,
+                (floats[0], floats[1], floats[2]),
+                (floats[3], floats[4]),
+            ));
+        }
+        Some(Self::with_keyframes(keyframes))
+    }
+}
+
+impl CameraPathPlayback {
+    pub fn new(path: CameraPath, start_time: usize, looping: bool) -> Self {
+        Self {
+            path,
+            start_time,
+            looping,
+        }
+    }
+    // Maps the wall-clock time onto the path's own timeline
+    pub fn path_time(&self, curr_time: usize) -> usize {
+        let elapsed = curr_time.saturating_sub(self.start_time);
+        let duration = self.path.duration();
+        if self.looping && duration > 0 {
+            self.path.start_time() + elapsed % duration
+        } else {
+            self.path.start_time() + elapsed
+        }
+    }
+    pub fn is_finished(&self, curr_time: usize) -> bool {
+        !self.looping && self.path_time(curr_time) >= self.path.end_time()
+    }
+    pub fn apply(&self, curr_time: usize, camera: &mut Camera) {
+        if let Some(sampled) = self.path.sample(self.path_time(curr_time)) {
+            let (position, target) = (sampled.position, sampled.target);
+            camera.set_position(position.0, position.1, position.2);
+            camera.set_look(target.0, target.1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_path() -> CameraPath {
+        CameraPath::with_keyframes(vec![
+            Keyframe::new(0, (0.0, 0.0, 0.0), (0.1, 0.0)),
+            Keyframe::new(1000, (10.0, 0.0, 5.0), (2.0 * PI - 0.1, 0.5)),
+            Keyframe::new(3000, (20.0, -4.0, 5.0), (0.3, -0.25)),
+        ])
+    }
+
+    #[test]
+    fn passes_through_keyframes() {
+        let path = test_path();
+        for keyframe in path.keyframes() {
+            assert_eq!(path.sample(keyframe.time), Some(*keyframe));
+        }
+    }
+    #[test]
+    fn angles_take_shortest_arc() {
+        let path = test_path();
+        let sampled = path.sample(500).unwrap();
+        assert_eq!(sampled.time, 500);
+        assert!(sampled.target.0.abs() < 0.0001);
+    }
+    #[test]
+    fn export_round_trips() {
+        let path = test_path();
+        assert_eq!(CameraPath::import(&path.export()), Some(path));
+    }
+}
@@ -0,0 +1,83 @@
+// Shared by the command line tools (m3d_render, m3d_bench). Errors are plain messages for
+// whoever ran the tool, they get printed as they are
+use crate::rendering::texturepack;
+use std::str::FromStr;
+use std::{env, fs, process, thread};
+
+// The arguments after the program name, run through parse. --help prints usage and exits, so
+// does running without any arguments if needs_args is set. Bad arguments exit with 2
+pub fn parse_args<T>(
+    usage: &str,
+    needs_args: bool,
+    parse: fn(&[String]) -> Result<T, String>,
+) -> T {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    if (needs_args && args.is_empty()) || args.iter().any(|arg| arg == "--help") {
+        println!("{}", usage);
+        process::exit(0);
+    }
+    parse(&args).unwrap_or_else(|err| {
+        eprintln!("{}\n{}", err, usage);
+        process::exit(2);
+    })
+}
+
+// For errors past parsing, like a file that can't be read
+pub fn fail(program: &str, err: &str) -> ! {
+    eprintln!("{}: {}", program, err);
+    process::exit(1);
+}
+
+pub fn parse_number<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| format!("{} can't use {}", name, value))
+}
+
+// <W>x<H>
+pub fn parse_size(name: &str, value: &str) -> Result<(usize, usize), String> {
+    let (width, height) = value
+        .split_once('x')
+        .ok_or_else(|| format!("{} isn't a size like 640x320", value))?;
+    Ok((parse_number(name, width)?, parse_number(name, height)?))
+}
+
+// Every core there is
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |threads| threads.get())
+}
+
+pub fn check_frames_and_threads(frames: usize, threads: usize) -> Result<(), String> {
+    if frames == 0 || threads == 0 {
+        return Err("--frames and --threads have to be at least 1".to_string());
+    }
+    Ok(())
+}
+
+pub fn read(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))
+}
+
+// A texture pack file (images.txt) decoded into what GameState::new takes
+pub fn load_textures(path: &str) -> Result<Vec<u8>, String> {
+    texturepack::decode(&read(path)?).map_err(|err| format!("{}: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_numbers_and_sizes() {
+        assert_eq!(parse_number::<f32>("--time", "6.5"), Ok(6.5));
+        assert_eq!(
+            parse_number::<usize>("--frames", "-1"),
+            Err("--frames can't use -1".to_string())
+        );
+        assert_eq!(parse_size("--size", "320x160"), Ok((320, 160)));
+        assert!(parse_size("--size", "320").is_err());
+        assert!(parse_size("--size", "320xabc").is_err());
+        assert!(check_frames_and_threads(1, 0).is_err());
+        assert!(check_frames_and_threads(1, 1).is_ok());
+    }
+}
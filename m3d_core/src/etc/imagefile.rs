@@ -0,0 +1,401 @@
+use std::cmp;
+
+// Encoders for frames (RGBA, as they come out of the renderer) so they can be written to
+// disk without a browser. Alpha is dropped, frames are always opaque
+
+// Binary PPM (P6)
+pub fn encode_ppm(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut to_return = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    to_return.reserve(3 * width * height);
+    for pixel in pixels.chunks_exact(4).take(width * height) {
+        to_return.extend_from_slice(&pixel[..3]);
+    }
+    to_return
+}
+
+// Reads back what encode_ppm writes (any P6 with a maxval of 255), as RGBA
+pub fn decode_ppm(data: &[u8]) -> Option<(usize, usize, Vec<u8>)> {
+    // Header is 4 whitespace separated tokens, # starts a comment that runs to the end of
+    // the line. Exactly one whitespace byte separates the header from the pixels
+    let mut tokens = Vec::with_capacity(4);
+    let mut indx = 0;
+    while tokens.len() < 4 {
+        match data.get(indx)? {
+            b'#' => {
+                while *data.get(indx)? != b'\n' {
+                    indx += 1;
+                }
+            }
+            byte if byte.is_ascii_whitespace() => indx += 1,
+            _ => {
+                let start = indx;
+                while !data.get(indx)?.is_ascii_whitespace() {
+                    indx += 1;
+                }
+                tokens.push(std::str::from_utf8(&data[start..indx]).ok()?);
+            }
+        }
+    }
+    if tokens[0] != "P6" || tokens[3] != "255" {
+        return None;
+    }
+    let width = tokens[1].parse::<usize>().ok()?;
+    let height = tokens[2].parse::<usize>().ok()?;
+
+    let rgb = data.get(indx + 1..indx + 1 + 3 * width * height)?;
+    let mut pixels = Vec::with_capacity(4 * width * height);
+    for pixel in rgb.chunks_exact(3) {
+        pixels.extend_from_slice(&[pixel[0], pixel[1], pixel[2], 255]);
+    }
+    Some((width, height, pixels))
+}
+
+// 8 bit RGB PNG
+pub fn encode_png(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    // Every row starts with its filter type, 0 is none
+    let mut raw = Vec::with_capacity((3 * width + 1) * height);
+    for row in pixels.chunks_exact(4 * width).take(height) {
+        raw.push(0);
+        for pixel in row.chunks_exact(4) {
+            raw.extend_from_slice(&pixel[..3]);
+        }
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth, color type (RGB), compression, filter and interlace methods
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut to_return = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    write_chunk(&mut to_return, b"IHDR", &header);
+    write_chunk(&mut to_return, b"IDAT", &zlib(&raw));
+    write_chunk(&mut to_return, b"IEND", &[]);
+    to_return
+}
+
+fn write_chunk(target: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    target.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = target.len();
+    target.extend_from_slice(kind);
+    target.extend_from_slice(data);
+    let crc = crc32(&target[start..]);
+    target.extend_from_slice(&crc.to_be_bytes());
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 is the most bytes that can be summed before b could overflow
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+// Deflate with the fixed Huffman codes and a greedy LZ77 match finder that only remembers the
+// last position of each 3 byte prefix. Nowhere near zlib's ratio, but frames are mostly runs
+// of sky and repeated texels, which it handles fine
+const WINDOW: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// Deflate packs bits starting from the least significant one
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    bits: u32,
+}
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            acc: 0,
+            bits: 0,
+        }
+    }
+    fn write(&mut self, value: u32, bits: u32) {
+        self.acc |= value << self.bits;
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+    // Huffman codes go in most significant bit first
+    fn write_code(&mut self, code: u32, bits: u32) {
+        self.write(code.reverse_bits() >> (32 - bits), bits);
+    }
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+        self.bytes
+    }
+}
+
+fn write_literal(writer: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xC0 + symbol - 280, 8),
+    }
+}
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE
+        .iter()
+        .rposition(|&base| base as usize <= length)
+        .unwrap();
+    write_literal(writer, 257 + code as u32);
+    writer.write(
+        (length - LENGTH_BASE[code] as usize) as u32,
+        LENGTH_EXTRA[code] as u32,
+    );
+
+    let code = DISTANCE_BASE
+        .iter()
+        .rposition(|&base| base as usize <= distance)
+        .unwrap();
+    writer.write_code(code as u32, 5);
+    writer.write(
+        (distance - DISTANCE_BASE[code] as usize) as u32,
+        DISTANCE_EXTRA[code] as u32,
+    );
+}
+
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let hash = |indx: usize| {
+        let prefix =
+            (data[indx] as u32) << 16 | (data[indx + 1] as u32) << 8 | data[indx + 2] as u32;
+        (prefix.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    };
+    let mut last_seen = vec![usize::MAX; 1 << HASH_BITS];
+
+    let mut writer = BitWriter::new();
+    // One final block with the fixed codes
+    writer.write(1, 1);
+    writer.write(1, 2);
+
+    let mut indx = 0;
+    while indx < data.len() {
+        let mut length = 0;
+        let mut distance = 0;
+        if indx + MIN_MATCH <= data.len() {
+            let slot = hash(indx);
+            let candidate = last_seen[slot];
+            last_seen[slot] = indx;
+            if candidate != usize::MAX && indx - candidate <= WINDOW {
+                let max_length = cmp::min(MAX_MATCH, data.len() - indx);
+                while length < max_length && data[candidate + length] == data[indx + length] {
+                    length += 1;
+                }
+                distance = indx - candidate;
+            }
+        }
+
+        if length >= MIN_MATCH {
+            write_match(&mut writer, length, distance);
+            // Positions inside the match still need to be findable
+            for skipped in (indx + 1)..cmp::min(indx + length, data.len() - 2) {
+                last_seen[hash(skipped)] = skipped;
+            }
+            indx += length;
+        } else {
+            write_literal(&mut writer, data[indx] as u32);
+            indx += 1;
+        }
+    }
+    write_literal(&mut writer, 256);
+    writer.finish()
+}
+
+fn zlib(data: &[u8]) -> Vec<u8> {
+    // 32K window, deflate, no preset dictionary, and a check value making the header a
+    // multiple of 31
+    let mut to_return = vec![0x78, 0x01];
+    to_return.extend_from_slice(&deflate(data));
+    to_return.extend_from_slice(&adler32(data).to_be_bytes());
+    to_return
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_and_ppm_round_trip() {
+        assert_eq!(crc32(b"IEND"), 0xAE426082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+
+        let pixels = (0..24u8).flat_map(|value| [value, value * 2, 255 - value, 255]);
+        let pixels = pixels.collect::<Vec<u8>>();
+        let encoded = encode_ppm(&pixels, 6, 4);
+        assert_eq!(decode_ppm(&encoded), Some((6, 4, pixels)));
+        assert_eq!(
+            decode_ppm(b"P6\n# comment\n1 1\n255\n\x01\x02\x03"),
+            Some((1, 1, vec![1, 2, 3, 255]))
+        );
+        assert_eq!(decode_ppm(b"P6\n2 2\n255\n\x01\x02\x03"), None);
+    }
+
+    // Just enough of inflate to read back what deflate writes, which is only ever fixed
+    // Huffman blocks. None for any other kind or a stream that runs out
+    fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+        let mut bit = 0;
+        let mut read = |bits: u32| -> Option<u32> {
+            let mut value = 0;
+            for shift in 0..bits {
+                let byte = *data.get(bit / 8)?;
+                value |= ((byte >> (bit % 8)) as u32 & 1) << shift;
+                bit += 1;
+            }
+            Some(value)
+        };
+        // Huffman codes come most significant bit first
+        let mut read_code = |read: &mut dyn FnMut(u32) -> Option<u32>, bits: u32| {
+            let mut code = 0;
+            for _ in 0..bits {
+                code = code << 1 | read(1)?;
+            }
+            Some(code)
+        };
+
+        let mut to_return: Vec<u8> = Vec::new();
+        loop {
+            let last = read(1)?;
+            if read(2)? != 1 {
+                return None;
+            }
+            loop {
+                let mut code = read_code(&mut read, 7)?;
+                let symbol = if code <= 0x17 {
+                    256 + code
+                } else {
+                    code = code << 1 | read(1)?;
+                    match code {
+                        0x30..=0xBF => code - 0x30,
+                        0xC0..=0xC7 => 280 + code - 0xC0,
+                        _ => 144 + (code << 1 | read(1)?) - 0x190,
+                    }
+                };
+                match symbol {
+                    0..=255 => to_return.push(symbol as u8),
+                    256 => break,
+                    _ => {
+                        let code = (symbol - 257) as usize;
+                        let length = *LENGTH_BASE.get(code)? as usize
+                            + read(LENGTH_EXTRA[code] as u32)? as usize;
+                        let code = read_code(&mut read, 5)? as usize;
+                        let distance = DISTANCE_BASE[code] as usize
+                            + read(DISTANCE_EXTRA[code] as u32)? as usize;
+                        let start = to_return.len().checked_sub(distance)?;
+                        for indx in start..start + length {
+                            to_return.push(to_return[indx]);
+                        }
+                    }
+                }
+            }
+            if last == 1 {
+                return Some(to_return);
+            }
+        }
+    }
+    fn unzlib(data: &[u8]) -> Option<Vec<u8>> {
+        let (header, rest) = data.split_at(2);
+        let (body, check) = rest.split_at(rest.len().checked_sub(4)?);
+        if (header[0] as u16 * 256 + header[1] as u16) % 31 != 0 {
+            return None;
+        }
+        let inflated = inflate(body)?;
+        (adler32(&inflated).to_be_bytes() == check).then_some(inflated)
+    }
+
+    #[test]
+    fn zlib_streams_inflate_back() {
+        // Checked against zlib's inflate. Its own encoder makes the same bytes for a single
+        // literal, but matches lazily so it sends one more literal before the repeat
+        assert_eq!(
+            zlib(b"a"),
+            [0x78, 0x01, 0x4b, 0x04, 0x00, 0x00, 0x62, 0x00, 0x62]
+        );
+        assert_eq!(
+            zlib(b"abcabcabcabc"),
+            [0x78, 0x01, 0x4b, 0x4c, 0x4a, 0x86, 0x23, 0x00, 0x1d, 0xe0, 0x04, 0x99]
+        );
+        assert_eq!(unzlib(&zlib(b"")), Some(Vec::new()));
+
+        // Bytes that only repeat from the far end of the window, and a run longer than one
+        // match
+        let mut state = 0x2545_f491u32;
+        let mut noise = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        };
+        let mut data = (0..WINDOW).map(|_| noise()).collect::<Vec<u8>>();
+        let literals_only = zlib(&data).len();
+        for distance in [WINDOW, WINDOW - 1] {
+            let start = data.len() - distance;
+            data.extend_from_within(start..start + 600);
+        }
+        data.extend_from_slice(&[7; 1000]);
+        let compressed = zlib(&data);
+        assert_eq!(unzlib(&compressed), Some(data));
+        // The repeats and the run have to have been found, as literals they'd be 2200 bytes
+        assert!(compressed.len() < literals_only + 100);
+
+        let pixels = (0..64 * 32)
+            .flat_map(|indx| [(indx % 64) as u8, (indx / 64) as u8, 200, 255])
+            .collect::<Vec<u8>>();
+        let png = encode_png(&pixels, 64, 32);
+        // Signature, then IHDR's 25 bytes, then IDAT
+        let length = u32::from_be_bytes([png[33], png[34], png[35], png[36]]) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        let raw = unzlib(&png[41..41 + length]).unwrap();
+        for (row, raw_row) in raw.chunks_exact(1 + 3 * 64).enumerate() {
+            assert_eq!(raw_row[0], 0);
+            for (x, rgb) in raw_row[1..].chunks_exact(3).enumerate() {
+                assert_eq!(rgb, &pixels[4 * (64 * row + x)..4 * (64 * row + x) + 3]);
+            }
+        }
+    }
+}
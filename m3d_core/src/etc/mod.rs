@@ -1,2 +1,5 @@
 pub mod camera;
+pub mod camerapath;
+pub mod cli;
+pub mod imagefile;
 pub mod rendermath;
@@ -9,9 +9,33 @@ pub struct Matrix {
     pub height: usize,
     pub elements: Vec<f32>,
 }
-#[derive(Debug)]
+// Fixed size versions of Vector and Matrix, these live on the stack and are what the
+// renderer uses. The dynamic ones are only for things like bundle_points where the size
+// actually varies
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vec3 {
+    pub elements: [f32; 3],
+}
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vec4 {
+    pub elements: [f32; 4],
+}
+// Row major, same layout as Matrix
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub elements: [f32; 16],
+}
+#[derive(Clone, Copy, Debug)]
 pub struct Point3D {
-    pub position: Vector,
+    pub position: Vec4,
+}
+// Unit quaternions only, w is the real part
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
 }
 
 impl Vector {
@@ -203,38 +227,323 @@ impl Matrix {
         }
         to_return
     }
+    pub fn transpose(&self) -> Matrix {
+        let mut to_return = Self::with_fill(self.height, self.width, 0.0);
+        for row in 0..self.height {
+            for column in 0..self.width {
+                to_return.set(row, column, self.get(column, row));
+            }
+        }
+        to_return
+    }
+    // Gauss-Jordan with partial pivoting, None if the matrix isn't square or is singular
+    pub fn inverse(&self) -> Option<Matrix> {
+        if self.width != self.height {
+            return None;
+        }
+        let size = self.width;
+        let mut work = Self::with_flat_data(size, size, self.elements.clone());
+        let mut to_return = Self::with_fill(size, size, 0.0);
+        for indx in 0..size {
+            to_return.set(indx, indx, 1.0);
+        }
+
+        for column in 0..size {
+            // Biggest value in the column keeps the error down
+            let pivot = (column..size)
+                .max_by(|a, b| {
+                    work.get(column, *a)
+                        .abs()
+                        .total_cmp(&work.get(column, *b).abs())
+                })
+                .unwrap();
+            if work.get(column, pivot).abs() < 1e-12 {
+                return None;
+            }
+            if pivot != column {
+                for x in 0..size {
+                    let (a, b) = (work.get(x, pivot), work.get(x, column));
+                    work.set(x, pivot, b);
+                    work.set(x, column, a);
+                    let (a, b) = (to_return.get(x, pivot), to_return.get(x, column));
+                    to_return.set(x, pivot, b);
+                    to_return.set(x, column, a);
+                }
+            }
+
+            let factor = 1.0 / work.get(column, column);
+            for x in 0..size {
+                work.set(x, column, work.get(x, column) * factor);
+                to_return.set(x, column, to_return.get(x, column) * factor);
+            }
+            for row in 0..size {
+                let multiple = work.get(column, row);
+                if row == column || multiple == 0.0 {
+                    continue;
+                }
+                for x in 0..size {
+                    work.set(x, row, work.get(x, row) - multiple * work.get(x, column));
+                    to_return.set(
+                        x,
+                        row,
+                        to_return.get(x, row) - multiple * to_return.get(x, column),
+                    );
+                }
+            }
+        }
+        Some(to_return)
+    }
+}
+impl From<Mat4> for Matrix {
+    fn from(input: Mat4) -> Self {
+        Self::with_flat_data(4, 4, input.elements.to_vec())
+    }
+}
+
+impl Vec3 {
+    #[inline(always)]
+    pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        Self {
+            elements: [x, y, z],
+        }
+    }
+    pub const fn zero() -> Self {
+        Self::new(0.0, 0.0, 0.0)
+    }
+    #[inline(always)]
+    pub fn get(&self, indx: usize) -> f32 {
+        self.elements[indx]
+    }
+    #[inline(always)]
+    pub fn set(&mut self, indx: usize, val: f32) {
+        self.elements[indx] = val;
+    }
+    #[inline(always)]
+    pub fn x(&self) -> f32 {
+        self.elements[0]
+    }
+    #[inline(always)]
+    pub fn y(&self) -> f32 {
+        self.elements[1]
+    }
+    #[inline(always)]
+    pub fn z(&self) -> f32 {
+        self.elements[2]
+    }
+    #[inline(always)]
+    pub fn dot(&self, rhs: &Self) -> f32 {
+        self.x() * rhs.x() + self.y() * rhs.y() + self.z() * rhs.z()
+    }
+    #[inline(always)]
+    pub fn cross(&self, rhs: &Self) -> Self {
+        Self::new(
+            self.y() * rhs.z() - self.z() * rhs.y(),
+            self.z() * rhs.x() - self.x() * rhs.z(),
+            self.x() * rhs.y() - self.y() * rhs.x(),
+        )
+    }
+    pub fn norm2(&self) -> f32 {
+        self.dot(self)
+    }
+    pub fn norm(&self) -> f32 {
+        self.norm2().sqrt()
+    }
+    pub fn normalize(&self) -> Self {
+        *self * (1.0 / self.norm())
+    }
+    pub fn to_homo(&self) -> Vec4 {
+        Vec4::new(self.x(), self.y(), self.z(), 1.0)
+    }
 }
+impl Vec4 {
+    #[inline(always)]
+    pub const fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+        Self {
+            elements: [x, y, z, w],
+        }
+    }
+    #[inline(always)]
+    pub fn get(&self, indx: usize) -> f32 {
+        self.elements[indx]
+    }
+    #[inline(always)]
+    pub fn get_mut(&mut self, indx: usize) -> &mut f32 {
+        &mut self.elements[indx]
+    }
+    #[inline(always)]
+    pub fn set(&mut self, indx: usize, val: f32) {
+        self.elements[indx] = val;
+    }
+    #[inline(always)]
+    pub fn xyz(&self) -> Vec3 {
+        Vec3::new(self.elements[0], self.elements[1], self.elements[2])
+    }
+    #[inline(always)]
+    pub fn dot(&self, rhs: &Self) -> f32 {
+        self.elements[0] * rhs.elements[0]
+            + self.elements[1] * rhs.elements[1]
+            + self.elements[2] * rhs.elements[2]
+            + self.elements[3] * rhs.elements[3]
+    }
+    #[inline(always)]
+    pub fn homo_to_euc(&self) -> Self {
+        let w = self.elements[3];
+        Self::new(
+            self.elements[0] / w,
+            self.elements[1] / w,
+            self.elements[2] / w,
+            self.elements[3] / w,
+        )
+    }
+}
+impl Mat4 {
+    pub const fn zero() -> Self {
+        Self {
+            elements: [0.0; 16],
+        }
+    }
+    pub const fn with_flat_data(elements: [f32; 16]) -> Self {
+        Self { elements }
+    }
+    #[inline(always)]
+    pub fn get(&self, x: usize, y: usize) -> f32 {
+        self.elements[y * 4 + x]
+    }
+    #[inline(always)]
+    pub fn set(&mut self, x: usize, y: usize, val: f32) {
+        self.elements[y * 4 + x] = val;
+    }
+    #[inline(always)]
+    pub fn row(&self, y: usize) -> Vec4 {
+        Vec4::new(
+            self.elements[y * 4],
+            self.elements[y * 4 + 1],
+            self.elements[y * 4 + 2],
+            self.elements[y * 4 + 3],
+        )
+    }
+    pub fn transpose(&self) -> Self {
+        let mut to_return = Self::zero();
+        for row in 0..4 {
+            for column in 0..4 {
+                to_return.set(row, column, self.get(column, row));
+            }
+        }
+        to_return
+    }
+    // Not for anything per vertex, goes through the dynamic Matrix
+    pub fn inverse(&self) -> Option<Self> {
+        let inverse = Matrix::from(*self).inverse()?;
+        let mut to_return = Self::zero();
+        to_return.elements.copy_from_slice(&inverse.elements);
+        Some(to_return)
+    }
+}
+
+impl std::ops::Add for Vec3 {
+    type Output = Self;
+    #[inline(always)]
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.x() + rhs.x(), self.y() + rhs.y(), self.z() + rhs.z())
+    }
+}
+impl std::ops::Sub for Vec3 {
+    type Output = Self;
+    #[inline(always)]
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.x() - rhs.x(), self.y() - rhs.y(), self.z() - rhs.z())
+    }
+}
+impl std::ops::Mul<f32> for Vec3 {
+    type Output = Self;
+    #[inline(always)]
+    fn mul(self, rhs: f32) -> Self {
+        Self::new(self.x() * rhs, self.y() * rhs, self.z() * rhs)
+    }
+}
+impl std::ops::Neg for Vec3 {
+    type Output = Self;
+    #[inline(always)]
+    fn neg(self) -> Self {
+        Self::new(-self.x(), -self.y(), -self.z())
+    }
+}
+impl std::ops::Mul<f32> for Vec4 {
+    type Output = Self;
+    #[inline(always)]
+    fn mul(self, rhs: f32) -> Self {
+        let e = self.elements;
+        Self::new(e[0] * rhs, e[1] * rhs, e[2] * rhs, e[3] * rhs)
+    }
+}
+impl std::ops::Mul<Vec4> for Mat4 {
+    type Output = Vec4;
+    #[inline(always)]
+    fn mul(self, rhs: Vec4) -> Vec4 {
+        Vec4::new(
+            self.row(0).dot(&rhs),
+            self.row(1).dot(&rhs),
+            self.row(2).dot(&rhs),
+            self.row(3).dot(&rhs),
+        )
+    }
+}
+impl std::ops::Mul for Mat4 {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        let mut to_return = Self::zero();
+        for row in 0..4 {
+            for column in 0..4 {
+                let mut acc = 0.0;
+                for indx in 0..4 {
+                    acc += self.get(indx, row) * rhs.get(column, indx);
+                }
+                to_return.set(column, row, acc);
+            }
+        }
+        to_return
+    }
+}
+
 impl Point3D {
     pub fn from_euc_coords(x: isize, y: isize, z: isize) -> Self {
         Self {
-            position: Vector::with_data(vec![x as f32, y as f32, z as f32, 1.0]),
+            position: Vec4::new(x as f32, y as f32, z as f32, 1.0),
         }
     }
     pub fn from_euc_coords_float(x: f32, y: f32, z: f32) -> Self {
         Self {
-            position: Vector::with_data(vec![x, y, z, 1.0]),
+            position: Vec4::new(x, y, z, 1.0),
+        }
+    }
+    pub fn from_vec3(input: Vec3) -> Self {
+        Self {
+            position: input.to_homo(),
         }
     }
     // haha funni name
     pub fn from_homo_coords(x: isize, y: isize, z: isize, w: f32) -> Self {
         Self {
-            position: Vector::with_data(vec![x as f32, y as f32, z as f32, w]),
+            position: Vec4::new(x as f32, y as f32, z as f32, w),
         }
     }
     pub fn from_homo_coords_float(x: f32, y: f32, z: f32, w: f32) -> Self {
         Self {
-            position: Vector::with_data(vec![x, y, z, w]),
+            position: Vec4::new(x, y, z, w),
         }
     }
     pub fn homo_to_euc(&mut self) {
-        self.position.homo_to_euc_inplace();
+        self.position = self.position.homo_to_euc();
     }
-    pub fn transform(&self, target: &Matrix) -> Self {
-        let mut to_return = Self {
-            position: target.vector_mul(&self.position),
-        };
-        to_return.homo_to_euc();
-        to_return
+    #[inline(always)]
+    pub fn transform(&self, target: &Mat4) -> Self {
+        Self {
+            position: (*target * self.position).homo_to_euc(),
+        }
+    }
+    #[inline(always)]
+    pub fn xyz(&self) -> Vec3 {
+        self.position.xyz()
     }
 
     #[inline(always)]
@@ -296,6 +605,117 @@ impl std::fmt::Display for Point3D {
     }
 }
 
+impl Quaternion {
+    pub fn new(w: f32, x: f32, y: f32, z: f32) -> Self {
+        Self { w, x, y, z }
+    }
+    pub fn identity() -> Self {
+        Self::new(1.0, 0.0, 0.0, 0.0)
+    }
+    // Counterclockwise looking down the axis towards the origin, axis must be normalized
+    pub fn from_axis_angle(axis: Vec3, theta: f32) -> Self {
+        let half = theta / 2.0;
+        let sin = half.sin();
+        Self::new(half.cos(), axis.x() * sin, axis.y() * sin, axis.z() * sin)
+    }
+    // Yaw around the world y axis, then pitch around the camera's x axis. The conjugate
+    // of this is the same rotation as RenderMatrices::rotation_x(inclination) *
+    // RenderMatrices::rotation_y(rotation)
+    pub fn from_yaw_pitch(rotation: f32, inclination: f32) -> Self {
+        Self::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), rotation).mul(&Self::from_axis_angle(
+            Vec3::new(1.0, 0.0, 0.0),
+            inclination,
+        ))
+    }
+    // Inverse of from_yaw_pitch, ignores any roll
+    pub fn to_yaw_pitch(&self) -> (f32, f32) {
+        let forward = self.rotate(Vec3::new(0.0, 0.0, 1.0));
+        (
+            forward.x().atan2(forward.z()),
+            (-forward.y()).clamp(-1.0, 1.0).asin(),
+        )
+    }
+    // Hamilton product, applies rhs first then self
+    pub fn mul(&self, rhs: &Self) -> Self {
+        Self::new(
+            self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+            self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+        )
+    }
+    pub fn conjugate(&self) -> Self {
+        Self::new(self.w, -self.x, -self.y, -self.z)
+    }
+    pub fn dot(&self, rhs: &Self) -> f32 {
+        self.w * rhs.w + self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    }
+    pub fn norm(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+    pub fn normalize(&self) -> Self {
+        let factor = 1.0 / self.norm();
+        Self::new(
+            self.w * factor,
+            self.x * factor,
+            self.y * factor,
+            self.z * factor,
+        )
+    }
+    pub fn rotate(&self, v: Vec3) -> Vec3 {
+        let rotated = self
+            .mul(&Self::new(0.0, v.x(), v.y(), v.z()))
+            .mul(&self.conjugate());
+        Vec3::new(rotated.x, rotated.y, rotated.z)
+    }
+    pub fn slerp(&self, rhs: &Self, t: f32) -> Self {
+        // q and -q are the same rotation, go the short way around
+        let mut cos_theta = self.dot(rhs);
+        let target = if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            Self::new(-rhs.w, -rhs.x, -rhs.y, -rhs.z)
+        } else {
+            *rhs
+        };
+
+        // Nearly parallel, sin(theta) blows up so just lerp
+        let (factor_a, factor_b) = if cos_theta > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            let inv_sin = 1.0 / theta.sin();
+            (
+                ((1.0 - t) * theta).sin() * inv_sin,
+                (t * theta).sin() * inv_sin,
+            )
+        };
+        Self::new(
+            factor_a * self.w + factor_b * target.w,
+            factor_a * self.x + factor_b * target.x,
+            factor_a * self.y + factor_b * target.y,
+            factor_a * self.z + factor_b * target.z,
+        )
+        .normalize()
+    }
+    pub fn to_matrix(&self) -> Mat4 {
+        let (w, x, y, z) = (self.w, self.x, self.y, self.z);
+
+        let mut to_return = Mat4::zero();
+        to_return.set(0, 0, 1.0 - 2.0 * (y * y + z * z));
+        to_return.set(1, 0, 2.0 * (x * y - w * z));
+        to_return.set(2, 0, 2.0 * (x * z + w * y));
+        to_return.set(0, 1, 2.0 * (x * y + w * z));
+        to_return.set(1, 1, 1.0 - 2.0 * (x * x + z * z));
+        to_return.set(2, 1, 2.0 * (y * z - w * x));
+        to_return.set(0, 2, 2.0 * (x * z - w * y));
+        to_return.set(1, 2, 2.0 * (y * z + w * x));
+        to_return.set(2, 2, 1.0 - 2.0 * (x * x + y * y));
+        to_return.set(3, 3, 1.0);
+
+        to_return
+    }
+}
+
 pub struct RenderMatrices {}
 impl RenderMatrices {
     pub fn bundle_points(points: &[&Point3D]) -> Matrix {
@@ -321,21 +741,21 @@ impl RenderMatrices {
         }
         to_return
     }
-    pub fn compose_transformations(input: &[&Matrix]) -> Matrix {
+    pub fn compose_transformations(input: &[&Mat4]) -> Mat4 {
         assert!(input.len() >= 2);
-        let mut to_return = input[1].matrix_mul(input[0]);
+        let mut to_return = *input[1] * *input[0];
         for indx in 2..input.len() {
-            to_return = input[indx].matrix_mul(&to_return);
+            to_return = *input[indx] * to_return;
         }
         to_return
     }
-    pub fn projection(near: f32, far: f32) -> Matrix {
+    pub fn projection(near: f32, far: f32) -> Mat4 {
         let n = near;
         let f = far;
         let a = f / (f - n);
         let b = -f * n / (f - n);
 
-        let mut to_return = Matrix::with_fill(4, 4, 0.0);
+        let mut to_return = Mat4::zero();
         to_return.set(0, 0, n);
         to_return.set(1, 1, n);
         to_return.set(2, 2, a);
@@ -344,11 +764,25 @@ impl RenderMatrices {
 
         to_return
     }
-    pub fn rotation_x(theta: f32) -> Matrix {
+    // Maps z linearly from [near, far] to [0, 1], leaves x and y alone
+    pub fn orthographic(near: f32, far: f32) -> Mat4 {
+        let a = 1.0 / (far - near);
+        let b = -near / (far - near);
+
+        let mut to_return = Mat4::zero();
+        to_return.set(0, 0, 1.0);
+        to_return.set(1, 1, 1.0);
+        to_return.set(2, 2, a);
+        to_return.set(3, 2, b);
+        to_return.set(3, 3, 1.0);
+
+        to_return
+    }
+    pub fn rotation_x(theta: f32) -> Mat4 {
         let cos = theta.cos();
         let sin = theta.sin();
 
-        let mut to_return = Matrix::with_fill(4, 4, 0.0);
+        let mut to_return = Mat4::zero();
         to_return.set(0, 0, 1.0);
         to_return.set(1, 1, cos);
         to_return.set(2, 1, sin);
@@ -358,11 +792,11 @@ impl RenderMatrices {
 
         to_return
     }
-    pub fn rotation_y(theta: f32) -> Matrix {
+    pub fn rotation_y(theta: f32) -> Mat4 {
         let cos = theta.cos();
         let sin = theta.sin();
 
-        let mut to_return = Matrix::with_fill(4, 4, 0.0);
+        let mut to_return = Mat4::zero();
         to_return.set(0, 0, cos);
         to_return.set(2, 0, -sin);
         to_return.set(1, 1, 1.0);
@@ -372,11 +806,11 @@ impl RenderMatrices {
 
         to_return
     }
-    pub fn rotation_z(theta: f32) -> Matrix {
+    pub fn rotation_z(theta: f32) -> Mat4 {
         let cos = theta.cos();
         let sin = theta.sin();
 
-        let mut to_return = Matrix::with_fill(4, 4, 0.0);
+        let mut to_return = Mat4::zero();
         to_return.set(0, 0, cos);
         to_return.set(1, 0, -sin);
         to_return.set(0, 1, sin);
@@ -391,7 +825,7 @@ impl RenderMatrices {
         thetay: f32,
         thetaz: f32,
         translation: Option<&(f32, f32, f32)>,
-    ) -> Matrix {
+    ) -> Mat4 {
         match translation {
             Some((tx, ty, tz)) => Self::compose_transformations(&[
                 &Self::translation(-*tx, -*ty, -*tz),
@@ -400,12 +834,13 @@ impl RenderMatrices {
                 &Self::rotation_x(thetax),
                 &Self::translation(*tx, *ty, *tz),
             ]),
-            None => Self::rotation_x(thetax)
-                .matrix_mul(&Self::rotation_y(thetay).matrix_mul(&Self::rotation_z(thetaz))),
+            None => {
+                Self::rotation_x(thetax) * (Self::rotation_y(thetay) * Self::rotation_z(thetaz))
+            }
         }
     }
-    pub fn translation(tx: f32, ty: f32, tz: f32) -> Matrix {
-        let mut to_return = Matrix::with_fill(4, 4, 0.0);
+    pub fn translation(tx: f32, ty: f32, tz: f32) -> Mat4 {
+        let mut to_return = Mat4::zero();
         to_return.set(0, 0, 1.0);
         to_return.set(1, 1, 1.0);
         to_return.set(2, 2, 1.0);
@@ -416,8 +851,8 @@ impl RenderMatrices {
 
         to_return
     }
-    pub fn scale(sx: f32, sy: f32, sz: f32) -> Matrix {
-        let mut to_return = Matrix::with_fill(4, 4, 0.0);
+    pub fn scale(sx: f32, sy: f32, sz: f32) -> Mat4 {
+        let mut to_return = Mat4::zero();
         to_return.set(0, 0, sx);
         to_return.set(1, 1, sy);
         to_return.set(2, 2, sz);
@@ -425,8 +860,8 @@ impl RenderMatrices {
 
         to_return
     }
-    pub fn identity() -> Matrix {
-        let mut to_return = Matrix::with_fill(4, 4, 0.0);
+    pub fn identity() -> Mat4 {
+        let mut to_return = Mat4::zero();
         to_return.set(0, 0, 1.0);
         to_return.set(1, 1, 1.0);
         to_return.set(2, 2, 1.0);
@@ -434,6 +869,40 @@ impl RenderMatrices {
 
         to_return
     }
+    // World to camera space for a camera at eye looking at target, the same mapping as
+    // Camera's reverse frustum: +z forward and +y down the screen. up is the world's up
+    // (0, -1, 0 for this world) and only has to not be parallel to the view direction.
+    // None if it is, or if eye and target are the same point
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Option<Mat4> {
+        let forward = target - eye;
+        let right = forward.cross(&up);
+        if forward.norm2() < 1e-12 || right.norm2() < 1e-12 * forward.norm2() * up.norm2() {
+            return None;
+        }
+        let forward = forward.normalize();
+        let right = right.normalize();
+        let down = forward.cross(&right);
+
+        let mut rotation = Self::identity();
+        for (row, axis) in [right, down, forward].iter().enumerate() {
+            for column in 0..3 {
+                rotation.set(column, row, axis.get(column));
+            }
+        }
+        Some(rotation * Self::translation(-eye.x(), -eye.y(), -eye.z()))
+    }
+    // For transforming normals by model, the inverse transpose of its upper 3x3. Keeps
+    // normals perpendicular to the surface under non uniform scales, they still need
+    // normalizing afterwards. None if model squashes everything flat
+    pub fn normal_matrix(model: &Mat4) -> Option<Mat4> {
+        let mut linear = *model;
+        for indx in 0..3 {
+            linear.set(3, indx, 0.0);
+            linear.set(indx, 3, 0.0);
+        }
+        linear.set(3, 3, 1.0);
+        Some(linear.inverse()?.transpose())
+    }
     pub fn det_3x3(a: f32, b: f32, c: f32, d: f32, e: f32, f: f32, g: f32, h: f32, i: f32) -> f32 {
         a * (e * i - f * h) - b * (d * i - f * g) + c * (d * h - e * g)
     }
@@ -475,15 +944,558 @@ impl RenderMatrices {
     }
     // Using right hand rule, thumb is normal, index finger is 2nd point,
     // middle finger gives first point, everything is relative to third point
-    pub fn triface_normal(v1: &Point3D, v2: &Point3D, v3: &Point3D) -> Vector {
-        let a = v2.position.minus(&v3.position);
-        let b = v1.position.minus(&v3.position);
-        a.cross(&b).normalize().scalar_mul(-1.0)
-    }
-    pub fn triface_center(v1: &Point3D, v2: &Point3D, v3: &Point3D) -> Vector {
-        v1.position
-            .plus(&v2.position)
-            .plus(&v3.position)
-            .scalar_mul(1.0 / 3.0)
+    pub fn triface_normal(v1: &Point3D, v2: &Point3D, v3: &Point3D) -> Vec3 {
+        let a = v2.xyz() - v3.xyz();
+        let b = v1.xyz() - v3.xyz();
+        -a.cross(&b).normalize()
+    }
+    pub fn triface_center(v1: &Point3D, v2: &Point3D, v3: &Point3D) -> Vec3 {
+        (v1.xyz() + v2.xyz() + v3.xyz()) * (1.0 / 3.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_matrix_eq(a: &Mat4, b: &Mat4) {
+        for (x, y) in a.elements.iter().zip(b.elements.iter()) {
+            assert!((x - y).abs() < 0.0001, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn quaternion_matches_euler_camera() {
+        for &(rotation, inclination) in &[(0.0, 0.0), (0.7, -1.0), (-2.5, 0.3), (3.0, 1.2)] {
+            let euler =
+                RenderMatrices::rotation_x(inclination) * RenderMatrices::rotation_y(rotation);
+            let quaternion = Quaternion::from_yaw_pitch(rotation, inclination)
+                .conjugate()
+                .to_matrix();
+            assert_matrix_eq(&euler, &quaternion);
+
+            let (back_rotation, back_inclination) =
+                Quaternion::from_yaw_pitch(rotation, inclination).to_yaw_pitch();
+            assert!((back_rotation - rotation).abs() < 0.0001);
+            assert!((back_inclination - inclination).abs() < 0.0001);
+        }
+    }
+    #[test]
+    fn slerp_hits_endpoints_and_midpoint() {
+        let a = Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 0.2);
+        let b = Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 1.4);
+        assert_matrix_eq(&a.slerp(&b, 0.0).to_matrix(), &a.to_matrix());
+        assert_matrix_eq(&a.slerp(&b, 1.0).to_matrix(), &b.to_matrix());
+        assert_matrix_eq(
+            &a.slerp(&b, 0.5).to_matrix(),
+            &Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 0.8).to_matrix(),
+        );
+    }
+
+    // Property tests: every check runs over CASES inputs from a fixed seed, so failures
+    // reproduce. Inputs are printed in the assert messages
+    const CASES: usize = 500;
+
+    // xorshift64*, plenty for picking test inputs
+    struct Rng {
+        state: u64,
+    }
+    impl Rng {
+        fn new(seed: u64) -> Self {
+            Self { state: seed.max(1) }
+        }
+        fn next_u64(&mut self) -> u64 {
+            self.state ^= self.state >> 12;
+            self.state ^= self.state << 25;
+            self.state ^= self.state >> 27;
+            self.state.wrapping_mul(0x2545F4914F6CDD1D)
+        }
+        // Uniform in [min, max)
+        fn range(&mut self, min: f32, max: f32) -> f32 {
+            let unit = (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32;
+            min + unit * (max - min)
+        }
+        fn angle(&mut self) -> f32 {
+            self.range(-2.0 * std::f32::consts::PI, 2.0 * std::f32::consts::PI)
+        }
+        fn vec3(&mut self, extent: f32) -> Vec3 {
+            Vec3::new(
+                self.range(-extent, extent),
+                self.range(-extent, extent),
+                self.range(-extent, extent),
+            )
+        }
+        fn quaternion(&mut self) -> Quaternion {
+            loop {
+                let q = Quaternion::new(
+                    self.range(-1.0, 1.0),
+                    self.range(-1.0, 1.0),
+                    self.range(-1.0, 1.0),
+                    self.range(-1.0, 1.0),
+                );
+                if q.norm() > 0.1 {
+                    return q.normalize();
+                }
+            }
+        }
+    }
+
+    fn close(a: f32, b: f32, tolerance: f32) -> bool {
+        (a - b).abs() <= tolerance * (1.0 + a.abs().max(b.abs()))
+    }
+    // Relative, for matrices with big entries
+    fn assert_matrix_close(a: &Mat4, b: &Mat4, tolerance: f32) {
+        for (x, y) in a.elements.iter().zip(b.elements.iter()) {
+            assert!(close(*x, *y, tolerance), "{:?} != {:?}", a, b);
+        }
+    }
+    fn det_upper_3x3(a: &Mat4) -> f32 {
+        RenderMatrices::det_3x3(
+            a.get(0, 0),
+            a.get(1, 0),
+            a.get(2, 0),
+            a.get(0, 1),
+            a.get(1, 1),
+            a.get(2, 1),
+            a.get(0, 2),
+            a.get(1, 2),
+            a.get(2, 2),
+        )
+    }
+
+    #[test]
+    fn rotations_are_orthonormal() {
+        let mut rng = Rng::new(1);
+        for _ in 0..CASES {
+            let (x, y, z) = (rng.angle(), rng.angle(), rng.angle());
+            for rotation in [
+                RenderMatrices::rotation_x(x),
+                RenderMatrices::rotation_y(y),
+                RenderMatrices::rotation_z(z),
+                RenderMatrices::rotation_3d(x, y, z, None),
+                rng.quaternion().to_matrix(),
+            ] {
+                assert_matrix_eq(
+                    &(rotation * rotation.transpose()),
+                    &RenderMatrices::identity(),
+                );
+                assert!(
+                    close(det_upper_3x3(&rotation), 1.0, 0.0001),
+                    "{:?} isn't a proper rotation",
+                    rotation
+                );
+            }
+        }
+    }
+    #[test]
+    fn rotations_keep_lengths_and_angles() {
+        let mut rng = Rng::new(2);
+        for _ in 0..CASES {
+            let q = rng.quaternion();
+            let (a, b) = (rng.vec3(100.0), rng.vec3(100.0));
+            let (rotated_a, rotated_b) = (q.rotate(a), q.rotate(b));
+            assert!(close(rotated_a.norm(), a.norm(), 0.0001), "{:?} {:?}", q, a);
+            assert!(
+                close(rotated_a.dot(&rotated_b), a.dot(&b), 0.001),
+                "{:?} {:?} {:?}",
+                q,
+                a,
+                b
+            );
+
+            // Same rotation through the matrix
+            let through_matrix = Point3D::from_vec3(a).transform(&q.to_matrix()).xyz();
+            for indx in 0..3 {
+                assert!(
+                    close(through_matrix.get(indx), rotated_a.get(indx), 0.0001),
+                    "{:?} {:?}",
+                    q,
+                    a
+                );
+            }
+        }
+    }
+    #[test]
+    fn inverses_round_trip() {
+        let mut rng = Rng::new(3);
+        for _ in 0..CASES {
+            let angle = rng.angle();
+            let t = rng.vec3(1000.0);
+            let s = Vec3::new(
+                rng.range(0.1, 10.0),
+                rng.range(0.1, 10.0),
+                rng.range(0.1, 10.0),
+            );
+            let pairs = [
+                (
+                    RenderMatrices::rotation_x(angle),
+                    RenderMatrices::rotation_x(-angle),
+                ),
+                (
+                    RenderMatrices::rotation_y(angle),
+                    RenderMatrices::rotation_y(-angle),
+                ),
+                (
+                    RenderMatrices::rotation_z(angle),
+                    RenderMatrices::rotation_z(-angle),
+                ),
+                (
+                    RenderMatrices::translation(t.x(), t.y(), t.z()),
+                    RenderMatrices::translation(-t.x(), -t.y(), -t.z()),
+                ),
+                (
+                    RenderMatrices::scale(s.x(), s.y(), s.z()),
+                    RenderMatrices::scale(1.0 / s.x(), 1.0 / s.y(), 1.0 / s.z()),
+                ),
+            ];
+            for (forward, back) in pairs.iter() {
+                assert_matrix_eq(&(*back * *forward), &RenderMatrices::identity());
+                assert_matrix_eq(&(*forward * *back), &RenderMatrices::identity());
+            }
+
+            let q = rng.quaternion();
+            assert_matrix_eq(
+                &q.mul(&q.conjugate()).to_matrix(),
+                &RenderMatrices::identity(),
+            );
+            let v = rng.vec3(100.0);
+            let back = q.conjugate().rotate(q.rotate(v));
+            for indx in 0..3 {
+                assert!(
+                    close(back.get(indx), v.get(indx), 0.0001),
+                    "{:?} {:?}",
+                    q,
+                    v
+                );
+            }
+
+            // Inclination is kept off the poles, where rotation is meaningless
+            let (rotation, inclination) = (rng.range(-3.1, 3.1), rng.range(-1.5, 1.5));
+            let (back_rotation, back_inclination) =
+                Quaternion::from_yaw_pitch(rotation, inclination).to_yaw_pitch();
+            assert!(
+                close(back_rotation, rotation, 0.001),
+                "{} {}",
+                rotation,
+                inclination
+            );
+            assert!(
+                close(back_inclination, inclination, 0.001),
+                "{} {}",
+                rotation,
+                inclination
+            );
+        }
+    }
+    #[test]
+    fn dynamic_and_fixed_size_math_agree() {
+        let mut rng = Rng::new(4);
+        for _ in 0..CASES {
+            let a = RenderMatrices::rotation_3d(rng.angle(), rng.angle(), rng.angle(), None)
+                * RenderMatrices::translation(rng.range(-50.0, 50.0), 0.0, rng.range(-50.0, 50.0));
+            let b = RenderMatrices::scale(rng.range(0.1, 4.0), 1.0, rng.range(0.1, 4.0))
+                * rng.quaternion().to_matrix();
+            let product = Matrix::from(a).matrix_mul(&Matrix::from(b));
+            for (x, y) in product.elements.iter().zip((a * b).elements.iter()) {
+                assert!(close(*x, *y, 0.0001), "{:?} {:?}", a, b);
+            }
+
+            let (u, v) = (rng.vec3(100.0), rng.vec3(100.0));
+            let (dynamic_u, dynamic_v) = (
+                Vector::with_data(u.elements.to_vec()),
+                Vector::with_data(v.elements.to_vec()),
+            );
+            let cross = dynamic_u.cross(&dynamic_v);
+            let scale = u.norm() * v.norm();
+            for indx in 0..3 {
+                assert!(
+                    close(cross.get(indx), u.cross(&v).get(indx), 0.0001),
+                    "{:?} {:?}",
+                    u,
+                    v
+                );
+            }
+            // Perpendicular to both inputs
+            assert!(
+                cross.dot(&dynamic_u).abs() <= 0.0001 * scale * u.norm(),
+                "{:?} {:?}",
+                u,
+                v
+            );
+            assert!(
+                cross.dot(&dynamic_v).abs() <= 0.0001 * scale * v.norm(),
+                "{:?} {:?}",
+                u,
+                v
+            );
+
+            if u.norm() > 0.001 {
+                assert!(close(dynamic_u.normalize().norm(), 1.0, 0.0001), "{:?}", u);
+                assert!(close(u.normalize().norm(), 1.0, 0.0001), "{:?}", u);
+            }
+        }
+    }
+    #[test]
+    fn barycentrics_sum_to_one_and_reconstruct() {
+        let mut rng = Rng::new(5);
+        for _ in 0..CASES {
+            let v = [
+                (rng.range(0.0, 1000.0), rng.range(0.0, 1000.0)),
+                (rng.range(0.0, 1000.0), rng.range(0.0, 1000.0)),
+                (rng.range(0.0, 1000.0), rng.range(0.0, 1000.0)),
+            ];
+            let p = (rng.range(-100.0, 1100.0), rng.range(-100.0, 1100.0));
+            let params = RenderMatrices::barycentric_params(
+                p.0, p.1, v[0].0, v[0].1, v[1].0, v[1].1, v[2].0, v[2].1,
+            );
+            let (u, bv, w, dudx, dvdx, dwdx, dudy, dvdy, dwdy) = match params {
+                Some(params) => params,
+                // Only degenerate triangles are turned away
+                None => {
+                    let area = (v[1].0 - v[0].0) * (v[2].1 - v[0].1)
+                        - (v[2].0 - v[0].0) * (v[1].1 - v[0].1);
+                    assert!(area.abs() < 1.0, "{:?} was rejected", v);
+                    continue;
+                }
+            };
+            // Far outside a thin triangle the coordinates get huge, scale the tolerance with them
+            let tolerance = 0.001 * (1.0 + u.abs() + bv.abs() + w.abs());
+
+            assert!(close(u + bv + w, 1.0, tolerance), "{:?} {:?}", v, p);
+            assert!(close(dudx + dvdx + dwdx, 0.0, tolerance), "{:?} {:?}", v, p);
+            assert!(close(dudy + dvdy + dwdy, 0.0, tolerance), "{:?} {:?}", v, p);
+            let x = u * v[0].0 + bv * v[1].0 + w * v[2].0;
+            let y = u * v[0].1 + bv * v[1].1 + w * v[2].1;
+            assert!(close(x, p.0, tolerance), "{:?} {:?} gave {}", v, p, x);
+            assert!(close(y, p.1, tolerance), "{:?} {:?} gave {}", v, p, y);
+
+            // Each vertex is all of its own coordinate
+            for (indx, corner) in v.iter().enumerate() {
+                let (u, bv, w, ..) = RenderMatrices::barycentric_params(
+                    corner.0, corner.1, v[0].0, v[0].1, v[1].0, v[1].1, v[2].0, v[2].1,
+                )
+                .unwrap();
+                let expected = [(1.0, 0.0, 0.0), (0.0, 1.0, 0.0), (0.0, 0.0, 1.0)][indx];
+                assert!(close(u, expected.0, 0.001), "{:?} corner {}", v, indx);
+                assert!(close(bv, expected.1, 0.001), "{:?} corner {}", v, indx);
+                assert!(close(w, expected.2, 0.001), "{:?} corner {}", v, indx);
+            }
+        }
+    }
+    #[test]
+    fn projections_map_near_and_far_to_zero_and_one() {
+        let mut rng = Rng::new(6);
+        for _ in 0..CASES {
+            let near = rng.range(0.1, 20.0);
+            let far = near + rng.range(1.0, 1000.0);
+            let (x, y) = (rng.range(-100.0, 100.0), rng.range(-100.0, 100.0));
+            for projection in [
+                RenderMatrices::projection(near, far),
+                RenderMatrices::orthographic(near, far),
+            ] {
+                let depth = |z: f32| {
+                    Point3D::from_euc_coords_float(x, y, z)
+                        .transform(&projection)
+                        .get(2)
+                };
+                assert!(close(depth(near), 0.0, 0.0001), "near {} far {}", near, far);
+                assert!(close(depth(far), 1.0, 0.0001), "near {} far {}", near, far);
+
+                // Depth only ever increases between the planes
+                let z1 = rng.range(near, far);
+                let z2 = rng.range(near, far);
+                let (z1, z2) = (z1.min(z2), z1.max(z2));
+                assert!(
+                    depth(z1) <= depth(z2) + 0.0001,
+                    "near {} far {} {} {}",
+                    near,
+                    far,
+                    z1,
+                    z2
+                );
+                assert!(
+                    (0.0..=1.0001).contains(&depth(z1)),
+                    "near {} far {} {}",
+                    near,
+                    far,
+                    z1
+                );
+            }
+            // Perspective divides x and y by distance, so they shrink going away
+            let z = rng.range(near, far);
+            let projected = Point3D::from_euc_coords_float(x, y, z)
+                .transform(&RenderMatrices::projection(near, far));
+            assert!(
+                close(projected.get(0), x * near / z, 0.0001),
+                "near {} far {} {}",
+                near,
+                far,
+                z
+            );
+            assert!(
+                close(projected.get(1), y * near / z, 0.0001),
+                "near {} far {} {}",
+                near,
+                far,
+                z
+            );
+        }
+    }
+    #[test]
+    fn general_inverses_round_trip() {
+        let mut rng = Rng::new(7);
+        for _ in 0..CASES {
+            let near = rng.range(0.1, 20.0);
+            let far = near + rng.range(1.0, 1000.0);
+            let model =
+                RenderMatrices::translation(rng.range(-50.0, 50.0), rng.range(-50.0, 50.0), 0.0)
+                    * rng.quaternion().to_matrix()
+                    * RenderMatrices::scale(
+                        rng.range(0.1, 10.0),
+                        rng.range(0.1, 10.0),
+                        rng.range(0.1, 10.0),
+                    );
+            for matrix in [model, RenderMatrices::projection(near, far) * model] {
+                let inverse = matrix.inverse().unwrap();
+                assert_matrix_close(&(inverse * matrix), &RenderMatrices::identity(), 0.001);
+                assert_matrix_close(&(matrix * inverse), &RenderMatrices::identity(), 0.001);
+                assert_matrix_eq(&matrix.transpose().transpose(), &matrix);
+                assert_matrix_close(
+                    &matrix.transpose().inverse().unwrap(),
+                    &inverse.transpose(),
+                    0.001,
+                );
+            }
+
+            // Points come back from wherever the matrix sends them
+            let point = Point3D::from_vec3(rng.vec3(100.0));
+            let back = point.transform(&model).transform(&model.inverse().unwrap());
+            for indx in 0..3 {
+                assert!(
+                    close(back.get(indx), point.get(indx), 0.0001),
+                    "{:?} {}",
+                    model,
+                    point
+                );
+            }
+        }
+        // Flattening z can't be undone, and only square matrices have inverses
+        assert!(RenderMatrices::scale(1.0, 1.0, 0.0).inverse().is_none());
+        assert!(Matrix::with_fill(3, 2, 1.0).inverse().is_none());
+        let tall = Matrix::with_flat_data(2, 3, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        assert_eq!(
+            tall.transpose().elements,
+            vec![1.0, 3.0, 5.0, 2.0, 4.0, 6.0]
+        );
+    }
+    #[test]
+    fn look_at_puts_the_target_straight_ahead() {
+        let mut rng = Rng::new(8);
+        let up = Vec3::new(0.0, -1.0, 0.0);
+        for _ in 0..CASES {
+            let eye = rng.vec3(100.0);
+            let target = eye + rng.vec3(50.0);
+            let view = match RenderMatrices::look_at(eye, target, up) {
+                Some(view) => view,
+                None => continue,
+            };
+            assert!(
+                close(det_upper_3x3(&view), 1.0, 0.0001),
+                "{:?} {:?}",
+                eye,
+                target
+            );
+            let eye_view = Point3D::from_vec3(eye).transform(&view);
+            let target_view = Point3D::from_vec3(target).transform(&view);
+            for indx in 0..3 {
+                assert!(
+                    close(eye_view.get(indx), 0.0, 0.0001),
+                    "{:?} {:?}",
+                    eye,
+                    target
+                );
+            }
+            let distance = (target - eye).norm();
+            assert!(
+                close(target_view.get(0), 0.0, 0.0001 * distance),
+                "{:?} {:?}",
+                eye,
+                target
+            );
+            assert!(
+                close(target_view.get(1), 0.0, 0.0001 * distance),
+                "{:?} {:?}",
+                eye,
+                target
+            );
+            assert!(
+                close(target_view.get(2), distance, 0.0001),
+                "{:?} {:?}",
+                eye,
+                target
+            );
+            // World up stays up on screen, which is -y in camera space
+            let above = Point3D::from_vec3(target + up).transform(&view);
+            assert!(above.get(1) < target_view.get(1), "{:?} {:?}", eye, target);
+
+            // Same matrix as the camera builds from its orientation
+            let q = Quaternion::from_yaw_pitch(rng.range(-3.1, 3.1), rng.range(-1.5, 1.5));
+            let camera_view = q.conjugate().to_matrix()
+                * RenderMatrices::translation(-eye.x(), -eye.y(), -eye.z());
+            let forward = q.rotate(Vec3::new(0.0, 0.0, 1.0));
+            assert_matrix_close(
+                &RenderMatrices::look_at(eye, eye + forward, up).unwrap(),
+                &camera_view,
+                0.001,
+            );
+        }
+        assert!(
+            RenderMatrices::look_at(Vec3::new(1.0, 2.0, 3.0), Vec3::new(1.0, 2.0, 3.0), up)
+                .is_none()
+        );
+        assert!(
+            RenderMatrices::look_at(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 5.0, 0.0), up)
+                .is_none()
+        );
+    }
+    #[test]
+    fn normal_matrix_keeps_normals_perpendicular() {
+        let mut rng = Rng::new(9);
+        for _ in 0..CASES {
+            let model =
+                RenderMatrices::translation(rng.range(-50.0, 50.0), 0.0, rng.range(-50.0, 50.0))
+                    * rng.quaternion().to_matrix()
+                    * RenderMatrices::scale(
+                        rng.range(0.1, 10.0),
+                        rng.range(0.1, 10.0),
+                        rng.range(0.1, 10.0),
+                    );
+            let normal_matrix = RenderMatrices::normal_matrix(&model).unwrap();
+            // A surface through the origin with two tangents and their normal
+            let (a, b) = (rng.vec3(10.0), rng.vec3(10.0));
+            let normal = a.cross(&b);
+            if normal.norm() < 0.01 {
+                continue;
+            }
+            let direction =
+                |v: Vec3, matrix: &Mat4| (*matrix * Vec4::new(v.x(), v.y(), v.z(), 0.0)).xyz();
+            let transformed = direction(normal, &normal_matrix).normalize();
+            for tangent in [a, b] {
+                let tangent = direction(tangent, &model).normalize();
+                assert!(
+                    transformed.dot(&tangent).abs() < 0.001,
+                    "{:?} {:?} {:?}",
+                    model,
+                    a,
+                    b
+                );
+            }
+        }
+        // Rotations are their own normal matrix
+        let rotation = RenderMatrices::rotation_3d(0.3, -1.2, 2.0, None);
+        assert_matrix_eq(
+            &RenderMatrices::normal_matrix(&rotation).unwrap(),
+            &rotation,
+        );
     }
 }
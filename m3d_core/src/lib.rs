@@ -1,36 +1,48 @@
 #![allow(dead_code)]
+// The wasm atomic wait/notify intrinsics are still unstable
+#![cfg_attr(
+    all(target_arch = "wasm32", feature = "threads"),
+    feature(stdarch_wasm_atomic_wait)
+)]
 
+mod error;
 mod etc;
 pub mod player;
 pub mod rendering;
 pub mod world;
 
+pub use error::{Error, Result};
 pub use etc::camera;
+pub use etc::camerapath;
+pub use etc::cli;
+pub use etc::imagefile;
 pub use etc::rendermath;
 
+use camerapath::{CameraPath, CameraPathPlayback};
+use rendering::debug::DebugView;
 use rendering::gfx::{Color, FarLight, Light, NearLight};
-use rendermath::{Point3D, Vector};
-use world::{Block, BlockData, Material, Shape, World};
+use rendering::postprocess::{Effect, FogColor, Lut3D, PostProcess};
+use rendering::sky::Sky;
+use rendering::stats::{self, Clock, FrameStats};
+use rendermath::{Point3D, Vec3};
+use world::{daycycle, Block, BlockData, DayCycle, Entities, Material, Shape, World, WorldData};
 
 pub struct GameState {
     last_frame: usize,
     renderer: rendering::Renderer,
     world: World,
+    camera_path: Option<CameraPathPlayback>,
+    day_cycle: DayCycle,
+    // Whether the day cycle repaints the sky, set_sky turns it off
+    sky_from_day_cycle: bool,
+    clock: Option<Clock>,
 }
 impl GameState {
-    pub fn new(width: usize, height: usize, texture_array: &[u8]) -> Self {
-        let game_camera = camera::Camera::new(
-            Point3D::from_euc_coords(0, 0, 0),
-            (0.0, 0.0),
-            std::f32::consts::PI * (135.0 / 180.0),
-            100,
-            width,
-            height,
-        );
-        let mut world = World::new(game_camera, 10);
+    pub fn new(width: usize, height: usize, texture_array: &[u8]) -> Result<Self> {
+        let mut world_data = WorldData::new(10);
         for indx in 0..150 {
             for indz in 0..150 {
-                world.data.set(
+                world_data.set(
                     indx,
                     1,
                     indz,
@@ -42,10 +54,10 @@ impl GameState {
                             Material::Grass
                         },
                     }),
-                );
+                )?;
             }
         }
-        world.data.lights = vec![
+        world_data.lights = vec![
             Light::Near(NearLight::new(
                 Color::new(255, 0, 0, 255),
                 3500,
@@ -56,17 +68,84 @@ impl GameState {
                 3000,
                 Point3D::from_euc_coords_float(50.0, 50.0, -10.0),
             )),
-            Light::Far(FarLight::new(
-                Color::new(255, 255, 255, 255),
-                120,
-                Vector::with_data(vec![0.0, 1.0, 0.0]),
-            )),
         ];
-        Self {
-            renderer: rendering::Renderer::new(width, height, texture_array),
+        Self::with_world(width, height, texture_array, world_data)
+    }
+    // Any world, e.g. one from worldfile::import. The sun and moon are added after its lights
+    pub fn with_world(
+        width: usize,
+        height: usize,
+        texture_array: &[u8],
+        mut world_data: WorldData,
+    ) -> Result<Self> {
+        // Checked first, the camera can't handle an empty frame
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidSize { width, height });
+        }
+        let game_camera = camera::Camera::new(
+            Point3D::from_euc_coords(0, 0, 0),
+            (0.0, 0.0),
+            std::f32::consts::PI * (135.0 / 180.0),
+            100,
+            width,
+            height,
+        );
+        // Sun and moon, day_cycle sets their color and direction every frame
+        let sun_light = world_data.lights.len();
+        world_data.lights.push(Light::Far(FarLight::new(
+            Color::new(255, 255, 255, 255),
+            120,
+            Vec3::new(0.0, 1.0, 0.0),
+        )));
+        world_data.lights.push(Light::Far(FarLight::new(
+            Color::new(0, 0, 0, 255),
+            0,
+            Vec3::new(0.0, -1.0, 0.0),
+        )));
+        let world = World {
+            camera: game_camera,
+            data: world_data,
+        };
+        let day_cycle = DayCycle::new(12.0, daycycle::DEFAULT_DAY_LENGTH, sun_light, sun_light + 1);
+        let mut renderer = rendering::Renderer::new(width, height, texture_array)?;
+        for material in Material::ALL {
+            for face in material.data().textures.iter().flatten() {
+                if face.id as usize >= renderer.textures.len() {
+                    return Err(Error::MissingTexture {
+                        id: face.id,
+                        loaded: renderer.textures.len(),
+                    });
+                }
+            }
+        }
+        renderer.set_sky(day_cycle.sky());
+        // Fog fades the edge of the render distance into the sky. Everything else starts off
+        // so frames look the same until a stage is turned on
+        renderer.add_post_process(PostProcess::new(Effect::Fog {
+            color: FogColor::Sky,
+            start: 0.7,
+            end: 1.0,
+        }));
+        for effect in [
+            Effect::Tonemap { exposure: 1.5 },
+            Effect::ColorGrade(Lut3D::identity(17)),
+            Effect::Gamma(2.2),
+            Effect::Vignette {
+                strength: 0.5,
+                radius: 0.5,
+            },
+        ] {
+            renderer.add_post_process(PostProcess::disabled(effect));
+        }
+        Ok(Self {
+            renderer,
             world,
             last_frame: 0,
-        }
+            camera_path: None,
+            day_cycle,
+            sky_from_day_cycle: true,
+            clock: stats::default_clock(),
+        })
     }
     pub fn get_pixels(&self) -> &[u8] {
         self.renderer.get_pixels()
@@ -75,16 +154,154 @@ impl GameState {
         self.renderer.get_mut_pixels()
     }
     pub fn render_world(&mut self, _curr_time: usize) {
+        let start = self.now();
         self.renderer.clear();
+        self.renderer.draw_sky(&self.world.camera.data);
+        let sky = self.now();
         World::draw_all(&self.world.data, &mut self.world.camera, &mut self.renderer);
+        let geometry = self.now();
+        self.renderer.flush();
+        let raster = self.now();
+        self.renderer.resolve();
+        let end = self.now();
+
+        let timings = &mut self.renderer.stats_mut().timings;
+        timings.sky = sky - start;
+        timings.geometry = geometry - sky;
+        timings.raster = raster - geometry;
+        timings.post = end - raster;
+        timings.total = end - start;
+    }
+    fn now(&self) -> f64 {
+        self.clock.map_or(0.0, |clock| clock())
+    }
+    // Timings are only filled in with a clock, which native builds have by default
+    pub fn set_clock(&mut self, clock: Option<Clock>) {
+        self.clock = clock;
+    }
+    pub fn frame_stats(&self) -> &FrameStats {
+        self.renderer.stats()
+    }
+    pub fn set_occlusion_culling(&mut self, occlusion_culling: bool) {
+        self.renderer.set_occlusion_culling(occlusion_culling);
+    }
+    pub fn set_raster_mode(&mut self, raster_mode: rendering::raster::RasterMode) {
+        self.renderer.set_raster_mode(raster_mode);
+    }
+    pub fn set_lighting_quality(&mut self, lighting_quality: rendering::raster::LightingQuality) {
+        self.renderer.set_lighting_quality(lighting_quality);
+    }
+    pub fn set_anti_aliasing(&mut self, anti_aliasing: rendering::antialias::AntiAliasing) {
+        self.renderer.set_anti_aliasing(anti_aliasing);
+        self.world.camera.set_resolution(
+            self.renderer.output_width(),
+            self.renderer.output_height(),
+            anti_aliasing.render_scale(),
+        );
+    }
+    pub fn post_processes_mut(&mut self) -> &mut Vec<PostProcess> {
+        self.renderer.post_processes_mut()
+    }
+    pub fn set_post_process_enabled(&mut self, indx: usize, enabled: bool) -> bool {
+        self.renderer.set_post_process_enabled(indx, enabled)
+    }
+    pub fn move_post_process(&mut self, from: usize, to: usize) -> bool {
+        self.renderer.move_post_process(from, to)
+    }
+    // Stays until use_day_cycle_sky, the day cycle only moves the sun and moon after this
+    pub fn set_sky(&mut self, sky: Sky) {
+        self.sky_from_day_cycle = false;
+        self.renderer.set_sky(sky);
+    }
+    pub fn use_day_cycle_sky(&mut self) {
+        self.sky_from_day_cycle = true;
+        self.update_day_cycle();
+    }
+    pub fn set_debug_view(&mut self, debug_view: DebugView) {
+        self.renderer.set_debug_view(debug_view);
+    }
+    pub fn set_debug_overlays(&mut self, bundles: bool, lights: bool) {
+        self.renderer.set_debug_overlays(bundles, lights);
     }
     pub fn render(&mut self, curr_time: usize) {
+        let start = self.now();
+        if let Some(playback) = &self.camera_path {
+            playback.apply(curr_time, &mut self.world.camera);
+            if playback.is_finished(curr_time) {
+                self.camera_path = None;
+            }
+        }
+        self.day_cycle.advance(curr_time);
+        self.update_day_cycle();
+        self.world.data.entities.advance(curr_time);
+        let update = self.now() - start;
         self.render_world(curr_time);
+
+        let timings = &mut self.renderer.stats_mut().timings;
+        timings.update = update;
+        timings.total += update;
+    }
+    // Lighting is worked out from WorldData.lights every frame, so changing the lights here
+    // is all it takes for the world to pick up the new sun
+    fn update_day_cycle(&mut self) {
+        self.day_cycle.update_lights(&mut self.world.data.lights);
+        if self.sky_from_day_cycle {
+            self.renderer.set_sky(self.day_cycle.sky());
+        }
+    }
+    // Hours, 0 to 24
+    pub fn time_of_day(&self) -> f32 {
+        self.day_cycle.time()
+    }
+    pub fn set_time_of_day(&mut self, time: f32) {
+        self.day_cycle.set_time(time);
+        self.update_day_cycle();
+    }
+    // ms of clock time per day, 0 stops the clock
+    pub fn set_day_length(&mut self, day_length: usize) {
+        self.day_cycle.day_length = day_length;
+    }
+    pub fn entities(&self) -> &Entities {
+        &self.world.data.entities
+    }
+    pub fn entities_mut(&mut self) -> &mut Entities {
+        &mut self.world.data.entities
+    }
+    pub fn play_camera_path(&mut self, path: CameraPath, start_time: usize, looping: bool) {
+        self.camera_path = Some(CameraPathPlayback::new(path, start_time, looping));
+    }
+    pub fn stop_camera_path(&mut self) -> Option<CameraPath> {
+        self.camera_path.take().map(|playback| playback.path)
+    }
+    pub fn is_playing_camera_path(&self) -> bool {
+        self.camera_path.is_some()
+    }
+    pub fn record_camera_keyframe(&self, path: &mut CameraPath, curr_time: usize) {
+        path.record(curr_time, &self.world.camera);
     }
     pub fn translate_camera(&mut self, trans_x: isize, trans_y: isize, trans_z: isize) {
         self.world.camera.translate(trans_x, trans_y, trans_z);
     }
+    pub fn set_camera_position(&mut self, x: f32, y: f32, z: f32) {
+        self.world.camera.set_position(x, y, z);
+    }
+    pub fn set_camera_look(&mut self, rotation: f32, inclination: f32) {
+        self.world.camera.set_look(rotation, inclination);
+    }
+    // For picking, see Camera::unproject
+    pub fn unproject(&mut self, screen_x: f32, screen_y: f32, depth: f32) -> Option<Point3D> {
+        self.world.camera.unproject(screen_x, screen_y, depth)
+    }
     pub fn rotate_camera(&mut self, d_rotation: f32, d_inclination: f32) {
         self.world.camera.translate_look(d_rotation, d_inclination);
     }
+    pub fn roll_camera(&mut self, d_roll: f32) {
+        self.world.camera.roll(d_roll);
+    }
+    pub fn set_projection(&mut self, projection: camera::Projection) {
+        self.world.camera.set_projection(projection);
+    }
+    pub fn set_isometric(&mut self, extent: f32) {
+        self.world.camera.set_isometric(extent);
+    }
 }
@@ -0,0 +1,227 @@
+use m3d_core::camerapath::CameraPath;
+use m3d_core::cli::{self, parse_number, read};
+use m3d_core::imagefile;
+use m3d_core::rendering::raster::RasterMode;
+use m3d_core::world::worldfile;
+use m3d_core::GameState;
+use std::{cmp, fs};
+
+const USAGE: &str = "usage: m3d_render [options] <output>
+  --world <file>         world file to load, the default floor otherwise
+  --textures <file>      texture pack (images.txt)
+  --size <W>x<H>         frame size (640x320)
+  --position <x> <y> <z> camera position in world units
+  --look <rot> <incl>    camera rotation and inclination in radians
+  --path <file>          camera path, overrides --position and --look
+  --frames <n>           frames spread evenly over the path (1)
+  --time <hours>         time of day, 0 to 24 (12)
+  --threads <n>          render threads (all of them)
+  --format <png|ppm>     output format, from the extension otherwise
+The output name gets the frame number in place of a run of #s, or before the extension
+when rendering more than one frame without any";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Png,
+    Ppm,
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    world: Option<String>,
+    textures: String,
+    width: usize,
+    height: usize,
+    position: (f32, f32, f32),
+    look: (f32, f32),
+    path: Option<String>,
+    frames: usize,
+    time: f32,
+    threads: usize,
+    format: Option<Format>,
+    output: String,
+}
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut to_return = Self {
+            world: None,
+            textures: "images.txt".to_string(),
+            width: 640,
+            height: 320,
+            position: (0.0, 0.0, 0.0),
+            look: (0.0, 0.0),
+            path: None,
+            frames: 1,
+            time: 12.0,
+            threads: cli::default_threads(),
+            format: None,
+            output: String::new(),
+        };
+        let mut output = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| format!("{} is missing a value", name))
+            };
+            match arg.as_str() {
+                "--world" => to_return.world = Some(value(arg)?.clone()),
+                "--textures" => to_return.textures = value(arg)?.clone(),
+                "--size" => {
+                    (to_return.width, to_return.height) = cli::parse_size(arg, value(arg)?)?
+                }
+                "--position" => {
+                    to_return.position = (
+                        parse_number(arg, value(arg)?)?,
+                        parse_number(arg, value(arg)?)?,
+                        parse_number(arg, value(arg)?)?,
+                    )
+                }
+                "--look" => {
+                    to_return.look = (
+                        parse_number(arg, value(arg)?)?,
+                        parse_number(arg, value(arg)?)?,
+                    )
+                }
+                "--path" => to_return.path = Some(value(arg)?.clone()),
+                "--frames" => to_return.frames = parse_number(arg, value(arg)?)?,
+                "--time" => to_return.time = parse_number(arg, value(arg)?)?,
+                "--threads" => to_return.threads = parse_number(arg, value(arg)?)?,
+                "--format" => {
+                    to_return.format = Some(match value(arg)?.as_str() {
+                        "png" => Format::Png,
+                        "ppm" => Format::Ppm,
+                        other => return Err(format!("unknown format {}", other)),
+                    })
+                }
+                _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+                _ if output.is_none() => output = Some(arg.clone()),
+                _ => return Err(format!("more than one output given, {}", arg)),
+            }
+        }
+        to_return.output = output.ok_or("no output given")?;
+        cli::check_frames_and_threads(to_return.frames, to_return.threads)?;
+        Ok(to_return)
+    }
+    fn format(&self) -> Format {
+        self.format.unwrap_or(if self.output.ends_with(".ppm") {
+            Format::Ppm
+        } else {
+            Format::Png
+        })
+    }
+}
+
+// frame_###.png -> frame_007.png, and out.png -> out_0007.png when there's more than one
+fn frame_name(output: &str, frame: usize, frames: usize) -> String {
+    if let Some(end) = output.rfind('#') {
+        let start = output[..end].trim_end_matches('#').len();
+        let width = end + 1 - start;
+        return format!(
+            "{}{:0width$}{}",
+            &output[..start],
+            frame,
+            &output[end + 1..],
+            width = width
+        );
+    }
+    if frames == 1 {
+        return output.to_string();
+    }
+    let stem_end = match output.rfind('.') {
+        Some(dot) if !output[dot..].contains('/') => dot,
+        _ => output.len(),
+    };
+    format!(
+        "{}_{:04}{}",
+        &output[..stem_end],
+        frame,
+        &output[stem_end..]
+    )
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let texture_array = cli::load_textures(&options.textures)?;
+    let mut game_state = match &options.world {
+        Some(world) => {
+            let world_data =
+                worldfile::import(&read(world)?).map_err(|err| format!("{}: {}", world, err))?;
+            GameState::with_world(options.width, options.height, &texture_array, world_data)
+        }
+        None => GameState::new(options.width, options.height, &texture_array),
+    }
+    .map_err(|err| err.to_string())?;
+
+    game_state.set_raster_mode(RasterMode::Tiled {
+        threads: options.threads,
+    });
+    // Frames are stills, the clock doesn't move the sun between them
+    game_state.set_day_length(0);
+    game_state.set_time_of_day(options.time);
+    let (x, y, z) = options.position;
+    game_state.set_camera_position(x, y, z);
+    game_state.set_camera_look(options.look.0, options.look.1);
+
+    let duration = match &options.path {
+        Some(path) => {
+            let camera_path =
+                CameraPath::import(&read(path)?).ok_or(format!("{}: not a camera path", path))?;
+            let duration = camera_path.duration();
+            game_state.play_camera_path(camera_path, 0, false);
+            duration
+        }
+        None => 0,
+    };
+
+    let steps = cmp::max(1, options.frames - 1);
+    for frame in 0..options.frames {
+        game_state.render(frame * duration / steps);
+        let pixels = game_state.get_pixels();
+        let encoded = match options.format() {
+            Format::Png => imagefile::encode_png(pixels, options.width, options.height),
+            Format::Ppm => imagefile::encode_ppm(pixels, options.width, options.height),
+        };
+        let file_name = frame_name(&options.output, frame + 1, options.frames);
+        fs::write(&file_name, encoded).map_err(|err| format!("{}: {}", file_name, err))?;
+        println!("wrote {}", file_name);
+    }
+    Ok(())
+}
+
+pub fn main() {
+    let options = cli::parse_args(USAGE, true, Options::parse);
+    if let Err(err) = run(&options) {
+        cli::fail("m3d_render", &err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String> {
+        Options::parse(
+            &args
+                .split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn parses_options_and_names_frames() {
+        let options = parse("--size 320x160 --position 1 -2 3.5 --frames 4 out.ppm").unwrap();
+        assert_eq!((options.width, options.height), (320, 160));
+        assert_eq!(options.position, (1.0, -2.0, 3.5));
+        assert_eq!(options.frames, 4);
+        assert_eq!(options.format(), Format::Ppm);
+        assert!(parse("--size 320 out.png").is_err());
+        assert!(parse("--frames 2").is_err());
+        assert!(parse("--bogus out.png").is_err());
+
+        assert_eq!(frame_name("out/frame_###.png", 7, 10), "out/frame_007.png");
+        assert_eq!(frame_name("out.png", 7, 10), "out_0007.png");
+        assert_eq!(frame_name("out.png", 1, 1), "out.png");
+        assert_eq!(frame_name("./frames/out", 2, 3), "./frames/out_0002");
+    }
+}
@@ -0,0 +1,257 @@
+pub const MAX_SUPERSAMPLE: usize = 4;
+
+// Below this much local contrast (in luma, 0 to 1) fxaa leaves the pixel alone
+const EDGE_THRESHOLD: f32 = 0.125;
+const EDGE_THRESHOLD_MIN: f32 = 0.0312;
+const SUBPIXEL_QUALITY: f32 = 0.75;
+const SEARCH_STEPS: isize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AntiAliasing {
+    Off,
+    // Renders at factor times the resolution on each axis, then averages each
+    // factor x factor block down to one pixel
+    Supersample(usize),
+    // Blurs along edges it finds in the finished frame, cheap but softer
+    Fxaa,
+}
+
+impl AntiAliasing {
+    pub fn render_scale(&self) -> usize {
+        match self {
+            Self::Supersample(factor) => (*factor).clamp(1, MAX_SUPERSAMPLE),
+            _ => 1,
+        }
+    }
+}
+
+// Box filter from a (width * factor) x (height * factor) RGBA frame into a width x height one
+pub fn downsample(source: &[u8], factor: usize, target: &mut [u8], width: usize, height: usize) {
+    let source_width = width * factor;
+    let samples = (factor * factor) as u32;
+    for y in 0..height {
+        for x in 0..width {
+            let mut acc = [0u32; 4];
+            for sample_y in (y * factor)..((y + 1) * factor) {
+                let row = 4 * (sample_y * source_width + x * factor);
+                for channel in source[row..row + 4 * factor].chunks_exact(4) {
+                    acc[0] += channel[0] as u32;
+                    acc[1] += channel[1] as u32;
+                    acc[2] += channel[2] as u32;
+                    acc[3] += channel[3] as u32;
+                }
+            }
+            let offset = 4 * (y * width + x);
+            for (target, acc) in target[offset..offset + 4].iter_mut().zip(acc.iter()) {
+                *target = ((acc + samples / 2) / samples) as u8;
+            }
+        }
+    }
+}
+
+struct Frame<'a> {
+    pixels: &'a [u8],
+    lumas: Vec<f32>,
+    width: isize,
+    height: isize,
+}
+impl<'a> Frame<'a> {
+    fn new(pixels: &'a [u8], width: usize, height: usize) -> Self {
+        let lumas = pixels
+            .chunks_exact(4)
+            .map(|pixel| {
+                (0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32)
+                    / 255.0
+            })
+            .collect();
+        Self {
+            pixels,
+            lumas,
+            width: width as isize,
+            height: height as isize,
+        }
+    }
+    // Clamped to the edge of the frame
+    fn luma(&self, x: isize, y: isize) -> f32 {
+        let x = x.clamp(0, self.width - 1);
+        let y = y.clamp(0, self.height - 1);
+        self.lumas[(y * self.width + x) as usize]
+    }
+    fn pixel(&self, x: isize, y: isize) -> &[u8] {
+        let x = x.clamp(0, self.width - 1);
+        let y = y.clamp(0, self.height - 1);
+        let offset = 4 * (y * self.width + x) as usize;
+        &self.pixels[offset..offset + 4]
+    }
+}
+
+// Roughly FXAA 3.11's quality preset: find edges by local luma contrast, walk along each edge
+// to find its ends, and blend towards the neighbour across the edge by how close the pixel is
+// to the middle of the edge
+pub fn fxaa(pixels: &[u8], width: usize, height: usize, output: &mut [u8]) {
+    let frame = Frame::new(pixels, width, height);
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let offset = 4 * (y as usize * width + x as usize);
+            let (blend, to_x, to_y) = fxaa_pixel(&frame, x, y);
+
+            let from = frame.pixel(x, y);
+            if blend <= 0.0 {
+                output[offset..offset + 4].copy_from_slice(from);
+                continue;
+            }
+            let to = frame.pixel(to_x, to_y);
+            for channel in 0..4 {
+                output[offset + channel] = (from[channel] as f32
+                    + (to[channel] as f32 - from[channel] as f32) * blend)
+                    as u8;
+            }
+        }
+    }
+}
+
+// How much to blend the pixel at (x, y) with the returned neighbour
+fn fxaa_pixel(frame: &Frame, x: isize, y: isize) -> (f32, isize, isize) {
+    let luma_m = frame.luma(x, y);
+    let luma_n = frame.luma(x, y - 1);
+    let luma_s = frame.luma(x, y + 1);
+    let luma_w = frame.luma(x - 1, y);
+    let luma_e = frame.luma(x + 1, y);
+
+    let luma_min = luma_m.min(luma_n).min(luma_s).min(luma_w).min(luma_e);
+    let luma_max = luma_m.max(luma_n).max(luma_s).max(luma_w).max(luma_e);
+    let range = luma_max - luma_min;
+    if range < EDGE_THRESHOLD_MIN.max(luma_max * EDGE_THRESHOLD) {
+        return (0.0, x, y);
+    }
+
+    let luma_nw = frame.luma(x - 1, y - 1);
+    let luma_ne = frame.luma(x + 1, y - 1);
+    let luma_sw = frame.luma(x - 1, y + 1);
+    let luma_se = frame.luma(x + 1, y + 1);
+
+    // Lone pixels (and thin lines) get blended with the average of their neighbourhood
+    let luma_average =
+        (2.0 * (luma_n + luma_s + luma_w + luma_e) + luma_nw + luma_ne + luma_sw + luma_se) / 12.0;
+    let subpixel = ((luma_average - luma_m).abs() / range).clamp(0.0, 1.0);
+    let subpixel = subpixel * subpixel * (3.0 - 2.0 * subpixel);
+    let subpixel_blend = subpixel * subpixel * SUBPIXEL_QUALITY;
+
+    let edge_horizontal = (luma_nw - 2.0 * luma_w + luma_sw).abs()
+        + 2.0 * (luma_n - 2.0 * luma_m + luma_s).abs()
+        + (luma_ne - 2.0 * luma_e + luma_se).abs();
+    let edge_vertical = (luma_nw - 2.0 * luma_n + luma_ne).abs()
+        + 2.0 * (luma_w - 2.0 * luma_m + luma_e).abs()
+        + (luma_sw - 2.0 * luma_s + luma_se).abs();
+    let is_horizontal = edge_horizontal >= edge_vertical;
+
+    // normal points across the edge, towards the side with the bigger change. along runs
+    // down the edge
+    let (luma_1, luma_2) = if is_horizontal {
+        (luma_n, luma_s)
+    } else {
+        (luma_w, luma_e)
+    };
+    let gradient_1 = (luma_1 - luma_m).abs();
+    let gradient_2 = (luma_2 - luma_m).abs();
+    let (normal_sign, luma_across) = if gradient_1 >= gradient_2 {
+        (-1, luma_1)
+    } else {
+        (1, luma_2)
+    };
+    let gradient_scaled = 0.25 * gradient_1.max(gradient_2);
+    let luma_local_average = 0.5 * (luma_m + luma_across);
+    let (normal_x, normal_y, along_x, along_y) = if is_horizontal {
+        (0, normal_sign, 1, 0)
+    } else {
+        (normal_sign, 0, 0, 1)
+    };
+
+    // Edge luma halfway between the pixel and its neighbour across the edge, relative to
+    // the average at this pixel. Once it changes by a lot, the edge has ended
+    let edge_luma = |step: isize| {
+        let sample_x = x + step * along_x;
+        let sample_y = y + step * along_y;
+        0.5 * (frame.luma(sample_x, sample_y)
+            + frame.luma(sample_x + normal_x, sample_y + normal_y))
+            - luma_local_average
+    };
+    let search = |direction: isize| {
+        for step in 1..=SEARCH_STEPS {
+            let luma_end = edge_luma(step * direction);
+            if luma_end.abs() >= gradient_scaled {
+                return (step, luma_end);
+            }
+        }
+        (SEARCH_STEPS, edge_luma(SEARCH_STEPS * direction))
+    };
+    let (distance_1, luma_end_1) = search(-1);
+    let (distance_2, luma_end_2) = search(1);
+
+    let edge_length = (distance_1 + distance_2) as f32;
+    let (distance, luma_end) = if distance_1 < distance_2 {
+        (distance_1, luma_end_1)
+    } else {
+        (distance_2, luma_end_2)
+    };
+    let pixel_offset = 0.5 - distance as f32 / edge_length;
+
+    // Only blend if the pixel is on the side of the edge that the nearer end says it is
+    let is_center_smaller = luma_m < luma_local_average;
+    let edge_blend = if (luma_end < 0.0) != is_center_smaller {
+        pixel_offset
+    } else {
+        0.0
+    };
+
+    (edge_blend.max(subpixel_blend), x + normal_x, y + normal_y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(value: u8) -> [u8; 4] {
+        [value, value, value, 255]
+    }
+
+    #[test]
+    fn downsample_averages_blocks() {
+        let mut source = Vec::new();
+        for value in [0, 100, 20, 40, 200, 100, 60, 80] {
+            source.extend_from_slice(&gray(value));
+        }
+        // 4x2 frame down to 2x1
+        let mut target = vec![0; 8];
+        downsample(&source, 2, &mut target, 2, 1);
+        assert_eq!(target, [100, 100, 100, 255, 50, 50, 50, 255]);
+    }
+    #[test]
+    fn fxaa_smooths_stairs_only() {
+        // Black/white staircase edge on the left, flat gray on the right
+        let (width, height) = (16, 16);
+        let mut pixels = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let value = if x >= 8 {
+                    128
+                } else if x < y / 2 {
+                    0
+                } else {
+                    255
+                };
+                pixels.extend_from_slice(&gray(value));
+            }
+        }
+        let mut output = vec![0; pixels.len()];
+        fxaa(&pixels, width, height, &mut output);
+
+        assert!(pixels
+            .chunks(4 * width)
+            .zip(output.chunks(4 * width))
+            .all(|(before, after)| before[4 * 10..] == after[4 * 10..]));
+        assert!(output
+            .chunks(4)
+            .any(|pixel| pixel[0] != 0 && pixel[0] != 255 && pixel[0] != 128));
+    }
+}
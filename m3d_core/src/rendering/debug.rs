@@ -0,0 +1,88 @@
+use super::gfx::Color;
+use crate::rendermath::{Point3D, Vec3};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugView {
+    Off,
+    // Triangle edges only, no fill
+    Wireframe,
+    // Faces colored by their world space normal, no texture or lighting
+    Normals,
+    // z buffer as grayscale, white is near
+    Depth,
+    // Number of times each pixel was shaded, black -> blue -> green -> yellow -> red
+    Overdraw,
+}
+// Drawn on top of everything once the frame is finished, points are in screen space
+pub enum Overlay {
+    Line(Point3D, Point3D, Color),
+    Square(Point3D, isize, Color),
+}
+
+pub const BUNDLE_BOUNDS_COLOR: Color = Color::new(255, 0, 255, 255);
+pub const ENTITY_BOUNDS_COLOR: Color = Color::new(0, 255, 255, 255);
+pub const WIREFRAME_COLOR: Color = Color::new(0, 0, 0, 255);
+
+// Pairs of bundle_corners indices making up the edges of a box
+pub const BOX_EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+pub fn normal_color(normal: &Vec3) -> Color {
+    let channel = |value: f32| ((value * 0.5 + 0.5) * 255.0) as u8;
+    Color::new(
+        channel(normal.x()),
+        channel(normal.y()),
+        channel(normal.z()),
+        255,
+    )
+}
+
+pub fn depth_color(z: f32) -> Color {
+    if z > 1.0 {
+        return Color::new(0, 0, 0, 255);
+    }
+    // Most of the depth range is bunched up near 0, so spread it out a bit
+    let value = (255.0 * (1.0 - z.max(0.0).sqrt())) as u8;
+    Color::new(value, value, value, 255)
+}
+
+pub fn overdraw_color(count: u16) -> Color {
+    const STEPS: [Color; 5] = [
+        Color::new(0, 0, 0, 255),
+        Color::new(0, 0, 255, 255),
+        Color::new(0, 255, 0, 255),
+        Color::new(255, 255, 0, 255),
+        Color::new(255, 0, 0, 255),
+    ];
+    STEPS[std::cmp::min(count as usize, STEPS.len() - 1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_colors_saturate() {
+        assert_eq!(depth_color(0.0).r, 255);
+        assert!(depth_color(0.2).r > depth_color(0.8).r);
+        assert_eq!(depth_color(100000.0).r, 0);
+        assert_eq!(overdraw_color(0).r, 0);
+        assert_eq!(overdraw_color(4).r, 255);
+        assert_eq!(overdraw_color(400).r, 255);
+
+        let up = normal_color(&Vec3::new(0.0, -1.0, 0.0));
+        assert_eq!((up.r, up.g, up.b), (127, 0, 127));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::rendermath::{Point3D, Vector};
+use crate::rendermath::{Mat4, Point3D, Vec3, Vec4};
 use std::cmp;
 
 pub const TEXTURE_SIZE: isize = 128;
@@ -20,18 +20,29 @@ pub struct Color {
 pub struct Texture {
     data: Vec<Color>,
 }
-#[derive(Debug)]
+// How a texture is laid onto a cube face. flip mirrors it left to right first, then it's
+// turned clockwise, then squeezed into rect, which is (left, top, right, bottom) from 0 to 1
+// across the texture
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaceTexture {
+    pub id: u16,
+    pub quarter_turns: u8,
+    pub flip: bool,
+    pub rect: (f32, f32, f32, f32),
+}
+#[derive(Debug, Clone)]
 pub struct NearLight {
     color: Color,
     intensity: u32,
     pub position: Point3D,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FarLight {
     color: Color,
     intensity: u32,
-    pub direction: Vector,
+    pub direction: Vec3,
 }
+#[derive(Debug, Clone)]
 pub enum Light {
     Near(NearLight),
     Far(FarLight),
@@ -39,7 +50,7 @@ pub enum Light {
 
 impl Color {
     #[inline(always)]
-    pub fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
         Self { r, g, b, a }
     }
     pub fn zero() -> Self {
@@ -50,6 +61,16 @@ impl Color {
             a: 0,
         }
     }
+    // Keeps self's alpha
+    pub fn lerp(&self, other: Self, t: f32) -> Self {
+        let channel = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t + 0.5) as u8;
+        Self::new(
+            channel(self.r, other.r),
+            channel(self.g, other.g),
+            channel(self.b, other.b),
+            self.a,
+        )
+    }
     pub fn compose(&mut self, other: Self) {
         self.r = ((self.r as u32) * (other.r as u32) / 255) as u8;
         self.g = ((self.g as u32) * (other.g as u32) / 255) as u8;
@@ -60,43 +81,14 @@ impl Color {
         self.g = cmp::min(255, (self.g as u32) + (other.g as u32)) as u8;
         self.b = cmp::min(255, (self.b as u32) + (other.b as u32)) as u8;
     }
-    pub fn interp_barycentric(
-        params: &(f32, f32, f32),
-        u: f32,
-        v: f32,
-        w: f32,
-        z: f32,
-        c1: Self,
-        c2: Self,
-        c3: Self,
-    ) -> Self {
-        unsafe {
-            Self {
-                r: ((z
-                    * (c1.r as f32 * params.0 * u
-                        + c2.r as f32 * params.1 * v
-                        + c3.r as f32 * params.2 * w))
-                    .to_int_unchecked::<i32>()
-                    & 255) as u8,
-                g: ((z
-                    * (c1.g as f32 * params.0 * u
-                        + c2.g as f32 * params.1 * v
-                        + c3.g as f32 * params.2 * w))
-                    .to_int_unchecked::<i32>()
-                    & 255) as u8,
-                b: ((z
-                    * (c1.b as f32 * params.0 * u
-                        + c2.b as f32 * params.1 * v
-                        + c3.b as f32 * params.2 * w))
-                    .to_int_unchecked::<i32>()
-                    & 255) as u8,
-                a: ((z
-                    * (c1.a as f32 * params.0 * u
-                        + c2.a as f32 * params.1 * v
-                        + c3.a as f32 * params.2 * w))
-                    .to_int_unchecked::<i32>()
-                    & 255) as u8,
-            }
+    // Float -> channel casts saturate, so lighting that overshoots 255 (or a pixel just
+    // outside the triangle going slightly negative) stays bright/dark instead of wrapping
+    pub fn from_f32(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self {
+            r: r as u8,
+            g: g as u8,
+            b: b as u8,
+            a: a as u8,
         }
     }
 }
@@ -137,6 +129,59 @@ impl Texture {
         }
     }
 }
+impl Texture {
+    // Same as sample, but with TEXTURE_SIZE * v + u already worked out (F32x4::texel_indices)
+    #[inline(always)]
+    pub fn sample_index(&self, indx: i32) -> Color {
+        unsafe {
+            *self
+                .data
+                .get_unchecked((indx as isize & TEXTURE_LEN) as usize)
+        }
+    }
+}
+impl FaceTexture {
+    // The whole texture, the right way up
+    pub const fn new(id: u16) -> Self {
+        Self {
+            id,
+            quarter_turns: 0,
+            flip: false,
+            rect: (0.0, 0.0, 1.0, 1.0),
+        }
+    }
+    pub const fn turned(self, quarter_turns: u8) -> Self {
+        Self {
+            quarter_turns: (self.quarter_turns + quarter_turns) % 4,
+            ..self
+        }
+    }
+    pub const fn flipped(self) -> Self {
+        Self {
+            flip: !self.flip,
+            ..self
+        }
+    }
+    pub const fn sub_rect(self, left: f32, top: f32, right: f32, bottom: f32) -> Self {
+        Self {
+            rect: (left, top, right, bottom),
+            ..self
+        }
+    }
+    // Where on the texture (0 to 1) a point on the face (0 to 1, (0, 0) at the top left as
+    // seen from outside) lands
+    pub fn uv(&self, u: f32, v: f32) -> (f32, f32) {
+        let (u, v) = if self.flip { (1.0 - u, v) } else { (u, v) };
+        let (u, v) = match self.quarter_turns % 4 {
+            0 => (u, v),
+            1 => (v, 1.0 - u),
+            2 => (1.0 - u, 1.0 - v),
+            _ => (1.0 - v, u),
+        };
+        let (left, top, right, bottom) = self.rect;
+        (left + (right - left) * u, top + (bottom - top) * v)
+    }
+}
 impl NearLight {
     pub fn new(color: Color, intensity: u32, position: Point3D) -> Self {
         Self {
@@ -146,8 +191,8 @@ impl NearLight {
         }
     }
     // still violates conservation of energy but at least is pretty
-    pub fn intensity(&self, position: &Point3D, normal: &Vector, scale: usize) -> Color {
-        let light_vec = self.position.position.minus(&position.position);
+    pub fn intensity(&self, position: &Point3D, normal: &Vec3, scale: usize) -> Color {
+        let light_vec = self.position.xyz() - position.xyz();
         if light_vec.dot(normal) < 0.0 {
             return Color {
                 r: 0,
@@ -156,7 +201,7 @@ impl NearLight {
                 a: 255,
             };
         }
-        let mut light_distance = light_vec.scalar_mul(1.0 / (4.0 * scale as f32)).norm2();
+        let mut light_distance = (light_vec * (1.0 / (4.0 * scale as f32))).norm2();
         if light_distance < 0.01 {
             light_distance = 0.01;
         }
@@ -171,15 +216,22 @@ impl NearLight {
     }
 }
 impl FarLight {
-    pub fn new(color: Color, intensity: u32, direction: Vector) -> Self {
+    pub fn new(color: Color, intensity: u32, direction: Vec3) -> Self {
         Self {
             color,
             intensity,
-            direction: direction.scalar_mul(-1.0),
+            direction: -direction,
         }
     }
+    pub fn set_color(&mut self, color: Color, intensity: u32) {
+        self.color = color;
+        self.intensity = intensity;
+    }
+    pub fn set_direction(&mut self, direction: Vec3) {
+        self.direction = -direction;
+    }
     // still violates conservation of energy but at least is pretty
-    pub fn intensity(&self, normal: &Vector, _scale: usize) -> Color {
+    pub fn intensity(&self, normal: &Vec3, _scale: usize) -> Color {
         let light_vec = &self.direction;
         if light_vec.dot(normal) < 0.0 {
             return Color {
@@ -199,10 +251,35 @@ impl FarLight {
     }
 }
 impl Light {
-    pub fn intensity(&self, position: &Point3D, normal: &Vector, scale: usize) -> Color {
+    pub fn color(&self) -> Color {
+        match self {
+            Self::Near(near_light) => near_light.color,
+            Self::Far(far_light) => far_light.color,
+        }
+    }
+    pub fn intensity(&self, position: &Point3D, normal: &Vec3, scale: usize) -> Color {
         match self {
             Self::Near(near_light) => near_light.intensity(position, normal, scale),
             Self::Far(far_light) => far_light.intensity(normal, scale),
         }
     }
+    // Same light, moved by transform. Lighting only depends on distances and angles, so
+    // lighting view space points with view space lights gives the same answer as world space
+    pub fn transformed(&self, transform: &Mat4) -> Self {
+        match self {
+            Self::Near(near_light) => Self::Near(NearLight {
+                position: near_light.position.transform(transform),
+                ..near_light.clone()
+            }),
+            Self::Far(far_light) => {
+                let direction = far_light.direction;
+                Self::Far(FarLight {
+                    direction: (*transform
+                        * Vec4::new(direction.x(), direction.y(), direction.z(), 0.0))
+                    .xyz(),
+                    ..far_light.clone()
+                })
+            }
+        }
+    }
 }
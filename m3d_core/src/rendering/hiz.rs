@@ -0,0 +1,156 @@
+// Max-depth pyramid over the z buffer. Level 0 is half the screen resolution, each level
+// after that halves again. Each cell holds the farthest depth under it, so if something's
+// nearest point is behind every cell it covers, it's completely hidden
+pub struct DepthPyramid {
+    width: usize,
+    height: usize,
+    levels: Vec<DepthLevel>,
+    is_built: bool,
+}
+struct DepthLevel {
+    width: usize,
+    height: usize,
+    depths: Vec<f32>,
+}
+
+// Biggest number of cells per axis looked at by is_occluded
+const MAX_CELLS: usize = 4;
+
+impl DepthPyramid {
+    pub fn new(width: usize, height: usize) -> Self {
+        let mut levels = Vec::new();
+        let (mut level_width, mut level_height) = (width, height);
+        while level_width > 1 || level_height > 1 {
+            level_width = level_width.div_ceil(2);
+            level_height = level_height.div_ceil(2);
+            levels.push(DepthLevel {
+                width: level_width,
+                height: level_height,
+                depths: vec![0.0; level_width * level_height],
+            });
+        }
+        Self {
+            width,
+            height,
+            levels,
+            is_built: false,
+        }
+    }
+    pub fn is_built(&self) -> bool {
+        self.is_built
+    }
+    pub fn invalidate(&mut self) {
+        self.is_built = false;
+    }
+    pub fn build(&mut self, z_buffer: &[f32]) {
+        for indx in 0..self.levels.len() {
+            let (below, rest) = self.levels.split_at_mut(indx);
+            let level = &mut rest[0];
+            match below.last() {
+                Some(previous) => {
+                    Self::downsample(&previous.depths, previous.width, previous.height, level)
+                }
+                None => Self::downsample(z_buffer, self.width, self.height, level),
+            }
+        }
+        self.is_built = !self.levels.is_empty();
+    }
+    fn downsample(source: &[f32], width: usize, height: usize, target: &mut DepthLevel) {
+        for indy in 0..target.height {
+            let y1 = 2 * indy;
+            let y2 = std::cmp::min(y1 + 1, height - 1);
+            for indx in 0..target.width {
+                let x1 = 2 * indx;
+                let x2 = std::cmp::min(x1 + 1, width - 1);
+                target.depths[indy * target.width + indx] = source[y1 * width + x1]
+                    .max(source[y1 * width + x2])
+                    .max(source[y2 * width + x1])
+                    .max(source[y2 * width + x2]);
+            }
+        }
+    }
+    // Screen rect is in pixels and inclusive, min_z uses the same mapping as the z buffer.
+    // Always false if the pyramid hasn't been built
+    pub fn is_occluded(&self, min_x: f32, min_y: f32, max_x: f32, max_y: f32, min_z: f32) -> bool {
+        if !self.is_built {
+            return false;
+        }
+        if max_x < 0.0 || max_y < 0.0 || min_x >= self.width as f32 || min_y >= self.height as f32 {
+            return false;
+        }
+        let x1 = min_x.max(0.0) as usize;
+        let y1 = min_y.max(0.0) as usize;
+        let x2 = std::cmp::min(max_x as usize, self.width - 1);
+        let y2 = std::cmp::min(max_y as usize, self.height - 1);
+
+        // Finest level where the rect only touches a handful of cells
+        let mut level_indx = 0;
+        while level_indx + 1 < self.levels.len()
+            && ((x2 >> (level_indx + 1)) - (x1 >> (level_indx + 1)) >= MAX_CELLS
+                || (y2 >> (level_indx + 1)) - (y1 >> (level_indx + 1)) >= MAX_CELLS)
+        {
+            level_indx += 1;
+        }
+        let level = &self.levels[level_indx];
+        let shift = level_indx + 1;
+
+        for cell_y in (y1 >> shift)..=(y2 >> shift) {
+            for cell_x in (x1 >> shift)..=(x2 >> shift) {
+                if level.depths[cell_y * level.width + cell_x] >= min_z {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_keep_the_farthest_depth() {
+        // 3x3, so the last row and column only have themselves to pair with
+        #[rustfmt::skip]
+        let z_buffer = [
+            0.1, 0.2, 0.3,
+            0.4, 0.9, 0.5,
+            0.6, 0.7, 0.8,
+        ];
+        let mut pyramid = DepthPyramid::new(3, 3);
+        assert!(!pyramid.is_built());
+        pyramid.build(&z_buffer);
+        assert!(pyramid.is_built());
+
+        assert_eq!(pyramid.levels.len(), 2);
+        assert_eq!(pyramid.levels[0].depths, vec![0.9, 0.5, 0.7, 0.8]);
+        assert_eq!(pyramid.levels[1].depths, vec![0.9]);
+        pyramid.invalidate();
+        assert!(!pyramid.is_built());
+    }
+
+    #[test]
+    fn only_rects_behind_everything_they_cover_are_occluded() {
+        let (width, height) = (64, 32);
+        let mut z_buffer = vec![0.5; width * height];
+        // A hole through the wall
+        z_buffer[10 * width + 40] = 1.0;
+        let mut pyramid = DepthPyramid::new(width, height);
+        assert!(!pyramid.is_occluded(0.0, 0.0, 10.0, 10.0, 0.9));
+        pyramid.build(&z_buffer);
+
+        assert!(pyramid.is_occluded(0.0, 0.0, 10.0, 10.0, 0.9));
+        // In front of the wall
+        assert!(!pyramid.is_occluded(0.0, 0.0, 10.0, 10.0, 0.4));
+        // Big enough to be looked up on a coarser level, and still fully behind
+        assert!(pyramid.is_occluded(0.0, 0.0, 30.0, 31.0, 0.9));
+        // Covers the hole
+        assert!(!pyramid.is_occluded(36.0, 8.0, 44.0, 12.0, 0.9));
+        assert!(!pyramid.is_occluded(0.0, 0.0, 63.0, 31.0, 0.9));
+        // Hanging off the screen is fine, being completely off it isn't occluded
+        assert!(pyramid.is_occluded(-20.0, -20.0, 5.0, 5.0, 0.9));
+        assert!(!pyramid.is_occluded(70.0, 0.0, 80.0, 10.0, 0.9));
+        assert!(!pyramid.is_occluded(-20.0, 0.0, -1.0, 10.0, 0.9));
+    }
+}
@@ -0,0 +1,400 @@
+use crate::error::{Error, Result};
+use crate::rendermath::{Mat4, Point3D, Vec3};
+use std::collections::HashMap;
+
+// Triangles that share a texture, for anything that isn't a block. uvs and normals go with
+// positions, uvs run 0 to 1 across the texture with (0, 0) at the top left. A mesh without
+// normals is lit with each triangle's own, one without uvs gets the top left texel. Triangles
+// are front facing when (b - a) x (c - a) points out of the mesh, the same as
+// RenderMatrices::triface_normal
+#[derive(Clone, Debug)]
+pub struct Mesh {
+    pub positions: Vec<Vec3>,
+    pub uvs: Vec<(f32, f32)>,
+    pub normals: Vec<Vec3>,
+    pub triangles: Vec<[usize; 3]>,
+    pub texture: u16,
+}
+
+// A mesh placed in the world that doesn't move. transform takes the mesh's own coordinates
+// into the world, and can scale as well as turn
+#[derive(Clone, Debug)]
+pub struct MeshInstance {
+    pub mesh: usize, // index into Entities::meshes
+    pub transform: Mat4,
+}
+
+impl Mesh {
+    pub fn new(texture: u16) -> Self {
+        Self {
+            positions: Vec::new(),
+            uvs: Vec::new(),
+            normals: Vec::new(),
+            triangles: Vec::new(),
+            texture,
+        }
+    }
+    // Box centered on the origin with the whole texture on every side
+    pub fn cuboid(half_extents: Vec3, texture: u16) -> Self {
+        let mut to_return = Self::new(texture);
+        let scaled = |v: Vec3| {
+            Vec3::new(
+                v.x() * half_extents.x(),
+                v.y() * half_extents.y(),
+                v.z() * half_extents.z(),
+            )
+        };
+        // (outward, down on the texture), the top and bottom just pick a way round
+        let sides = [
+            (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+            (Vec3::new(-1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+            (Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0)),
+            (Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0)),
+            (Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)),
+            (Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)),
+        ];
+        for (outward, down) in sides {
+            // Right as seen from outside, looking back along -outward
+            let right = down.cross(&-outward);
+            let (center, right, down) = (scaled(outward), scaled(right), scaled(down));
+            to_return.add_face(
+                [
+                    center - right - down,
+                    center + right - down,
+                    center + right + down,
+                    center - right + down,
+                ],
+                outward,
+            );
+        }
+        to_return
+    }
+    // Flat rectangle on the x-y plane facing -z, so towards a camera that hasn't been turned.
+    // Only the front is drawn
+    pub fn quad(half_width: f32, half_height: f32, texture: u16) -> Self {
+        let mut to_return = Self::new(texture);
+        to_return.add_face(
+            [
+                Vec3::new(-half_width, -half_height, 0.0),
+                Vec3::new(half_width, -half_height, 0.0),
+                Vec3::new(half_width, half_height, 0.0),
+                Vec3::new(-half_width, half_height, 0.0),
+            ],
+            Vec3::new(0.0, 0.0, -1.0),
+        );
+        to_return
+    }
+    // corners go top left, top right, bottom right, bottom left as seen from the front
+    fn add_face(&mut self, corners: [Vec3; 4], outward: Vec3) {
+        let start = self.positions.len();
+        self.positions.extend_from_slice(&corners);
+        self.uvs
+            .extend_from_slice(&[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+        self.normals.extend_from_slice(&[outward.normalize(); 4]);
+        for [a, b, c] in [[0, 1, 2], [0, 2, 3]] {
+            let (a, b, c) = (start + a, start + b, start + c);
+            let normal = (corners[b - start] - corners[a - start])
+                .cross(&(corners[c - start] - corners[a - start]));
+            self.triangles.push(if normal.dot(&outward) < 0.0 {
+                [a, c, b]
+            } else {
+                [a, b, c]
+            });
+        }
+    }
+    // Wavefront OBJ, only v, vt, vn and f lines are used and the rest (groups, materials,
+    // smoothing) is skipped. Faces with more than 3 corners are split into a fan, indices
+    // can be negative to count back from the latest. OBJ is y up with vt going up the
+    // texture, both get flipped here so models stand upright in the world, which turns the
+    // winding round too. Corners without a normal get their triangle's, triangles with no area
+    // are left out
+    pub fn from_obj(input: &str, texture: u16) -> Result<Self> {
+        let mut to_return = Self::new(texture);
+        let mut positions = Vec::new();
+        let mut uvs = Vec::new();
+        let mut normals = Vec::new();
+        // Corners that have been added already, so vertices shared between faces are too
+        let mut added: HashMap<(usize, Option<usize>, usize), usize> = HashMap::new();
+
+        for (indx, text) in input.lines().enumerate() {
+            let line = indx + 1;
+            let fields = text
+                .split('#')
+                .next()
+                .unwrap_or("")
+                .split_whitespace()
+                .collect::<Vec<&str>>();
+            let (command, args) = match fields.split_first() {
+                Some(split) => split,
+                None => continue,
+            };
+            match *command {
+                "v" => {
+                    let values = parse_floats(line, args, 3)?;
+                    positions.push(Vec3::new(values[0], -values[1], values[2]));
+                }
+                "vt" => {
+                    let values = parse_floats(line, args, 1)?;
+                    // Textures don't tile, so anything off the edge is clamped back onto it
+                    let v = 1.0 - values.get(1).unwrap_or(&0.0);
+                    uvs.push((values[0].clamp(0.0, 1.0), v.clamp(0.0, 1.0)));
+                }
+                "vn" => {
+                    let values = parse_floats(line, args, 3)?;
+                    let normal = Vec3::new(values[0], -values[1], values[2]);
+                    if !normal.norm().is_normal() {
+                        return Err(parse_error(line, "normal has no length"));
+                    }
+                    normals.push(normal.normalize());
+                }
+                "f" => {
+                    if args.len() < 3 {
+                        return Err(parse_error(line, "faces need at least 3 corners"));
+                    }
+                    let corners = args
+                        .iter()
+                        .map(|corner| {
+                            parse_corner(line, corner, positions.len(), uvs.len(), normals.len())
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    for second in 1..corners.len() - 1 {
+                        // Backwards because of the flip
+                        let triangle = [corners[0], corners[second + 1], corners[second]];
+                        let [a, b, c] = triangle.map(|(position, _, _)| positions[position]);
+                        let face_normal = (b - a).cross(&(c - a));
+                        // Corners in a line or on top of each other cover nothing and have
+                        // no normal to light them with
+                        if !face_normal.norm().is_normal() {
+                            continue;
+                        }
+                        let face_normal = face_normal.normalize();
+                        let indices = triangle.map(|(position, uv, normal)| {
+                            // Ones using the face normal can't be shared
+                            let key = normal.map(|normal| (position, uv, normal));
+                            if let Some(&indx) = key.and_then(|key| added.get(&key)) {
+                                return indx;
+                            }
+                            to_return.positions.push(positions[position]);
+                            to_return.uvs.push(uv.map_or((0.0, 0.0), |uv| uvs[uv]));
+                            to_return
+                                .normals
+                                .push(normal.map_or(face_normal, |normal| normals[normal]));
+                            let indx = to_return.positions.len() - 1;
+                            if let Some(key) = key {
+                                added.insert(key, indx);
+                            }
+                            indx
+                        });
+                        to_return.triangles.push(indices);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(to_return)
+    }
+    // Smallest and largest corners of the box around every position
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        let mut min = Vec3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Vec3::new(f32::MIN, f32::MIN, f32::MIN);
+        for position in self.positions.iter() {
+            for indx in 0..3 {
+                min.set(indx, min.get(indx).min(position.get(indx)));
+                max.set(indx, max.get(indx).max(position.get(indx)));
+            }
+        }
+        (min, max)
+    }
+}
+
+impl MeshInstance {
+    pub fn new(mesh: usize, transform: Mat4) -> Self {
+        Self { mesh, transform }
+    }
+    // The mesh's bounds moved by transform, in the same order as World::bundle_corners
+    pub fn bounding_corners(&self, mesh: &Mesh) -> [Point3D; 8] {
+        let (min, max) = mesh.bounds();
+        let mut to_return = [Point3D::from_euc_coords(0, 0, 0); 8];
+        for (indx, corner) in to_return.iter_mut().enumerate() {
+            let pick = |bit: usize, axis: usize| {
+                if indx & bit == 0 {
+                    min.get(axis)
+                } else {
+                    max.get(axis)
+                }
+            };
+            *corner = Point3D::from_vec3(Vec3::new(pick(1, 0), pick(2, 1), pick(4, 2)))
+                .transform(&self.transform);
+        }
+        to_return
+    }
+}
+
+fn parse_error(line: usize, reason: &'static str) -> Error {
+    Error::MeshParse { line, reason }
+}
+// At least count of them, anything past that is left to the caller
+fn parse_floats(line: usize, args: &[&str], count: usize) -> Result<Vec<f32>> {
+    if args.len() < count {
+        return Err(parse_error(line, "too few values"));
+    }
+    args.iter()
+        .map(|arg| {
+            arg.parse::<f32>()
+                .map_err(|_| parse_error(line, "couldn't parse a number"))
+        })
+        .collect()
+}
+// position/uv/normal with uv and normal optional, as indices from 0
+fn parse_corner(
+    line: usize,
+    corner: &str,
+    positions: usize,
+    uvs: usize,
+    normals: usize,
+) -> Result<(usize, Option<usize>, Option<usize>)> {
+    let mut parts = corner.split('/');
+    let mut next_index = |count: usize| -> Result<Option<usize>> {
+        let part = match parts.next() {
+            Some(part) if !part.is_empty() => part,
+            _ => return Ok(None),
+        };
+        let index = part
+            .parse::<isize>()
+            .map_err(|_| parse_error(line, "couldn't parse an index"))?;
+        let resolved = if index < 0 {
+            count as isize + index
+        } else {
+            index - 1
+        };
+        if resolved < 0 || resolved >= count as isize {
+            return Err(parse_error(line, "index out of range"));
+        }
+        Ok(Some(resolved as usize))
+    };
+    let position =
+        next_index(positions)?.ok_or_else(|| parse_error(line, "corner has no position"))?;
+    Ok((position, next_index(uvs)?, next_index(normals)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rendermath::{Point3D, RenderMatrices};
+
+    fn normals_and_centers(mesh: &Mesh) -> Vec<(Vec3, Vec3)> {
+        mesh.triangles
+            .iter()
+            .map(|triangle| {
+                let [a, b, c] = triangle.map(|indx| Point3D::from_vec3(mesh.positions[indx]));
+                (
+                    RenderMatrices::triface_normal(&a, &b, &c),
+                    RenderMatrices::triface_center(&a, &b, &c),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn built_meshes_face_outwards() {
+        let cuboid = Mesh::cuboid(Vec3::new(1.0, 2.0, 3.0), 0);
+        assert_eq!(cuboid.triangles.len(), 12);
+        assert_eq!(
+            cuboid.bounds(),
+            (Vec3::new(-1.0, -2.0, -3.0), Vec3::new(1.0, 2.0, 3.0))
+        );
+        // Every side of a box is off center in the direction it faces
+        for (normal, center) in normals_and_centers(&cuboid) {
+            assert!(normal.dot(&center) > 0.0, "{:?} faces inwards", normal);
+        }
+        for (normal, _) in normals_and_centers(&Mesh::quad(1.0, 1.0, 0)) {
+            assert!(normal.z() < 0.0);
+        }
+        // Vertex normals agree with the triangles they're on
+        for triangle in cuboid.triangles.iter() {
+            let (normal, _) = normals_and_centers(&Mesh {
+                triangles: vec![*triangle],
+                ..cuboid.clone()
+            })[0];
+            for &indx in triangle.iter() {
+                assert!((cuboid.normals[indx] - normal).norm() < 0.001);
+            }
+        }
+    }
+
+    #[test]
+    fn loads_obj_upright_and_facing_the_same_way() {
+        // A unit square facing +z in OBJ's y up coordinates, then its first triangle again
+        // without uvs or normals
+        let mesh = Mesh::from_obj(
+            "# square
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            vt 0 0
+            vt 1 0
+            vt 1 1
+            vt 0 1
+            vn 0 0 1
+            g square
+            f 1/1/1 2/2/1 3/3/1 4/4/1
+            f -4 -3 -2",
+            3,
+        )
+        .unwrap();
+        assert_eq!(mesh.texture, 3);
+        assert_eq!(mesh.triangles.len(), 3);
+        // The quad's corners are shared, the last triangle's aren't
+        assert_eq!(mesh.positions.len(), 4 + 3);
+        assert_eq!(
+            mesh.bounds(),
+            (Vec3::new(0.0, -1.0, 0.0), Vec3::new(1.0, 0.0, 0.0))
+        );
+        for (normal, _) in normals_and_centers(&mesh) {
+            assert!((normal - Vec3::new(0.0, 0.0, 1.0)).norm() < 0.001);
+        }
+        for (indx, position) in mesh.positions.iter().enumerate() {
+            assert!((mesh.normals[indx] - Vec3::new(0.0, 0.0, 1.0)).norm() < 0.001);
+            if indx < 4 {
+                // Bottom left of the texture is at the bottom, which is +y now
+                assert_eq!(mesh.uvs[indx], (position.x(), 1.0 + position.y()));
+            }
+        }
+
+        let error = |input: &str| Mesh::from_obj(input, 0).err();
+        assert_eq!(
+            error("v 0 0 0\nv 1 0 0\n\nf 1 2 3"),
+            Some(Error::MeshParse {
+                line: 4,
+                reason: "index out of range"
+            })
+        );
+        assert!(error("v 0 0\nf 1 1 1").is_some());
+        assert!(error("v 0 0 0\nf 1 1").is_some());
+        assert!(error("v 0 0 0\nf 0 1 1").is_some());
+        assert!(error("v 0 0 0\nf 1/x 1 1").is_some());
+        assert!(error("vn 0 0 0").is_some());
+
+        // Flat triangles are dropped rather than given a NaN normal
+        let flat =
+            Mesh::from_obj("v 0 0 0\nv 1 0 0\nv 2 0 0\nv 0 1 0\nf 1 2 3\nf 1 2 4", 0).unwrap();
+        assert_eq!(flat.triangles.len(), 1);
+        assert!(flat.normals.iter().all(|normal| normal.norm().is_finite()));
+    }
+
+    #[test]
+    fn instances_are_bounded_after_their_transform() {
+        let mesh = Mesh::cuboid(Vec3::new(1.0, 1.0, 1.0), 0);
+        let instance = MeshInstance::new(
+            0,
+            RenderMatrices::translation(10.0, 0.0, 0.0) * RenderMatrices::scale(2.0, 1.0, 1.0),
+        );
+        let corners = instance.bounding_corners(&mesh);
+        for (indx, corner) in corners.iter().enumerate() {
+            let expected_x = if indx & 1 == 0 { 8.0 } else { 12.0 };
+            assert!((corner.get(0) - expected_x).abs() < 0.001);
+            assert!((corner.get(1).abs() - 1.0).abs() < 0.001);
+        }
+    }
+}
@@ -1,21 +1,79 @@
-use std::cmp;
-
+pub mod antialias;
+pub mod debug;
 pub mod gfx;
+mod hiz;
+pub mod mesh;
 mod pixeliterator;
-
-use crate::camera::{CameraCache, CameraData};
-use crate::rendermath::{Matrix, Point3D, RenderMatrices, Vector};
-use gfx::{Color, Texture};
+pub mod postprocess;
+pub mod raster;
+mod simd;
+pub mod sky;
+pub mod stats;
+pub mod texturepack;
+#[cfg(all(target_arch = "wasm32", feature = "threads"))]
+pub mod workers;
+
+use crate::camera::{CameraCache, CameraData, Projection};
+use crate::error::{Error, Result};
+use crate::rendermath::{Mat4, Point3D, RenderMatrices, Vec3, Vec4};
+use antialias::AntiAliasing;
+use debug::{DebugView, Overlay};
+use gfx::{Color, FaceTexture, Light, Texture};
+use hiz::DepthPyramid;
+use mesh::Mesh;
 use pixeliterator::PixelIterator;
+use postprocess::PostProcess;
+use raster::{
+    FrameTarget, LightingQuality, RasterMode, RasterTarget, TileBins, TriangleEdges, TriangleSetup,
+};
+use sky::{Sky, SkyRays, SkyView};
+use stats::{FrameStats, RasterCounters};
 
 pub struct Renderer {
+    // Size of the frame being drawn into, bigger than the output when supersampling
     pub width: usize,
     pub height: usize,
+    output_width: usize,
+    output_height: usize,
     pixels: Vec<u8>,
     z_buffer: Vec<f32>,
+    raster_mode: RasterMode,
+    tile_bins: TileBins,
+    depth_pyramid: DepthPyramid,
+    occlusion_culling: bool,
+    lighting_quality: LightingQuality,
+    // Copy of the world's lights moved into view space, for per pixel lighting
+    view_lights: Vec<Light>,
+    anti_aliasing: AntiAliasing,
+    // Anti-aliased frame, unused when anti_aliasing is off
+    output: Vec<u8>,
+    // Run in order over the finished frame, before anti-aliasing
+    post_processes: Vec<PostProcess>,
+    sky: Sky,
+    // Set by draw_sky, for fogging towards the sky behind each pixel
+    sky_rays: Option<SkyRays>,
+    // World -> view space transform of the frame being drawn
+    view: Mat4,
+
+    debug_view: DebugView,
+    debug_bundles: bool, // bundle and entity bounding boxes
+    debug_lights: bool,
+    // Only allocated for DebugView::Overdraw
+    overdraw: Vec<u16>,
+    overlays: Vec<Overlay>,
+
+    stats: FrameStats,
 
     pub textures: Vec<Texture>,
 }
+// What draw_triface interpolates across a triangle, one per corner
+#[derive(Clone, Copy, Debug)]
+pub struct VertexAttributes {
+    pub light_color: Color,
+    pub texcoord: (f32, f32), // 0 to MTEXCOORD
+    // In view space, for per pixel lighting. None lights the corner with the face's own
+    pub normal: Option<Vec3>,
+}
 #[derive(Clone, Copy, Debug)]
 pub enum CubeFace {
     PlusX = 0,
@@ -25,14 +83,38 @@ pub enum CubeFace {
     MinusY = 4,
     MinusZ = 5,
 }
+impl CubeFace {
+    // (outward, down the texture) for laying textures on the face, the same as Mesh::cuboid.
+    // Side faces have the top of the texture at the top of the block, the top and bottom
+    // faces have it towards -z
+    pub fn texture_axes(self) -> (Vec3, Vec3) {
+        let down = Vec3::new(0.0, 1.0, 0.0);
+        let towards_z = Vec3::new(0.0, 0.0, 1.0);
+        match self {
+            Self::PlusX => (Vec3::new(1.0, 0.0, 0.0), down),
+            Self::PlusY => (Vec3::new(0.0, 1.0, 0.0), towards_z),
+            Self::PlusZ => (Vec3::new(0.0, 0.0, 1.0), down),
+            Self::MinusX => (Vec3::new(-1.0, 0.0, 0.0), down),
+            Self::MinusY => (Vec3::new(0.0, -1.0, 0.0), towards_z),
+            Self::MinusZ => (Vec3::new(0.0, 0.0, -1.0), down),
+        }
+    }
+}
 
 impl Renderer {
-    pub fn new(width: usize, height: usize, texture_array: &[u8]) -> Self {
+    pub fn new(width: usize, height: usize, texture_array: &[u8]) -> Result<Self> {
         let texture_slice_len = (4 * (gfx::TEXTURE_LEN + 1)) as usize;
 
         let mut textures: Vec<Texture> = Vec::new();
 
-        assert_eq!(texture_array.len() % texture_slice_len, 0);
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidSize { width, height });
+        }
+        if !texture_array.len().is_multiple_of(texture_slice_len) {
+            return Err(Error::TextureLength {
+                length: texture_array.len(),
+            });
+        }
 
         let mut acc: Vec<Color> = Vec::new();
         for pixel_indx in (0..texture_array.len()).step_by(4) {
@@ -48,23 +130,340 @@ impl Renderer {
             }
         }
 
-        Self {
+        Ok(Self {
             pixels: vec![0; 4 * width * height],
             z_buffer: vec![100000.0; width * height],
+            raster_mode: RasterMode::Immediate,
+            tile_bins: TileBins::new(width, height),
+            depth_pyramid: DepthPyramid::new(width, height),
+            occlusion_culling: true,
+            lighting_quality: LightingQuality::PerVertex,
+            view_lights: Vec::new(),
+            anti_aliasing: AntiAliasing::Off,
+            output: Vec::new(),
+            post_processes: Vec::new(),
+            sky: Sky::Solid(Color::new(255, 255, 255, 255)),
+            sky_rays: None,
+            view: RenderMatrices::identity(),
+            debug_view: DebugView::Off,
+            debug_bundles: false,
+            debug_lights: false,
+            overdraw: Vec::new(),
+            overlays: Vec::new(),
+            stats: FrameStats::default(),
             width,
             height,
+            output_width: width,
+            output_height: height,
             textures,
-        }
+        })
     }
     pub fn clear(&mut self) {
         self.pixels.fill(255);
         self.z_buffer.fill(100000.0);
+        self.depth_pyramid.invalidate();
+        self.overdraw.fill(0);
+        self.overlays.clear();
+        self.stats = FrameStats::default();
+    }
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+    pub fn stats_mut(&mut self) -> &mut FrameStats {
+        &mut self.stats
+    }
+    pub fn set_sky(&mut self, sky: Sky) {
+        self.sky = sky;
+    }
+    pub fn sky(&self) -> &Sky {
+        &self.sky
+    }
+    // Fills the whole frame with the sky, goes after clear and before any geometry
+    pub fn draw_sky(&mut self, camera_data: &CameraData) {
+        if let Sky::Solid(color) = self.sky {
+            self.sky_rays = None;
+            for pixel in self.pixels.chunks_exact_mut(4) {
+                pixel.copy_from_slice(&[color.r, color.g, color.b, color.a]);
+            }
+            return;
+        }
+
+        let sky_rays = SkyRays::new(camera_data);
+        for (indx, pixel) in self.pixels.chunks_exact_mut(4).enumerate() {
+            let direction = sky_rays.direction(indx % self.width, indx / self.width);
+            let color = self.sky.color(&direction, &self.textures);
+            pixel.copy_from_slice(&[color.r, color.g, color.b, color.a]);
+        }
+        self.sky_rays = Some(sky_rays);
+    }
+    pub fn set_debug_view(&mut self, debug_view: DebugView) {
+        self.flush();
+        self.debug_view = debug_view;
+        self.overdraw = match debug_view {
+            DebugView::Overdraw => vec![0; self.width * self.height],
+            _ => Vec::new(),
+        };
+    }
+    pub fn debug_view(&self) -> DebugView {
+        self.debug_view
+    }
+    pub fn set_debug_overlays(&mut self, bundles: bool, lights: bool) {
+        self.debug_bundles = bundles;
+        self.debug_lights = lights;
+    }
+    pub fn debug_bundles(&self) -> bool {
+        self.debug_bundles
+    }
+    pub fn debug_lights(&self) -> bool {
+        self.debug_lights
+    }
+    // Outline of a box given by the 8 corners in World::bundle_corners order. Skipped if
+    // any corner is in front of the near plane
+    pub fn write_box(
+        &mut self,
+        corners: &[Point3D; 8],
+        cache: &mut CameraCache,
+        camera_data: &CameraData,
+        color: Color,
+    ) {
+        let reverse_frustum =
+            *CameraCache::reverse_frustum(&mut cache.reverse_frustum, camera_data);
+        let to_screen_space = CameraCache::to_screen_space(&mut cache.to_screen_space, camera_data);
+
+        let mut projected = [Point3D::from_euc_coords(0, 0, 0); 8];
+        for (corner, target) in corners.iter().zip(projected.iter_mut()) {
+            let view = corner.transform(&reverse_frustum);
+            if view.z_coord_float() < camera_data.near {
+                return;
+            }
+            *target = view.transform(to_screen_space);
+        }
+        for (a, b) in debug::BOX_EDGES {
+            self.overlays
+                .push(Overlay::Line(projected[a], projected[b], color));
+        }
+    }
+    // Squares where the lights are, far lights are put halfway to the far plane in the
+    // direction the light comes from
+    pub fn write_light_markers(
+        &mut self,
+        lights: &[Light],
+        cache: &mut CameraCache,
+        camera_data: &CameraData,
+    ) {
+        let reverse_frustum =
+            *CameraCache::reverse_frustum(&mut cache.reverse_frustum, camera_data);
+        let to_screen_space = CameraCache::to_screen_space(&mut cache.to_screen_space, camera_data);
+
+        for light in lights {
+            let (view, size) = match light {
+                Light::Near(near_light) => (near_light.position.transform(&reverse_frustum), 8),
+                Light::Far(far_light) => {
+                    let towards = far_light.direction * (0.5 * camera_data.far);
+                    let rotated =
+                        reverse_frustum * Vec4::new(towards.x(), towards.y(), towards.z(), 0.0);
+                    (Point3D::from_vec3(rotated.xyz()), 16)
+                }
+            };
+            if view.z_coord_float() < camera_data.near {
+                continue;
+            }
+            self.overlays.push(Overlay::Square(
+                view.transform(to_screen_space),
+                size,
+                light.color(),
+            ));
+        }
+    }
+    fn draw_overlays(&mut self) {
+        if self.overlays.is_empty() {
+            return;
+        }
+        // Nothing needs the depth of the frame any more, clearing it puts overlays on top
+        self.z_buffer.fill(100000.0);
+        for overlay in std::mem::take(&mut self.overlays) {
+            match overlay {
+                Overlay::Line(p1, p2, color) => self.write_line(&p1, &p2, color),
+                Overlay::Square(p, sidelen, color) => self.write_square(&p, sidelen, color),
+            }
+        }
+    }
+    pub fn set_occlusion_culling(&mut self, occlusion_culling: bool) {
+        self.occlusion_culling = occlusion_culling;
+        self.depth_pyramid.invalidate();
+    }
+    // Snapshots the z buffer for is_occluded, anything drawn after this won't occlude
+    // until the next rebuild
+    pub fn build_depth_pyramid(&mut self) {
+        if !self.occlusion_culling {
+            return;
+        }
+        self.flush();
+        self.depth_pyramid.build(&self.z_buffer);
+    }
+    pub fn is_occluded(&self, bounds: (f32, f32, f32, f32, f32)) -> bool {
+        let (min_x, min_y, max_x, max_y, min_z) = bounds;
+        self.occlusion_culling
+            && self
+                .depth_pyramid
+                .is_occluded(min_x, min_y, max_x, max_y, min_z)
+    }
+    pub fn set_lighting_quality(&mut self, lighting_quality: LightingQuality) {
+        self.flush();
+        self.lighting_quality = lighting_quality;
+    }
+    pub fn lighting_quality(&self) -> LightingQuality {
+        self.lighting_quality
+    }
+    // view is the world -> view space transform the frame is being drawn with
+    pub fn set_lights(&mut self, lights: &[Light], view: &Mat4) {
+        self.flush();
+        self.view = *view;
+        self.view_lights.clear();
+        self.view_lights
+            .extend(lights.iter().map(|light| light.transformed(view)));
+    }
+    pub fn set_raster_mode(&mut self, raster_mode: RasterMode) {
+        self.flush();
+        self.raster_mode = raster_mode;
+    }
+    pub fn raster_mode(&self) -> RasterMode {
+        self.raster_mode
+    }
+    // Supersampling changes the size of the frame, so the camera has to be told about
+    // the new width and height afterwards
+    pub fn set_anti_aliasing(&mut self, anti_aliasing: AntiAliasing) {
+        self.flush();
+        self.anti_aliasing = anti_aliasing;
+
+        let render_scale = anti_aliasing.render_scale();
+        self.width = self.output_width * render_scale;
+        self.height = self.output_height * render_scale;
+        self.pixels = vec![0; 4 * self.width * self.height];
+        self.z_buffer = vec![100000.0; self.width * self.height];
+        self.tile_bins = TileBins::new(self.width, self.height);
+        self.depth_pyramid = DepthPyramid::new(self.width, self.height);
+        if !self.overdraw.is_empty() {
+            self.overdraw = vec![0; self.width * self.height];
+        }
+        self.output = match anti_aliasing {
+            AntiAliasing::Off => Vec::new(),
+            _ => vec![0; 4 * self.output_width * self.output_height],
+        };
+    }
+    pub fn anti_aliasing(&self) -> AntiAliasing {
+        self.anti_aliasing
+    }
+    pub fn output_width(&self) -> usize {
+        self.output_width
+    }
+    pub fn output_height(&self) -> usize {
+        self.output_height
+    }
+    pub fn post_processes(&self) -> &[PostProcess] {
+        &self.post_processes
+    }
+    pub fn post_processes_mut(&mut self) -> &mut Vec<PostProcess> {
+        &mut self.post_processes
     }
+    pub fn add_post_process(&mut self, post_process: PostProcess) -> usize {
+        self.post_processes.push(post_process);
+        self.post_processes.len() - 1
+    }
+    // Returns false if there's no stage at indx
+    pub fn set_post_process_enabled(&mut self, indx: usize, enabled: bool) -> bool {
+        match self.post_processes.get_mut(indx) {
+            Some(post_process) => {
+                post_process.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+    // Moves the stage at from so it runs at position to, shifting the ones in between
+    pub fn move_post_process(&mut self, from: usize, to: usize) -> bool {
+        if from >= self.post_processes.len() || to >= self.post_processes.len() {
+            return false;
+        }
+        let post_process = self.post_processes.remove(from);
+        self.post_processes.insert(to, post_process);
+        true
+    }
+    // Finishes the frame, after this get_pixels has the final image
+    pub fn resolve(&mut self) {
+        self.flush();
+        match self.debug_view {
+            DebugView::Depth => {
+                for (pixel, &z) in self.pixels.chunks_exact_mut(4).zip(self.z_buffer.iter()) {
+                    let color = debug::depth_color(z);
+                    pixel.copy_from_slice(&[color.r, color.g, color.b, color.a]);
+                }
+            }
+            DebugView::Overdraw => {
+                for (pixel, &count) in self.pixels.chunks_exact_mut(4).zip(self.overdraw.iter()) {
+                    let color = debug::overdraw_color(count);
+                    pixel.copy_from_slice(&[color.r, color.g, color.b, color.a]);
+                }
+            }
+            _ => {
+                let sky = SkyView {
+                    sky: &self.sky,
+                    rays: self.sky_rays.as_ref(),
+                    textures: &self.textures,
+                };
+                postprocess::apply(
+                    &self.post_processes,
+                    &mut self.pixels,
+                    &self.z_buffer,
+                    self.width,
+                    self.height,
+                    &sky,
+                );
+            }
+        }
+        self.draw_overlays();
+        match self.anti_aliasing {
+            AntiAliasing::Off => {}
+            AntiAliasing::Supersample(_) => antialias::downsample(
+                &self.pixels,
+                self.anti_aliasing.render_scale(),
+                &mut self.output,
+                self.output_width,
+                self.output_height,
+            ),
+            AntiAliasing::Fxaa => {
+                antialias::fxaa(&self.pixels, self.width, self.height, &mut self.output)
+            }
+        }
+    }
+    // Draws anything still waiting in the tile bins, has to be called before the frame is
+    // read back
+    pub fn flush(&mut self) {
+        if let RasterMode::Tiled { threads } = self.raster_mode {
+            let frame = FrameTarget {
+                width: self.width,
+                pixels: &mut self.pixels,
+                z_buffer: &mut self.z_buffer,
+                overdraw: &mut self.overdraw,
+            };
+            self.stats.raster +=
+                self.tile_bins
+                    .draw(threads, frame, &self.textures, &self.view_lights);
+            self.tile_bins.clear();
+        }
+    }
+    // The output frame, output_width x output_height
     pub fn get_pixels(&self) -> &[u8] {
-        &self.pixels
+        match self.anti_aliasing {
+            AntiAliasing::Off => &self.pixels,
+            _ => &self.output,
+        }
     }
     pub fn get_mut_pixels(&mut self) -> &mut [u8] {
-        &mut self.pixels
+        match self.anti_aliasing {
+            AntiAliasing::Off => &mut self.pixels,
+            _ => &mut self.output,
+        }
     }
     fn pixel_iterator(
         &self,
@@ -178,48 +577,43 @@ impl Renderer {
             }
         }
     }
+    // The view matrix only rotates and translates, so its transpose takes directions back
+    fn world_normal(&self, normal: &Vec3) -> Vec3 {
+        let n = [normal.x(), normal.y(), normal.z()];
+        let row = |i: usize| (0..3).map(|j| self.view.get(i, j) * n[j]).sum::<f32>();
+        Vec3::new(row(0), row(1), row(2)).normalize()
+    }
     fn barycentric_interp_params(z_a: f32, z_b: f32, z_c: f32) -> (f32, f32, f32) {
         (1.0 / z_a, 1.0 / z_b, 1.0 / z_c)
     }
-    #[inline(always)]
-    fn interp_barycentric_z(params: &(f32, f32, f32), u: f32, v: f32, w: f32) -> f32 {
-        1.0 / (params.0 * u + params.1 * v + params.2 * w)
-    }
-    #[inline(always)]
-    fn interp_barycentric(
-        params: &(f32, f32, f32),
-        u: f32,
-        v: f32,
-        w: f32,
-        z: f32,
-        v_a: f32,
-        v_b: f32,
-        v_c: f32,
-    ) -> f32 {
-        z * (v_a * params.0 * u + v_b * params.1 * v + v_c * params.2 * w)
-    }
     pub fn draw_triface(
         &mut self,
-        screen_space: &mut Option<Matrix>,
+        screen_space: &mut Option<Mat4>,
         camera_data: &CameraData,
-        v1: &Point3D,
-        v2: &Point3D,
-        v3: &Point3D,
-        light_color_1: Color,
-        light_color_2: Color,
-        light_color_3: Color,
-        texture: (f32, f32, f32, f32, f32, f32, u16),
+        vertices: [Point3D; 3],
+        corners: [VertexAttributes; 3],
+        texture_id: u16,
     ) {
+        let [v1, v2, v3] = &vertices;
+        let is_orthographic = camera_data.projection != Projection::Perspective;
+        self.stats.faces_emitted += 1;
+
         let normal = RenderMatrices::triface_normal(v1, v2, v3);
-        if normal.dot(&RenderMatrices::triface_center(v1, v2, v3)) >= 0.0 {
+        let facing_away = if is_orthographic {
+            normal.z() >= 0.0
+        } else {
+            normal.dot(&RenderMatrices::triface_center(v1, v2, v3)) >= 0.0
+        };
+        if facing_away {
+            self.stats.triangles_backface_culled += 1;
             return;
         }
 
         let reverse = CameraCache::to_screen_space(screen_space, camera_data);
 
-        let projected1 = v1.transform(&reverse);
-        let projected2 = v2.transform(&reverse);
-        let projected3 = v3.transform(&reverse);
+        let projected1 = v1.transform(reverse);
+        let projected2 = v2.transform(reverse);
+        let projected3 = v3.transform(reverse);
 
         let p1x = projected1.x_coord();
         let p1y = projected1.y_coord();
@@ -237,99 +631,145 @@ impl Renderer {
             return;
         }
 
-        let (tc1x, tc1y, tc2x, tc2y, tc3x, tc3y, texture_id) = texture;
+        if self.debug_view == DebugView::Wireframe {
+            self.write_line(&projected1, &projected2, debug::WIREFRAME_COLOR);
+            self.write_line(&projected2, &projected3, debug::WIREFRAME_COLOR);
+            self.write_line(&projected3, &projected1, debug::WIREFRAME_COLOR);
+            return;
+        }
+
+        let [c1, c2, c3] = corners;
+        let ((tc1x, tc1y), (tc2x, tc2y), (tc3x, tc3y)) = (c1.texcoord, c2.texcoord, c3.texcoord);
 
-        let bary_interp_params = Self::barycentric_interp_params(
-            v1.z_coord_float(),
-            v2.z_coord_float(),
-            v3.z_coord_float(),
-        );
+        // Orthographic projections don't need perspective correction, so everything
+        // (including z) gets interpolated linearly in screen space
+        let bary_interp_params = if is_orthographic {
+            (1.0, 1.0, 1.0)
+        } else {
+            Self::barycentric_interp_params(
+                v1.z_coord_float(),
+                v2.z_coord_float(),
+                v3.z_coord_float(),
+            )
+        };
 
-        let min_x = cmp::max(0, cmp::min(cmp::min(p1x, p2x), p3x));
-        let min_y = cmp::max(0, cmp::min(cmp::min(p1y, p2y), p3y));
-        let max_x = cmp::min(self.width as isize - 1, cmp::max(cmp::max(p1x, p2x), p3x));
-        let max_y = cmp::min(self.height as isize - 1, cmp::max(cmp::max(p1y, p2y), p3y));
+        let snap_vertex = |projected: &Point3D| {
+            Some((
+                raster::snap(projected.get(0))?,
+                raster::snap(projected.get(1))?,
+            ))
+        };
+        let edges = match (
+            snap_vertex(&projected1),
+            snap_vertex(&projected2),
+            snap_vertex(&projected3),
+        ) {
+            (Some(s1), Some(s2), Some(s3)) => {
+                TriangleEdges::new([s1, s2, s3], self.width, self.height)
+                    .map(|edges| (edges, [s1, s2, s3]))
+            }
+            _ => None,
+        };
+        if edges.is_none() {
+            return;
+        }
+        let (edges, [s1, s2, s3]) = edges.unwrap();
 
+        // Attributes are interpolated from the snapped vertices and sampled at pixel centers,
+        // same as coverage
         let barycentric_params_wrapped = RenderMatrices::barycentric_params(
-            min_x as f32,
-            min_y as f32,
-            projected1.get(0),
-            projected1.get(1),
-            projected2.get(0),
-            projected2.get(1),
-            projected3.get(0),
-            projected3.get(1),
+            edges.min_x as f32 + 0.5,
+            edges.min_y as f32 + 0.5,
+            raster::unsnap(s1.0),
+            raster::unsnap(s1.1),
+            raster::unsnap(s2.0),
+            raster::unsnap(s2.1),
+            raster::unsnap(s3.0),
+            raster::unsnap(s3.1),
         );
         if barycentric_params_wrapped.is_none() {
             return;
         }
         let barycentric_params = barycentric_params_wrapped.unwrap();
 
-        let mut pixel_iterator =
-            self.pixel_iterator(min_x as usize, min_y as usize, &barycentric_params);
-
-        let z_map_denominator = 1.0 / (camera_data.far - camera_data.near);
-
-        for _indy in min_y..=max_y {
-            let [x_start, x_end] = pixel_iterator.solve_x_range(min_x, max_x);
-            pixel_iterator.set_x(x_start + min_x);
-
-            for _indx in x_start..=x_end {
-                let u = pixel_iterator.u;
-                let v = pixel_iterator.v;
-                let w = pixel_iterator.w;
-
-                let interp_z = Self::interp_barycentric_z(&bary_interp_params, u, v, w);
-                let actual_z = (interp_z - camera_data.near) * z_map_denominator;
-
-                if !self.fails_z_check(pixel_iterator.pixel_offset, actual_z) {
-                    let tcx = Self::interp_barycentric(
-                        &bary_interp_params,
-                        u,
-                        v,
-                        w,
-                        interp_z,
-                        tc1x,
-                        tc2x,
-                        tc3x,
-                    );
-                    let tcy = Self::interp_barycentric(
-                        &bary_interp_params,
-                        u,
-                        v,
-                        w,
-                        interp_z,
-                        tc1y,
-                        tc2y,
-                        tc3y,
-                    );
-                    let mut pixel_color = self.textures[texture_id as usize].sample(tcx, tcy);
-                    pixel_color.compose(Color::interp_barycentric(
-                        &bary_interp_params,
-                        u,
-                        v,
-                        w,
-                        interp_z,
-                        light_color_1,
-                        light_color_2,
-                        light_color_3,
-                    ));
-
-                    self.write_pixel_internal(
-                        pixel_iterator.pixel_offset,
-                        pixel_iterator.offset,
-                        actual_z,
-                        pixel_color,
-                    );
-                }
-                pixel_iterator.next_column();
+        let pixel_iterator = self.pixel_iterator(
+            edges.min_x as usize,
+            edges.min_y as usize,
+            &barycentric_params,
+        );
+
+        let (p1, p2, p3) = bary_interp_params;
+        let [n1, n2, n3] = corners.map(|corner| corner.normal.unwrap_or(normal.normalize()));
+        let setup = TriangleSetup {
+            edges,
+            pixel_iterator,
+
+            is_orthographic,
+            near: camera_data.near,
+            z_map: 1.0 / (camera_data.far - camera_data.near),
+            z_weights: if is_orthographic {
+                (v1.z_coord_float(), v2.z_coord_float(), v3.z_coord_float())
+            } else {
+                bary_interp_params
+            },
+            tcx_weights: (tc1x * p1, tc2x * p2, tc3x * p3),
+            tcy_weights: (tc1y * p1, tc2y * p2, tc3y * p3),
+            r_weights: (
+                c1.light_color.r as f32 * p1,
+                c2.light_color.r as f32 * p2,
+                c3.light_color.r as f32 * p3,
+            ),
+            g_weights: (
+                c1.light_color.g as f32 * p1,
+                c2.light_color.g as f32 * p2,
+                c3.light_color.g as f32 * p3,
+            ),
+            b_weights: (
+                c1.light_color.b as f32 * p1,
+                c2.light_color.b as f32 * p2,
+                c3.light_color.b as f32 * p3,
+            ),
+            texture_id,
+
+            per_pixel_lighting: self.lighting_quality == LightingQuality::PerPixel,
+            nx_weights: (n1.x() * p1, n2.x() * p2, n3.x() * p3),
+            ny_weights: (n1.y() * p1, n2.y() * p2, n3.y() * p3),
+            nz_weights: (n1.z() * p1, n2.z() * p2, n3.z() * p3),
+            px_weights: (v1.get(0) * p1, v2.get(0) * p2, v3.get(0) * p3),
+            py_weights: (v1.get(1) * p1, v2.get(1) * p2, v3.get(1) * p3),
+            pz_weights: (v1.get(2) * p1, v2.get(2) * p2, v3.get(2) * p3),
+
+            flat_color: match self.debug_view {
+                DebugView::Normals => Some(debug::normal_color(&self.world_normal(&normal))),
+                _ => None,
+            },
+        };
+
+        match self.raster_mode {
+            RasterMode::Immediate => {
+                let mut target = RasterTarget {
+                    width: self.width,
+                    first_row: 0,
+                    pixels: &mut self.pixels,
+                    z_buffer: &mut self.z_buffer,
+                    overdraw: &mut self.overdraw,
+                    counters: RasterCounters::default(),
+                };
+                raster::rasterize(
+                    &setup,
+                    &mut target,
+                    &self.textures,
+                    &self.view_lights,
+                    (0, 0, self.width as isize - 1, self.height as isize - 1),
+                );
+                self.stats.raster += target.counters;
             }
-            pixel_iterator.next_row();
+            RasterMode::Tiled { .. } => self.tile_bins.push(setup),
         }
     }
     pub fn draw_quadface(
         &mut self,
-        screen_space: &mut Option<Matrix>,
+        screen_space: &mut Option<Mat4>,
         camera_data: &CameraData,
         v1: &Point3D,
         v2: &Point3D,
@@ -342,39 +782,40 @@ impl Renderer {
         fill: (f32, f32, f32, f32, f32, f32, f32, f32, u16),
     ) {
         let (tc1x, tc1y, tc2x, tc2y, tc3x, tc3y, tc4x, tc4y, tex) = fill;
+        let corner = |light_color: Color, texcoord: (f32, f32)| VertexAttributes {
+            light_color,
+            texcoord,
+            normal: None,
+        };
+        let c1 = corner(light_color_1, (tc1x, tc1y));
+        let c2 = corner(light_color_2, (tc2x, tc2y));
+        let c3 = corner(light_color_3, (tc3x, tc3y));
+        let c4 = corner(light_color_4, (tc4x, tc4y));
         self.draw_triface(
             screen_space,
             camera_data,
-            v1,
-            v2,
-            v3,
-            light_color_1,
-            light_color_2,
-            light_color_3,
-            (tc1x, tc1y, tc2x, tc2y, tc3x, tc3y, tex),
+            [*v1, *v2, *v3],
+            [c1, c2, c3],
+            tex,
         );
         self.draw_triface(
             screen_space,
             camera_data,
-            v3,
-            v4,
-            v1,
-            light_color_3,
-            light_color_4,
-            light_color_1,
-            (tc3x, tc3y, tc4x, tc4y, tc1x, tc1y, tex),
+            [*v3, *v4, *v1],
+            [c3, c4, c1],
+            tex,
         );
     }
-    pub fn draw_cubeface<LightingCalculator: Fn(&Point3D, &Vector) -> Color>(
+    pub fn draw_cubeface<LightingCalculator: Fn(&Point3D, &Vec3) -> Color>(
         &mut self,
-        screen_space: &mut Option<Matrix>,
+        screen_space: &mut Option<Mat4>,
         camera_data: &CameraData,
         center: &Point3D,
         side: CubeFace,
         halfsides: &[f32],
-        post_transform: &Matrix,
+        post_transform: &Mat4,
         calculate_lighting: &LightingCalculator,
-        texture: u16,
+        texture: &FaceTexture,
     ) {
         let x = center.get(0);
         let y = center.get(1);
@@ -414,6 +855,15 @@ impl Renderer {
         let color_3 = calculate_lighting(&p3, &normal);
         let color_4 = calculate_lighting(&p4, &normal);
 
+        let (outward, down) = side.texture_axes();
+        let right = down.cross(&-outward);
+        let [tc1, tc2, tc3, tc4] = [&p1, &p2, &p3, &p4].map(|point| {
+            let offset = point.xyz() - center.xyz();
+            let corner = |axis: &Vec3| if offset.dot(axis) > 0.0 { 1.0 } else { 0.0 };
+            let (u, v) = texture.uv(corner(&right), corner(&down));
+            (u * gfx::MTEXCOORD, v * gfx::MTEXCOORD)
+        });
+
         p1 = p1.transform(post_transform);
         p2 = p2.transform(post_transform);
         p3 = p3.transform(post_transform);
@@ -431,16 +881,57 @@ impl Renderer {
             color_3,
             color_4,
             (
-                0.0,
-                gfx::MTEXCOORD,
-                gfx::MTEXCOORD,
-                gfx::MTEXCOORD,
-                gfx::MTEXCOORD,
-                0.0,
-                0.0,
-                0.0,
-                texture,
+                tc1.0, tc1.1, tc2.0, tc2.1, tc3.0, tc3.1, tc4.0, tc4.1, texture.id,
             ),
         );
     }
+    // model takes the mesh's own coordinates into the world, lighting is worked out there
+    pub fn draw_mesh<LightingCalculator: Fn(&Point3D, &Vec3) -> Color>(
+        &mut self,
+        screen_space: &mut Option<Mat4>,
+        camera_data: &CameraData,
+        mesh: &Mesh,
+        model: &Mat4,
+        post_transform: &Mat4,
+        calculate_lighting: &LightingCalculator,
+    ) {
+        // Nothing to see if model squashes the mesh flat
+        let normal_matrix = match RenderMatrices::normal_matrix(model) {
+            Some(normal_matrix) => normal_matrix,
+            None => return,
+        };
+        for triangle in mesh.triangles.iter() {
+            let points =
+                triangle.map(|indx| Point3D::from_vec3(mesh.positions[indx]).transform(model));
+            let [p1, p2, p3] = points;
+            let face_normal = RenderMatrices::triface_normal(&p1, &p2, &p3);
+            let corners = [0, 1, 2].map(|corner| {
+                let indx = triangle[corner];
+                let normal = match mesh.normals.get(indx) {
+                    Some(normal) => (normal_matrix
+                        * Vec4::new(normal.x(), normal.y(), normal.z(), 0.0))
+                    .xyz()
+                    .normalize(),
+                    None => face_normal,
+                };
+                let (u, v) = mesh.uvs.get(indx).copied().unwrap_or((0.0, 0.0));
+                VertexAttributes {
+                    light_color: calculate_lighting(&points[corner], &normal),
+                    texcoord: (u * gfx::MTEXCOORD, v * gfx::MTEXCOORD),
+                    normal: Some(
+                        (*post_transform * Vec4::new(normal.x(), normal.y(), normal.z(), 0.0))
+                            .xyz()
+                            .normalize(),
+                    ),
+                }
+            });
+            self.draw_triface(
+                screen_space,
+                camera_data,
+                points.map(|point| point.transform(post_transform)),
+                corners,
+                mesh.texture,
+            );
+        }
+    }
 }
@@ -1,18 +1,23 @@
+#[derive(Clone)]
 pub struct PixelIterator {
     width: usize,
     height: usize,
 
     pub x: usize,
     pub y: usize,
+    origin_x: usize,
+    origin_y: usize,
 
     pub u: f32,
     pub v: f32,
     pub w: f32,
-    row_u: f32,
-    row_v: f32,
-    row_w: f32,
+    origin_u: f32,
+    origin_v: f32,
+    origin_w: f32,
+    pub row_u: f32,
+    pub row_v: f32,
+    pub row_w: f32,
     barycentric_deltas: [f32; 6],
-    inv_x_deltas: [Option<f32>; 3],
 
     pub pixel_offset: usize,
     pub offset: usize,
@@ -33,10 +38,15 @@ impl PixelIterator {
 
             x,
             y,
+            origin_x: x,
+            origin_y: y,
 
             u: barycentric_params.0,
             v: barycentric_params.1,
             w: barycentric_params.2,
+            origin_u: barycentric_params.0,
+            origin_v: barycentric_params.1,
+            origin_w: barycentric_params.2,
             row_u: barycentric_params.0,
             row_v: barycentric_params.1,
             row_w: barycentric_params.2,
@@ -49,40 +59,38 @@ impl PixelIterator {
                 barycentric_params.7, // dvdy
                 barycentric_params.8, // dwdy
             ],
-            inv_x_deltas: [
-                if barycentric_params.3.abs() < 0.0001 {
-                    None
-                } else {
-                    Some(1.0 / barycentric_params.3)
-                },
-                if barycentric_params.4.abs() < 0.0001 {
-                    None
-                } else {
-                    Some(1.0 / barycentric_params.4)
-                },
-                if barycentric_params.5.abs() < 0.0001 {
-                    None
-                } else {
-                    Some(1.0 / barycentric_params.5)
-                },
-            ],
 
             pixel_offset,
             offset: 4 * pixel_offset,
         }
     }
-    pub fn next_row(&mut self) {
-        self.y += 1;
-        self.pixel_offset += self.width;
-        self.offset += 4 * self.width;
+    // Rows are computed straight from the origin instead of accumulating deltas, so a row's
+    // coordinates don't depend on which rows were visited before it
+    pub fn set_row(&mut self, y: usize) {
+        let dy = (y as isize - self.origin_y as isize) as f32;
+
+        self.x = self.origin_x;
+        self.y = y;
+        self.pixel_offset = y * self.width + self.origin_x;
+        self.offset = 4 * self.pixel_offset;
 
-        self.u += self.barycentric_deltas[3];
-        self.v += self.barycentric_deltas[4];
-        self.w += self.barycentric_deltas[5];
+        self.row_u = self.origin_u + dy * self.barycentric_deltas[3];
+        self.row_v = self.origin_v + dy * self.barycentric_deltas[4];
+        self.row_w = self.origin_w + dy * self.barycentric_deltas[5];
 
-        self.row_u += self.barycentric_deltas[3];
-        self.row_v += self.barycentric_deltas[4];
-        self.row_w += self.barycentric_deltas[5];
+        self.u = self.row_u;
+        self.v = self.row_v;
+        self.w = self.row_w;
+    }
+    pub fn next_row(&mut self) {
+        self.set_row(self.y + 1);
+    }
+    pub fn x_deltas(&self) -> (f32, f32, f32) {
+        (
+            self.barycentric_deltas[0],
+            self.barycentric_deltas[1],
+            self.barycentric_deltas[2],
+        )
     }
     pub fn next_column(&mut self) {
         self.x += 1;
@@ -93,6 +101,17 @@ impl PixelIterator {
         self.v += self.barycentric_deltas[1];
         self.w += self.barycentric_deltas[2];
     }
+    pub fn skip_columns(&mut self, n: usize) {
+        let n_f = n as f32;
+
+        self.x += n;
+        self.pixel_offset += n;
+        self.offset += 4 * n;
+
+        self.u += n_f * self.barycentric_deltas[0];
+        self.v += n_f * self.barycentric_deltas[1];
+        self.w += n_f * self.barycentric_deltas[2];
+    }
     pub fn set_x(&mut self, x: isize) {
         let dx = x - self.x as isize;
         let dx_f = dx as f32;
@@ -122,35 +141,4 @@ impl PixelIterator {
         self.row_v += dy * self.barycentric_deltas[4];
         self.row_w += dy * self.barycentric_deltas[5];
     }
-    // Solves for the range of x-coordinates (euclidean) to make points in the triangle
-    // Call it once for each of the 3 barycentric coordinates (u, v, w)
-    // a is the current row's barycentric coordinate, b is the RECIPROCAL of du/dx, or dv/dx, or
-    // dw/dx
-    fn solve_bary_range(lower: &mut f32, upper: &mut f32, a: f32, b: f32) {
-        let calculated = -a * b;
-        if b >= 0.0 {
-            if *lower < calculated {
-                *lower = calculated;
-            }
-        } else {
-            if *upper > calculated {
-                *upper = calculated;
-            }
-        }
-    }
-    pub fn solve_x_range(&self, min_x: isize, max_x: isize) -> [isize; 2] {
-        let mut low = 0.0;
-        let mut high = (max_x - min_x) as f32;
-        if let Some(inv) = self.inv_x_deltas[0] {
-            Self::solve_bary_range(&mut low, &mut high, self.row_u, inv)
-        }
-        if let Some(inv) = self.inv_x_deltas[1] {
-            Self::solve_bary_range(&mut low, &mut high, self.row_v, inv)
-        }
-        if let Some(inv) = self.inv_x_deltas[2] {
-            Self::solve_bary_range(&mut low, &mut high, self.row_w, inv)
-        }
-
-        [low as isize, (high + 0.5) as isize]
-    }
 }
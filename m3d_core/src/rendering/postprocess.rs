@@ -0,0 +1,325 @@
+use super::gfx::Color;
+use super::sky::SkyView;
+
+#[derive(Clone, Debug)]
+pub enum Effect {
+    // Blends towards color between start and end, which use the z buffer's mapping
+    // (0 at the near plane, 1 at the far plane). Pixels nothing was drawn to are left alone
+    Fog {
+        color: FogColor,
+        start: f32,
+        end: f32,
+    },
+    Gamma(f32),
+    // Exponential curve, rolls off highlights instead of clipping them
+    Tonemap {
+        exposure: f32,
+    },
+    // Darkens towards the corners, radius is the fraction of the half-diagonal left untouched
+    Vignette {
+        strength: f32,
+        radius: f32,
+    },
+    ColorGrade(Lut3D),
+}
+#[derive(Clone, Copy, Debug)]
+pub enum FogColor {
+    Fixed(Color),
+    // Whatever sky is behind the pixel, so the edge of the render distance fades into it
+    Sky,
+}
+#[derive(Clone, Debug)]
+pub struct PostProcess {
+    pub effect: Effect,
+    pub enabled: bool,
+}
+// size x size x size grid of colors, red changes fastest then green then blue
+#[derive(Clone, Debug)]
+pub struct Lut3D {
+    size: usize,
+    data: Vec<[f32; 3]>,
+}
+
+impl PostProcess {
+    pub fn new(effect: Effect) -> Self {
+        Self {
+            effect,
+            enabled: true,
+        }
+    }
+    pub fn disabled(effect: Effect) -> Self {
+        Self {
+            effect,
+            enabled: false,
+        }
+    }
+    fn apply(
+        &self,
+        pixels: &mut [u8],
+        z_buffer: &[f32],
+        width: usize,
+        height: usize,
+        sky: &SkyView,
+    ) {
+        match &self.effect {
+            Effect::Fog { color, start, end } => {
+                let inv_range = 1.0 / (end - start).max(0.0001);
+                for (indx, (pixel, &z)) in
+                    pixels.chunks_exact_mut(4).zip(z_buffer.iter()).enumerate()
+                {
+                    if z > 1.0 || z <= *start {
+                        continue;
+                    }
+                    let color = match color {
+                        FogColor::Fixed(color) => *color,
+                        FogColor::Sky => match sky.color_at(indx % width, indx / width) {
+                            Some(color) => color,
+                            None => continue,
+                        },
+                    };
+                    let amount = ((z - start) * inv_range).min(1.0);
+                    pixel[0] = lerp(pixel[0], color.r, amount);
+                    pixel[1] = lerp(pixel[1], color.g, amount);
+                    pixel[2] = lerp(pixel[2], color.b, amount);
+                }
+            }
+            Effect::Gamma(gamma) => {
+                let inv_gamma = 1.0 / gamma;
+                Self::apply_curve(pixels, |value| value.powf(inv_gamma));
+            }
+            Effect::Tonemap { exposure } => {
+                // The curve flattens out to a straight line as exposure goes to 0, and
+                // anything below that (or NaN) doesn't mean anything, so both are left alone
+                if *exposure <= 0.0 || exposure.is_nan() {
+                    return;
+                }
+                // Scaled so that white still maps to white
+                let white = 1.0 - (-exposure).exp();
+                Self::apply_curve(pixels, |value| (1.0 - (-value * exposure).exp()) / white);
+            }
+            Effect::Vignette { strength, radius } => {
+                let center_x = width as f32 / 2.0;
+                let center_y = height as f32 / 2.0;
+                let inv_half_diagonal = 1.0 / (center_x * center_x + center_y * center_y).sqrt();
+                for (indx, pixel) in pixels.chunks_exact_mut(4).enumerate() {
+                    let dx = (indx % width) as f32 + 0.5 - center_x;
+                    let dy = (indx / width) as f32 + 0.5 - center_y;
+                    let distance = (dx * dx + dy * dy).sqrt() * inv_half_diagonal;
+                    if distance <= *radius {
+                        continue;
+                    }
+                    let falloff = ((distance - radius) / (1.0 - radius).max(0.0001)).min(1.0);
+                    let factor = 1.0 - strength * falloff * falloff;
+                    pixel[0] = (pixel[0] as f32 * factor) as u8;
+                    pixel[1] = (pixel[1] as f32 * factor) as u8;
+                    pixel[2] = (pixel[2] as f32 * factor) as u8;
+                }
+            }
+            Effect::ColorGrade(lut) => {
+                for pixel in pixels.chunks_exact_mut(4) {
+                    let graded = lut.sample(
+                        pixel[0] as f32 / 255.0,
+                        pixel[1] as f32 / 255.0,
+                        pixel[2] as f32 / 255.0,
+                    );
+                    pixel[0] = (graded[0] * 255.0 + 0.5) as u8;
+                    pixel[1] = (graded[1] * 255.0 + 0.5) as u8;
+                    pixel[2] = (graded[2] * 255.0 + 0.5) as u8;
+                }
+            }
+        }
+    }
+    // Per channel curves only ever see 256 different inputs, so they're tabulated first
+    fn apply_curve(pixels: &mut [u8], curve: impl Fn(f32) -> f32) {
+        let mut table = [0u8; 256];
+        for (indx, entry) in table.iter_mut().enumerate() {
+            *entry = (curve(indx as f32 / 255.0) * 255.0 + 0.5) as u8;
+        }
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel[0] = table[pixel[0] as usize];
+            pixel[1] = table[pixel[1] as usize];
+            pixel[2] = table[pixel[2] as usize];
+        }
+    }
+}
+
+fn lerp(from: u8, to: u8, amount: f32) -> u8 {
+    (from as f32 + (to as f32 - from as f32) * amount + 0.5) as u8
+}
+
+// Runs the enabled stages in order
+pub fn apply(
+    post_processes: &[PostProcess],
+    pixels: &mut [u8],
+    z_buffer: &[f32],
+    width: usize,
+    height: usize,
+    sky: &SkyView,
+) {
+    for post_process in post_processes.iter().filter(|stage| stage.enabled) {
+        post_process.apply(pixels, z_buffer, width, height, sky);
+    }
+}
+
+impl Lut3D {
+    // data has size^3 rgb entries from 0 to 1
+    pub fn new(size: usize, data: Vec<[f32; 3]>) -> Option<Self> {
+        if size < 2 || data.len() != size * size * size {
+            return None;
+        }
+        Some(Self { size, data })
+    }
+    // Sizes below 2 get 2, the smallest grid sample can interpolate across
+    pub fn identity(size: usize) -> Self {
+        let size = size.max(2);
+        let max = (size - 1) as f32;
+        let mut data = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.push([r as f32 / max, g as f32 / max, b as f32 / max]);
+                }
+            }
+        }
+        Self { size, data }
+    }
+    // Same layout as the 2D strips most editors export: size tiles of size x size laid out
+    // left to right, one per blue value, red along x and green along y
+    pub fn from_strip(size: usize, rgba: &[u8]) -> Option<Self> {
+        if rgba.len() != 4 * size * size * size {
+            return None;
+        }
+        let strip_width = size * size;
+        let mut data = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let offset = 4 * (g * strip_width + b * size + r);
+                    data.push([
+                        rgba[offset] as f32 / 255.0,
+                        rgba[offset + 1] as f32 / 255.0,
+                        rgba[offset + 2] as f32 / 255.0,
+                    ]);
+                }
+            }
+        }
+        Self::new(size, data)
+    }
+    fn get(&self, r: usize, g: usize, b: usize) -> [f32; 3] {
+        self.data[(b * self.size + g) * self.size + r]
+    }
+    // Trilinear, inputs from 0 to 1
+    pub fn sample(&self, r: f32, g: f32, b: f32) -> [f32; 3] {
+        let max = (self.size - 1) as f32;
+        let split = |value: f32| {
+            let scaled = value.clamp(0.0, 1.0) * max;
+            let low = (scaled as usize).min(self.size - 2);
+            (low, scaled - low as f32)
+        };
+        let (r0, tr) = split(r);
+        let (g0, tg) = split(g);
+        let (b0, tb) = split(b);
+
+        let mut to_return = [0.0; 3];
+        for (corner, weight) in [
+            ((0, 0, 0), (1.0 - tr) * (1.0 - tg) * (1.0 - tb)),
+            ((1, 0, 0), tr * (1.0 - tg) * (1.0 - tb)),
+            ((0, 1, 0), (1.0 - tr) * tg * (1.0 - tb)),
+            ((1, 1, 0), tr * tg * (1.0 - tb)),
+            ((0, 0, 1), (1.0 - tr) * (1.0 - tg) * tb),
+            ((1, 0, 1), tr * (1.0 - tg) * tb),
+            ((0, 1, 1), (1.0 - tr) * tg * tb),
+            ((1, 1, 1), tr * tg * tb),
+        ] {
+            let value = self.get(r0 + corner.0, g0 + corner.1, b0 + corner.2);
+            to_return[0] += value[0] * weight;
+            to_return[1] += value[1] * weight;
+            to_return[2] += value[2] * weight;
+        }
+        to_return
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rendering::sky::Sky;
+
+    fn solid_sky(sky: &Sky) -> SkyView<'_> {
+        SkyView {
+            sky,
+            rays: None,
+            textures: &[],
+        }
+    }
+
+    #[test]
+    fn identity_stages_leave_frame_alone() {
+        let pixels = (0..=255).flat_map(|value| [value, 255 - value, value / 2, 255]);
+        let pixels = pixels.collect::<Vec<u8>>();
+        let z_buffer = vec![100000.0; 256];
+
+        let mut processed = pixels.clone();
+        let stages = [
+            PostProcess::new(Effect::Gamma(1.0)),
+            PostProcess::new(Effect::ColorGrade(Lut3D::identity(17))),
+            PostProcess::new(Effect::ColorGrade(Lut3D::identity(0))),
+            PostProcess::new(Effect::ColorGrade(Lut3D::identity(1))),
+            PostProcess::new(Effect::Tonemap { exposure: 0.0 }),
+            PostProcess::new(Effect::Tonemap { exposure: -1.0 }),
+            PostProcess::new(Effect::Fog {
+                color: FogColor::Fixed(Color::new(0, 0, 0, 255)),
+                start: 0.0,
+                end: 1.0,
+            }),
+            PostProcess::disabled(Effect::Vignette {
+                strength: 1.0,
+                radius: 0.0,
+            }),
+        ];
+        let sky = Sky::Solid(Color::new(0, 0, 0, 255));
+        apply(&stages, &mut processed, &z_buffer, 16, 16, &solid_sky(&sky));
+        assert_eq!(pixels, processed);
+    }
+    #[test]
+    fn fog_follows_depth() {
+        let mut pixels = vec![200; 12];
+        let z_buffer = [0.1, 0.5, 0.95];
+        let fog = PostProcess::new(Effect::Fog {
+            color: FogColor::Fixed(Color::new(0, 0, 0, 255)),
+            start: 0.3,
+            end: 0.9,
+        });
+        let sky = Sky::default_gradient();
+        apply(&[fog], &mut pixels, &z_buffer, 3, 1, &solid_sky(&sky));
+        assert_eq!(pixels[0], 200);
+        assert!(pixels[4] < 200 && pixels[4] > 0);
+        assert_eq!(pixels[8], 0);
+    }
+    #[test]
+    fn sky_fog_needs_a_known_sky() {
+        let fog = PostProcess::new(Effect::Fog {
+            color: FogColor::Sky,
+            start: 0.0,
+            end: 0.5,
+        });
+        let z_buffer = [0.75];
+        let mut pixels = vec![200; 4];
+        let sky = Sky::Solid(Color::new(10, 20, 30, 255));
+        apply(
+            std::slice::from_ref(&fog),
+            &mut pixels,
+            &z_buffer,
+            1,
+            1,
+            &solid_sky(&sky),
+        );
+        assert_eq!(pixels[..3], [10, 20, 30]);
+
+        // A gradient that wasn't drawn this frame has no rays to look it up with
+        let mut pixels = vec![200; 4];
+        let sky = Sky::default_gradient();
+        apply(&[fog], &mut pixels, &z_buffer, 1, 1, &solid_sky(&sky));
+        assert_eq!(pixels, vec![200; 4]);
+    }
+}
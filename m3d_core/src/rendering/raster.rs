@@ -0,0 +1,618 @@
+use super::gfx::{Color, Light, Texture, TEXTURE_SIZE};
+use super::pixeliterator::PixelIterator;
+use super::simd::{self, F32x4};
+use super::stats::RasterCounters;
+use crate::rendermath::{Point3D, Vec3};
+use std::cmp;
+
+pub const TILE_SIZE: usize = 64;
+
+// Vertices are snapped to 1/256th of a pixel before rasterizing. Coverage is then worked out
+// with exact integer edge functions, so two triangles sharing an edge agree on every pixel
+pub const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL_ONE: i64 = 1 << SUBPIXEL_BITS;
+const SUBPIXEL_HALF: i64 = SUBPIXEL_ONE / 2;
+// Vertices further than this many pixels from the origin could overflow the edge functions
+const GUARD_BAND: f32 = (1 << 21) as f32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RasterMode {
+    // Triangles are drawn as soon as they're submitted
+    Immediate,
+    // Triangles are binned into TILE_SIZE x TILE_SIZE tiles and drawn when the frame is
+    // flushed, with rows of tiles split between this many threads
+    Tiled { threads: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightingQuality {
+    // Lights are evaluated at the vertices and the colors interpolated across the face
+    PerVertex,
+    // Position and normal get interpolated instead and every pixel is lit on its own. Much
+    // slower, but near lights don't get smeared out over big faces
+    PerPixel,
+}
+
+// Everything the back end needs to draw a triangle, worked out once by the front end.
+// Vertex attributes are premultiplied by the barycentric interp params, so interpolating
+// any of them is just a weighted sum of u, v and w
+#[derive(Clone)]
+pub struct TriangleSetup {
+    pub edges: TriangleEdges,
+    pub pixel_iterator: PixelIterator,
+
+    pub is_orthographic: bool,
+    pub near: f32,
+    pub z_map: f32,
+    pub z_weights: (f32, f32, f32),
+    pub tcx_weights: (f32, f32, f32),
+    pub tcy_weights: (f32, f32, f32),
+    pub r_weights: (f32, f32, f32),
+    pub g_weights: (f32, f32, f32),
+    pub b_weights: (f32, f32, f32),
+    pub texture_id: u16,
+
+    // Only used with LightingQuality::PerPixel. Positions and normals are in view space
+    pub per_pixel_lighting: bool,
+    pub nx_weights: (f32, f32, f32),
+    pub ny_weights: (f32, f32, f32),
+    pub nz_weights: (f32, f32, f32),
+    pub px_weights: (f32, f32, f32),
+    pub py_weights: (f32, f32, f32),
+    pub pz_weights: (f32, f32, f32),
+
+    // Drawn instead of the texture and lighting if set, for debug views
+    pub flat_color: Option<Color>,
+}
+// E(P) = (b - a) x (P - a) for one edge, in subpixel units. origin is the value at the center
+// of the bounding box's top left pixel (minus the fill rule bias), the steps move one pixel
+#[derive(Clone, Copy)]
+struct EdgeFunction {
+    origin: i64,
+    step_x: i64,
+    step_y: i64,
+}
+// The three edges of a triangle, oriented so that the inside is where all of them are >= 0.
+// Pixel centers exactly on an edge follow the top-left rule: they belong to the triangle if
+// the edge is a top edge (flat, with the triangle below it) or a left edge
+#[derive(Clone)]
+pub struct TriangleEdges {
+    pub min_x: isize,
+    pub min_y: isize,
+    pub max_x: isize,
+    pub max_y: isize,
+    edges: [EdgeFunction; 3],
+}
+// A band of whole rows of the framebuffer, starting at first_row
+pub struct RasterTarget<'a> {
+    pub width: usize,
+    pub first_row: usize,
+    pub pixels: &'a mut [u8],
+    pub z_buffer: &'a mut [f32],
+    // Times each pixel was written, only kept track of if this isn't empty
+    pub overdraw: &'a mut [u16],
+    pub counters: RasterCounters,
+}
+// The whole frame, handed to TileBins::draw to be cut into RasterTargets
+pub struct FrameTarget<'a> {
+    pub width: usize,
+    pub pixels: &'a mut [u8],
+    pub z_buffer: &'a mut [f32],
+    // Empty if overdraw isn't being kept track of
+    pub overdraw: &'a mut [u16],
+}
+pub struct TileBins {
+    tiles_x: usize,
+    tiles_y: usize,
+    setups: Vec<TriangleSetup>,
+    bins: Vec<Vec<u32>>,
+}
+
+// Draws the part of the triangle inside clip, which is (min_x, min_y, max_x, max_y) inclusive.
+// Every pixel's barycentric coordinates are computed from the triangle's origin rather than
+// stepped, so the result is the same no matter how the screen is cut up
+pub fn rasterize(
+    setup: &TriangleSetup,
+    target: &mut RasterTarget,
+    textures: &[Texture],
+    lights: &[Light],
+    clip: (isize, isize, isize, isize),
+) {
+    let (clip_min_x, clip_min_y, clip_max_x, clip_max_y) = clip;
+    let edges = &setup.edges;
+    let start_y = cmp::max(edges.min_y, clip_min_y);
+    let end_y = cmp::min(edges.max_y, clip_max_y);
+    if start_y > end_y {
+        return;
+    }
+
+    let texture = &textures[setup.texture_id as usize];
+    let mut pixel_iterator = setup.pixel_iterator.clone();
+    let (dudx, dvdx, dwdx) = pixel_iterator.x_deltas();
+
+    let zero = F32x4::splat(0.0);
+    let one = F32x4::splat(1.0);
+    let near = F32x4::splat(setup.near);
+    let z_map = F32x4::splat(setup.z_map);
+    let lane_offsets = F32x4::new(0.0, 1.0, 2.0, 3.0);
+    let (dudx, dvdx, dwdx) = (F32x4::splat(dudx), F32x4::splat(dvdx), F32x4::splat(dwdx));
+
+    for y in start_y..=end_y {
+        let (x_start, x_end) = match edges.x_range(y) {
+            Some(range) => range,
+            None => continue,
+        };
+        let x_start = cmp::max(x_start, clip_min_x);
+        let x_end = cmp::min(x_end, clip_max_x);
+        pixel_iterator.set_row(y as usize);
+
+        let row_u = F32x4::splat(pixel_iterator.row_u);
+        let row_v = F32x4::splat(pixel_iterator.row_v);
+        let row_w = F32x4::splat(pixel_iterator.row_w);
+        let row_offset = (y as usize - target.first_row) * target.width;
+
+        let mut x = x_start;
+        while x <= x_end {
+            let lanes = cmp::min(simd::LANES as isize, x_end - x + 1) as usize;
+            let pixel_offset = row_offset + x as usize;
+
+            let lane_x = F32x4::splat((x - edges.min_x) as f32) + lane_offsets;
+            let u = row_u + lane_x * dudx;
+            let v = row_v + lane_x * dvdx;
+            let w = row_w + lane_x * dwdx;
+
+            let (z1, z2, z3) = setup.z_weights;
+            let z_sum = F32x4::interp(u, v, w, z1, z2, z3);
+            let (interp_z, view_z) = if setup.is_orthographic {
+                (one, z_sum)
+            } else {
+                let perspective_z = one / z_sum;
+                (perspective_z, perspective_z)
+            };
+            let actual_z = (view_z - near) * z_map;
+
+            let mut old_z = [0.0; simd::LANES];
+            old_z[..lanes].copy_from_slice(&target.z_buffer[pixel_offset..pixel_offset + lanes]);
+            let covered = F32x4::first_lanes(lanes);
+            let in_front = actual_z.lt(F32x4::from_array(old_z)).bitmask();
+            let mut to_draw =
+                in_front & actual_z.ge(zero).and(actual_z.le(one)).bitmask() & covered;
+            target.counters.z_test_failures += (covered & !in_front).count_ones() as u64;
+            target.counters.pixels_shaded += to_draw.count_ones() as u64;
+
+            if to_draw != 0 {
+                let new_z = actual_z.to_array();
+                if let Some(color) = setup.flat_color {
+                    while to_draw != 0 {
+                        let lane = to_draw.trailing_zeros() as usize;
+                        to_draw &= to_draw - 1;
+                        target.write(pixel_offset + lane, new_z[lane], color);
+                    }
+                } else {
+                    let interp = |weights: (f32, f32, f32)| {
+                        interp_z * F32x4::interp(u, v, w, weights.0, weights.1, weights.2)
+                    };
+                    let indices = F32x4::texel_indices(
+                        interp(setup.tcx_weights),
+                        interp(setup.tcy_weights),
+                        TEXTURE_SIZE as f32,
+                    );
+                    // Every index is masked to the texture, so lanes that won't be drawn
+                    // can be looked up too
+                    let texels = [
+                        texture.sample_index(indices[0]),
+                        texture.sample_index(indices[1]),
+                        texture.sample_index(indices[2]),
+                        texture.sample_index(indices[3]),
+                    ];
+
+                    let (light_r, light_g, light_b) = if setup.per_pixel_lighting {
+                        let px = interp(setup.px_weights).to_array();
+                        let py = interp(setup.py_weights).to_array();
+                        let pz = interp(setup.pz_weights).to_array();
+                        let nx = interp(setup.nx_weights).to_array();
+                        let ny = interp(setup.ny_weights).to_array();
+                        let nz = interp(setup.nz_weights).to_array();
+
+                        let mut light_colors = [Color::new(0, 0, 0, 255); simd::LANES];
+                        let mut lanes_left = to_draw;
+                        while lanes_left != 0 {
+                            let lane = lanes_left.trailing_zeros() as usize;
+                            lanes_left &= lanes_left - 1;
+
+                            let position =
+                                Point3D::from_euc_coords_float(px[lane], py[lane], pz[lane]);
+                            let normal = Vec3::new(nx[lane], ny[lane], nz[lane]).normalize();
+                            let light_color = &mut light_colors[lane];
+                            for light in lights {
+                                light_color.add(light.intensity(&position, &normal, 1));
+                            }
+                        }
+                        channels(&light_colors)
+                    } else {
+                        (
+                            interp(setup.r_weights).saturate(),
+                            interp(setup.g_weights).saturate(),
+                            interp(setup.b_weights).saturate(),
+                        )
+                    };
+                    let (texel_r, texel_g, texel_b) = channels(&texels);
+                    let r = texel_r.compose(light_r).trunc_i32();
+                    let g = texel_g.compose(light_g).trunc_i32();
+                    let b = texel_b.compose(light_b).trunc_i32();
+
+                    while to_draw != 0 {
+                        let lane = to_draw.trailing_zeros() as usize;
+                        to_draw &= to_draw - 1;
+                        let pixel_color =
+                            Color::new(r[lane] as u8, g[lane] as u8, b[lane] as u8, texels[lane].a);
+                        target.write(pixel_offset + lane, new_z[lane], pixel_color);
+                    }
+                }
+            }
+            x += lanes as isize;
+        }
+    }
+}
+
+// Red, green and blue of four pixels, one vector each
+#[inline(always)]
+fn channels(colors: &[Color; simd::LANES]) -> (F32x4, F32x4, F32x4) {
+    let channel = |get: fn(&Color) -> u8| {
+        F32x4::new(
+            get(&colors[0]) as f32,
+            get(&colors[1]) as f32,
+            get(&colors[2]) as f32,
+            get(&colors[3]) as f32,
+        )
+    };
+    (
+        channel(|color| color.r),
+        channel(|color| color.g),
+        channel(|color| color.b),
+    )
+}
+
+// Fixed point screen space coordinate, None if it's too far off screen (or NaN)
+pub fn snap(coord: f32) -> Option<i64> {
+    if coord.abs() < GUARD_BAND {
+        Some((coord * SUBPIXEL_ONE as f32).round() as i64)
+    } else {
+        None
+    }
+}
+pub fn unsnap(coord: i64) -> f32 {
+    coord as f32 / SUBPIXEL_ONE as f32
+}
+
+impl TriangleEdges {
+    // Vertices come from snap. None if the triangle has no area or doesn't cover any pixel
+    // center on the screen
+    pub fn new(vertices: [(i64, i64); 3], width: usize, height: usize) -> Option<Self> {
+        let [a, mut b, mut c] = vertices;
+        let area = (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0);
+        if area == 0 {
+            return None;
+        }
+        if area < 0 {
+            std::mem::swap(&mut b, &mut c);
+        }
+
+        // Pixel x's center is at x * SUBPIXEL_ONE + SUBPIXEL_HALF
+        let first_center = |coord: i64| -(-(coord - SUBPIXEL_HALF)).div_euclid(SUBPIXEL_ONE);
+        let last_center = |coord: i64| (coord - SUBPIXEL_HALF).div_euclid(SUBPIXEL_ONE);
+        let min_x = cmp::max(0, first_center(cmp::min(cmp::min(a.0, b.0), c.0)));
+        let min_y = cmp::max(0, first_center(cmp::min(cmp::min(a.1, b.1), c.1)));
+        let max_x = cmp::min(
+            width as i64 - 1,
+            last_center(cmp::max(cmp::max(a.0, b.0), c.0)),
+        );
+        let max_y = cmp::min(
+            height as i64 - 1,
+            last_center(cmp::max(cmp::max(a.1, b.1), c.1)),
+        );
+        if min_x > max_x || min_y > max_y {
+            return None;
+        }
+
+        let origin = (
+            min_x * SUBPIXEL_ONE + SUBPIXEL_HALF,
+            min_y * SUBPIXEL_ONE + SUBPIXEL_HALF,
+        );
+        Some(Self {
+            min_x: min_x as isize,
+            min_y: min_y as isize,
+            max_x: max_x as isize,
+            max_y: max_y as isize,
+            edges: [
+                Self::edge(b, c, origin),
+                Self::edge(c, a, origin),
+                Self::edge(a, b, origin),
+            ],
+        })
+    }
+    fn edge(from: (i64, i64), to: (i64, i64), origin: (i64, i64)) -> EdgeFunction {
+        let dx = to.0 - from.0;
+        let dy = to.1 - from.1;
+        // y points down the screen, so with this winding the inside of a left edge is to
+        // its right when it goes up, and the inside of a top edge is below it when it goes right
+        let is_top_left = dy < 0 || (dy == 0 && dx > 0);
+        EdgeFunction {
+            origin: dx * (origin.1 - from.1)
+                - dy * (origin.0 - from.0)
+                - if is_top_left { 0 } else { 1 },
+            step_x: -dy * SUBPIXEL_ONE,
+            step_y: dx * SUBPIXEL_ONE,
+        }
+    }
+    // Inclusive range of pixels on row y with their centers inside the triangle
+    pub fn x_range(&self, y: isize) -> Option<(isize, isize)> {
+        let dy = (y - self.min_y) as i64;
+        let mut low = 0;
+        let mut high = (self.max_x - self.min_x) as i64;
+        for edge in self.edges.iter() {
+            let value = edge.origin + dy * edge.step_y;
+            // value + dx * step_x >= 0, solved for dx
+            if edge.step_x > 0 {
+                low = cmp::max(low, -value.div_euclid(edge.step_x));
+            } else if edge.step_x < 0 {
+                high = cmp::min(high, value.div_euclid(-edge.step_x));
+            } else if value < 0 {
+                return None;
+            }
+        }
+        if low > high {
+            return None;
+        }
+        Some((self.min_x + low as isize, self.min_x + high as isize))
+    }
+}
+
+impl<'a> RasterTarget<'a> {
+    #[inline(always)]
+    fn write(&mut self, pixel_offset: usize, z: f32, color: Color) {
+        unsafe {
+            let offset = 4 * pixel_offset;
+            *self.pixels.get_unchecked_mut(offset) = color.r;
+            *self.pixels.get_unchecked_mut(offset + 1) = color.g;
+            *self.pixels.get_unchecked_mut(offset + 2) = color.b;
+            *self.pixels.get_unchecked_mut(offset + 3) = color.a;
+
+            *self.z_buffer.get_unchecked_mut(pixel_offset) = z;
+        }
+        if !self.overdraw.is_empty() {
+            self.overdraw[pixel_offset] = self.overdraw[pixel_offset].saturating_add(1);
+        }
+    }
+}
+
+impl TileBins {
+    pub fn new(width: usize, height: usize) -> Self {
+        let tiles_x = width.div_ceil(TILE_SIZE);
+        let tiles_y = height.div_ceil(TILE_SIZE);
+        Self {
+            tiles_x,
+            tiles_y,
+            setups: Vec::new(),
+            bins: vec![Vec::new(); tiles_x * tiles_y],
+        }
+    }
+    pub fn clear(&mut self) {
+        self.setups.clear();
+        for bin in self.bins.iter_mut() {
+            bin.clear();
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.setups.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.setups.is_empty()
+    }
+    // Bins are filled in submission order, which keeps z-test ties resolving the same way
+    // as in immediate mode
+    pub fn push(&mut self, setup: TriangleSetup) {
+        let edges = &setup.edges;
+        let indx = self.setups.len() as u32;
+        for tile_y in (edges.min_y as usize / TILE_SIZE)..=(edges.max_y as usize / TILE_SIZE) {
+            for tile_x in (edges.min_x as usize / TILE_SIZE)..=(edges.max_x as usize / TILE_SIZE) {
+                self.bins[tile_y * self.tiles_x + tile_x].push(indx);
+            }
+        }
+        self.setups.push(setup);
+    }
+    fn draw_tile_row(
+        &self,
+        tile_y: usize,
+        target: &mut RasterTarget,
+        textures: &[Texture],
+        lights: &[Light],
+    ) {
+        let height = target.z_buffer.len() / target.width;
+        let min_y = tile_y * TILE_SIZE;
+        let max_y = min_y + height - 1;
+
+        for tile_x in 0..self.tiles_x {
+            let min_x = tile_x * TILE_SIZE;
+            let max_x = cmp::min(min_x + TILE_SIZE, target.width) - 1;
+            let clip = (
+                min_x as isize,
+                min_y as isize,
+                max_x as isize,
+                max_y as isize,
+            );
+            for &indx in self.bins[tile_y * self.tiles_x + tile_x].iter() {
+                rasterize(&self.setups[indx as usize], target, textures, lights, clip);
+            }
+        }
+    }
+    pub fn draw(
+        &self,
+        threads: usize,
+        frame: FrameTarget,
+        textures: &[Texture],
+        lights: &[Light],
+    ) -> RasterCounters {
+        let width = frame.width;
+        let mut overdraw_rows = frame.overdraw.chunks_mut(width * TILE_SIZE);
+        let mut tile_rows = frame
+            .pixels
+            .chunks_mut(4 * width * TILE_SIZE)
+            .zip(frame.z_buffer.chunks_mut(width * TILE_SIZE))
+            .enumerate()
+            .map(|(tile_y, (pixels, z_buffer))| {
+                (
+                    tile_y,
+                    RasterTarget {
+                        width,
+                        first_row: tile_y * TILE_SIZE,
+                        pixels,
+                        z_buffer,
+                        overdraw: overdraw_rows.next().unwrap_or_default(),
+                        counters: RasterCounters::default(),
+                    },
+                )
+            })
+            .collect::<Vec<(usize, RasterTarget)>>();
+        debug_assert_eq!(tile_rows.len(), self.tiles_y);
+        let threads = cmp::min(threads, tile_rows.len());
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if threads > 1 {
+            // Interleave rows so threads aren't stuck with all the sky or all the ground
+            let mut work: Vec<Vec<(usize, RasterTarget)>> =
+                (0..threads).map(|_| Vec::new()).collect();
+            for (indx, tile_row) in tile_rows.into_iter().enumerate() {
+                work[indx % threads].push(tile_row);
+            }
+            return std::thread::scope(|scope| {
+                let handles = work
+                    .into_iter()
+                    .map(|mut rows| {
+                        scope.spawn(move || {
+                            let mut counters = RasterCounters::default();
+                            for (tile_y, target) in rows.iter_mut() {
+                                self.draw_tile_row(*tile_y, target, textures, lights);
+                                counters += target.counters;
+                            }
+                            counters
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let mut counters = RasterCounters::default();
+                for handle in handles {
+                    counters += handle.join().unwrap();
+                }
+                counters
+            });
+        }
+        // wasm32-unknown-unknown can't spawn std threads, the page has to start workers for
+        // super::workers instead. The number of threads is then up to the page, and without
+        // any tiles are drawn in order on the calling thread
+        #[cfg(all(target_arch = "wasm32", feature = "threads"))]
+        if threads > 1 && super::workers::running() > 0 {
+            // Rows are taken whole by whichever thread gets to them first, so each lock is
+            // only ever taken once and never waited on
+            let rows = tile_rows
+                .into_iter()
+                .map(std::sync::Mutex::new)
+                .collect::<Vec<_>>();
+            super::workers::for_each(rows.len(), &|indx| {
+                let mut row = rows[indx].lock().unwrap();
+                let (tile_y, target) = &mut *row;
+                self.draw_tile_row(*tile_y, target, textures, lights);
+            });
+            let mut counters = RasterCounters::default();
+            for row in rows {
+                counters += row.into_inner().unwrap().1.counters;
+            }
+            return counters;
+        }
+        #[cfg(all(target_arch = "wasm32", not(feature = "threads")))]
+        let _ = threads;
+
+        let mut counters = RasterCounters::default();
+        for (tile_y, target) in tile_rows.iter_mut() {
+            self.draw_tile_row(*tile_y, target, textures, lights);
+            counters += target.counters;
+        }
+        counters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts how many times each pixel of a width x height screen gets drawn
+    fn coverage(triangles: &[[(f32, f32); 3]], width: usize, height: usize) -> Vec<u32> {
+        let mut to_return = vec![0; width * height];
+        for triangle in triangles {
+            let vertices = [
+                (snap(triangle[0].0).unwrap(), snap(triangle[0].1).unwrap()),
+                (snap(triangle[1].0).unwrap(), snap(triangle[1].1).unwrap()),
+                (snap(triangle[2].0).unwrap(), snap(triangle[2].1).unwrap()),
+            ];
+            if let Some(edges) = TriangleEdges::new(vertices, width, height) {
+                for y in edges.min_y..=edges.max_y {
+                    if let Some((x_start, x_end)) = edges.x_range(y) {
+                        for x in x_start..=x_end {
+                            to_return[y as usize * width + x as usize] += 1;
+                        }
+                    }
+                }
+            }
+        }
+        to_return
+    }
+
+    #[test]
+    fn shared_edges_are_watertight() {
+        // A fan of triangles around an off-grid center, alternating winding, covering the
+        // whole screen. Every pixel should be drawn exactly once
+        let (width, height) = (37, 23);
+        let center = (17.3, 9.55);
+        let mut border = Vec::new();
+        for x in 0..=width {
+            border.push((x as f32, 0.0));
+        }
+        for y in 1..=height {
+            border.push((width as f32, y as f32 - 0.01 * y as f32));
+        }
+        for x in (0..width).rev() {
+            border.push((x as f32 + 0.25, height as f32));
+        }
+        for y in (1..height).rev() {
+            border.push((0.0, y as f32));
+        }
+
+        let mut triangles = Vec::new();
+        for indx in 0..border.len() {
+            let a = border[indx];
+            let b = border[(indx + 1) % border.len()];
+            if indx % 2 == 0 {
+                triangles.push([center, a, b]);
+            } else {
+                triangles.push([b, a, center]);
+            }
+        }
+        let covered = coverage(&triangles, width, height);
+        assert!(covered.iter().all(|&count| count == 1), "{:?}", covered);
+    }
+    #[test]
+    fn top_left_rule() {
+        // Quad exactly covering pixel centers 1..=2 on both axes, split along the diagonal
+        let quad = [
+            [(1.5, 1.5), (3.5, 1.5), (3.5, 3.5)],
+            [(3.5, 3.5), (1.5, 3.5), (1.5, 1.5)],
+        ];
+        let covered = coverage(&quad, 5, 5);
+        let mut expected = vec![0; 25];
+        for y in 1..3 {
+            for x in 1..3 {
+                expected[y * 5 + x] = 1;
+            }
+        }
+        assert_eq!(covered, expected);
+    }
+}
@@ -0,0 +1,510 @@
+// 4-wide f32 vectors for the rasterizer's inner loop. Uses SSE on x86, simd128 on wasm
+// (build with -C target-feature=+simd128) and plain arrays everywhere else. Every backend
+// does the same IEEE ops lane by lane, so the output doesn't depend on which one got picked.
+// The scalar one is always built for tests to check the others against
+
+pub const LANES: usize = 4;
+
+// Built out of the backend's own ops, so every backend gets the same ones
+macro_rules! shared_ops {
+    () => {
+        impl F32x4 {
+            #[inline(always)]
+            pub fn from_array(input: [f32; 4]) -> Self {
+                Self::new(input[0], input[1], input[2], input[3])
+            }
+            // Mask with the first n lanes set
+            #[inline(always)]
+            pub fn first_lanes(n: usize) -> u32 {
+                (1 << n) - 1
+            }
+            // Interpolates a per-vertex attribute, the vertex values should already be
+            // multiplied by the barycentric_interp_params
+            #[inline(always)]
+            pub fn interp(u: Self, v: Self, w: Self, a: f32, b: f32, c: f32) -> Self {
+                Self::splat(a) * u + Self::splat(b) * v + Self::splat(c) * w
+            }
+            // Truncates to 0..=255, anything out of range (or NaN) is pinned to the nearest
+            // end instead of wrapping around
+            #[inline(always)]
+            pub fn saturate(self) -> Self {
+                self.max(Self::splat(0.0)).min(Self::splat(255.0)).trunc()
+            }
+            // Color::compose on one channel of four pixels. Both sides are whole numbers up
+            // to 255, so the product is exact and the division never rounds up past a whole
+            // number, which makes this the same as the integer version
+            #[inline(always)]
+            pub fn compose(self, light: Self) -> Self {
+                (self * light / Self::splat(255.0)).trunc()
+            }
+            // size * v + u with both coordinates truncated first, for looking up texels in a
+            // texture size texels wide. Exact while the result fits in 24 bits
+            #[inline(always)]
+            pub fn texel_indices(u: Self, v: Self, size: f32) -> [i32; 4] {
+                (v.trunc() * Self::splat(size) + u.trunc()).trunc_i32()
+            }
+        }
+    };
+}
+
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    target_feature = "sse2"
+))]
+mod backend {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    #[derive(Clone, Copy)]
+    pub struct F32x4(__m128);
+    #[derive(Clone, Copy)]
+    pub struct Mask4(__m128);
+
+    shared_ops!();
+
+    impl F32x4 {
+        #[inline(always)]
+        pub fn splat(val: f32) -> Self {
+            unsafe { Self(_mm_set1_ps(val)) }
+        }
+        #[inline(always)]
+        pub fn new(a: f32, b: f32, c: f32, d: f32) -> Self {
+            unsafe { Self(_mm_setr_ps(a, b, c, d)) }
+        }
+        #[inline(always)]
+        pub fn to_array(self) -> [f32; 4] {
+            let mut to_return = [0.0; 4];
+            unsafe { _mm_storeu_ps(to_return.as_mut_ptr(), self.0) };
+            to_return
+        }
+        #[inline(always)]
+        pub fn lt(self, rhs: Self) -> Mask4 {
+            unsafe { Mask4(_mm_cmplt_ps(self.0, rhs.0)) }
+        }
+        #[inline(always)]
+        pub fn le(self, rhs: Self) -> Mask4 {
+            unsafe { Mask4(_mm_cmple_ps(self.0, rhs.0)) }
+        }
+        #[inline(always)]
+        pub fn ge(self, rhs: Self) -> Mask4 {
+            unsafe { Mask4(_mm_cmpge_ps(self.0, rhs.0)) }
+        }
+        // Lane-wise `if self < rhs { self } else { rhs }`, same as minps
+        #[inline(always)]
+        pub fn min(self, rhs: Self) -> Self {
+            unsafe { Self(_mm_min_ps(self.0, rhs.0)) }
+        }
+        #[inline(always)]
+        pub fn max(self, rhs: Self) -> Self {
+            unsafe { Self(_mm_max_ps(self.0, rhs.0)) }
+        }
+        // Drops the fraction, only exact while every lane fits in an i32
+        #[inline(always)]
+        pub fn trunc(self) -> Self {
+            unsafe { Self(_mm_cvtepi32_ps(_mm_cvttps_epi32(self.0))) }
+        }
+        // Rounds towards zero, like `as`
+        #[inline(always)]
+        pub fn trunc_i32(self) -> [i32; 4] {
+            let mut to_return = [0; 4];
+            unsafe {
+                _mm_storeu_si128(
+                    to_return.as_mut_ptr() as *mut __m128i,
+                    _mm_cvttps_epi32(self.0),
+                )
+            };
+            to_return
+        }
+    }
+    impl std::ops::Add for F32x4 {
+        type Output = Self;
+        #[inline(always)]
+        fn add(self, rhs: Self) -> Self {
+            unsafe { Self(_mm_add_ps(self.0, rhs.0)) }
+        }
+    }
+    impl std::ops::Sub for F32x4 {
+        type Output = Self;
+        #[inline(always)]
+        fn sub(self, rhs: Self) -> Self {
+            unsafe { Self(_mm_sub_ps(self.0, rhs.0)) }
+        }
+    }
+    impl std::ops::Mul for F32x4 {
+        type Output = Self;
+        #[inline(always)]
+        fn mul(self, rhs: Self) -> Self {
+            unsafe { Self(_mm_mul_ps(self.0, rhs.0)) }
+        }
+    }
+    impl std::ops::Div for F32x4 {
+        type Output = Self;
+        #[inline(always)]
+        fn div(self, rhs: Self) -> Self {
+            unsafe { Self(_mm_div_ps(self.0, rhs.0)) }
+        }
+    }
+    impl Mask4 {
+        #[inline(always)]
+        pub fn and(self, rhs: Self) -> Self {
+            unsafe { Self(_mm_and_ps(self.0, rhs.0)) }
+        }
+        // Lane n is bit n
+        #[inline(always)]
+        pub fn bitmask(self) -> u32 {
+            unsafe { _mm_movemask_ps(self.0) as u32 }
+        }
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+mod backend {
+    use std::arch::wasm32::*;
+
+    #[derive(Clone, Copy)]
+    pub struct F32x4(v128);
+    #[derive(Clone, Copy)]
+    pub struct Mask4(v128);
+
+    shared_ops!();
+
+    impl F32x4 {
+        #[inline(always)]
+        pub fn splat(val: f32) -> Self {
+            Self(f32x4_splat(val))
+        }
+        #[inline(always)]
+        pub fn new(a: f32, b: f32, c: f32, d: f32) -> Self {
+            Self(f32x4(a, b, c, d))
+        }
+        #[inline(always)]
+        pub fn to_array(self) -> [f32; 4] {
+            [
+                f32x4_extract_lane::<0>(self.0),
+                f32x4_extract_lane::<1>(self.0),
+                f32x4_extract_lane::<2>(self.0),
+                f32x4_extract_lane::<3>(self.0),
+            ]
+        }
+        #[inline(always)]
+        pub fn lt(self, rhs: Self) -> Mask4 {
+            Mask4(f32x4_lt(self.0, rhs.0))
+        }
+        #[inline(always)]
+        pub fn le(self, rhs: Self) -> Mask4 {
+            Mask4(f32x4_le(self.0, rhs.0))
+        }
+        #[inline(always)]
+        pub fn ge(self, rhs: Self) -> Mask4 {
+            Mask4(f32x4_ge(self.0, rhs.0))
+        }
+        // Lane-wise `if self < rhs { self } else { rhs }`, same as minps
+        #[inline(always)]
+        pub fn min(self, rhs: Self) -> Self {
+            Self(f32x4_pmin(rhs.0, self.0))
+        }
+        #[inline(always)]
+        pub fn max(self, rhs: Self) -> Self {
+            Self(f32x4_pmax(rhs.0, self.0))
+        }
+        // Drops the fraction, only exact while every lane fits in an i32
+        #[inline(always)]
+        pub fn trunc(self) -> Self {
+            Self(f32x4_convert_i32x4(i32x4_trunc_sat_f32x4(self.0)))
+        }
+        // Rounds towards zero, like `as`
+        #[inline(always)]
+        pub fn trunc_i32(self) -> [i32; 4] {
+            let converted = i32x4_trunc_sat_f32x4(self.0);
+            [
+                i32x4_extract_lane::<0>(converted),
+                i32x4_extract_lane::<1>(converted),
+                i32x4_extract_lane::<2>(converted),
+                i32x4_extract_lane::<3>(converted),
+            ]
+        }
+    }
+    impl std::ops::Add for F32x4 {
+        type Output = Self;
+        #[inline(always)]
+        fn add(self, rhs: Self) -> Self {
+            Self(f32x4_add(self.0, rhs.0))
+        }
+    }
+    impl std::ops::Sub for F32x4 {
+        type Output = Self;
+        #[inline(always)]
+        fn sub(self, rhs: Self) -> Self {
+            Self(f32x4_sub(self.0, rhs.0))
+        }
+    }
+    impl std::ops::Mul for F32x4 {
+        type Output = Self;
+        #[inline(always)]
+        fn mul(self, rhs: Self) -> Self {
+            Self(f32x4_mul(self.0, rhs.0))
+        }
+    }
+    impl std::ops::Div for F32x4 {
+        type Output = Self;
+        #[inline(always)]
+        fn div(self, rhs: Self) -> Self {
+            Self(f32x4_div(self.0, rhs.0))
+        }
+    }
+    impl Mask4 {
+        #[inline(always)]
+        pub fn and(self, rhs: Self) -> Self {
+            Self(v128_and(self.0, rhs.0))
+        }
+        // Lane n is bit n
+        #[inline(always)]
+        pub fn bitmask(self) -> u32 {
+            i32x4_bitmask(self.0) as u32
+        }
+    }
+}
+
+#[cfg(any(
+    test,
+    not(any(
+        all(
+            any(target_arch = "x86", target_arch = "x86_64"),
+            target_feature = "sse2"
+        ),
+        all(target_arch = "wasm32", target_feature = "simd128")
+    ))
+))]
+mod scalar {
+    #[derive(Clone, Copy)]
+    pub struct F32x4([f32; 4]);
+    #[derive(Clone, Copy)]
+    pub struct Mask4([bool; 4]);
+
+    shared_ops!();
+
+    impl F32x4 {
+        #[inline(always)]
+        fn zip(self, rhs: Self, op: impl Fn(f32, f32) -> f32) -> Self {
+            Self([
+                op(self.0[0], rhs.0[0]),
+                op(self.0[1], rhs.0[1]),
+                op(self.0[2], rhs.0[2]),
+                op(self.0[3], rhs.0[3]),
+            ])
+        }
+        #[inline(always)]
+        fn compare(self, rhs: Self, op: impl Fn(f32, f32) -> bool) -> Mask4 {
+            Mask4([
+                op(self.0[0], rhs.0[0]),
+                op(self.0[1], rhs.0[1]),
+                op(self.0[2], rhs.0[2]),
+                op(self.0[3], rhs.0[3]),
+            ])
+        }
+        #[inline(always)]
+        pub fn splat(val: f32) -> Self {
+            Self([val; 4])
+        }
+        #[inline(always)]
+        pub fn new(a: f32, b: f32, c: f32, d: f32) -> Self {
+            Self([a, b, c, d])
+        }
+        #[inline(always)]
+        pub fn to_array(self) -> [f32; 4] {
+            self.0
+        }
+        #[inline(always)]
+        pub fn lt(self, rhs: Self) -> Mask4 {
+            self.compare(rhs, |a, b| a < b)
+        }
+        #[inline(always)]
+        pub fn le(self, rhs: Self) -> Mask4 {
+            self.compare(rhs, |a, b| a <= b)
+        }
+        #[inline(always)]
+        pub fn ge(self, rhs: Self) -> Mask4 {
+            self.compare(rhs, |a, b| a >= b)
+        }
+        // Lane-wise `if self < rhs { self } else { rhs }`, same as minps
+        #[inline(always)]
+        pub fn min(self, rhs: Self) -> Self {
+            self.zip(rhs, |a, b| if a < b { a } else { b })
+        }
+        #[inline(always)]
+        pub fn max(self, rhs: Self) -> Self {
+            self.zip(rhs, |a, b| if a > b { a } else { b })
+        }
+        // Drops the fraction, only exact while every lane fits in an i32
+        #[inline(always)]
+        pub fn trunc(self) -> Self {
+            Self([
+                self.0[0] as i32 as f32,
+                self.0[1] as i32 as f32,
+                self.0[2] as i32 as f32,
+                self.0[3] as i32 as f32,
+            ])
+        }
+        // Rounds towards zero, like `as`
+        #[inline(always)]
+        pub fn trunc_i32(self) -> [i32; 4] {
+            [
+                self.0[0] as i32,
+                self.0[1] as i32,
+                self.0[2] as i32,
+                self.0[3] as i32,
+            ]
+        }
+    }
+    impl std::ops::Add for F32x4 {
+        type Output = Self;
+        #[inline(always)]
+        fn add(self, rhs: Self) -> Self {
+            self.zip(rhs, |a, b| a + b)
+        }
+    }
+    impl std::ops::Sub for F32x4 {
+        type Output = Self;
+        #[inline(always)]
+        fn sub(self, rhs: Self) -> Self {
+            self.zip(rhs, |a, b| a - b)
+        }
+    }
+    impl std::ops::Mul for F32x4 {
+        type Output = Self;
+        #[inline(always)]
+        fn mul(self, rhs: Self) -> Self {
+            self.zip(rhs, |a, b| a * b)
+        }
+    }
+    impl std::ops::Div for F32x4 {
+        type Output = Self;
+        #[inline(always)]
+        fn div(self, rhs: Self) -> Self {
+            self.zip(rhs, |a, b| a / b)
+        }
+    }
+    impl Mask4 {
+        #[inline(always)]
+        pub fn and(self, rhs: Self) -> Self {
+            Self([
+                self.0[0] && rhs.0[0],
+                self.0[1] && rhs.0[1],
+                self.0[2] && rhs.0[2],
+                self.0[3] && rhs.0[3],
+            ])
+        }
+        // Lane n is bit n
+        #[inline(always)]
+        pub fn bitmask(self) -> u32 {
+            (self.0[0] as u32)
+                | (self.0[1] as u32) << 1
+                | (self.0[2] as u32) << 2
+                | (self.0[3] as u32) << 3
+        }
+    }
+}
+
+#[cfg(not(any(
+    all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "sse2"
+    ),
+    all(target_arch = "wasm32", target_feature = "simd128")
+)))]
+use scalar as backend;
+
+pub use backend::F32x4;
+
+#[cfg(test)]
+mod tests {
+    use super::{scalar, F32x4, LANES};
+
+    const WIDTH: usize = 62;
+    const HEIGHT: usize = 16;
+    const TEXTURE_SIZE: usize = 16;
+
+    // Runs the same per-pixel steps as raster::rasterize over a made up frame: perspective
+    // depth, the depth test, saturated lighting, texel lookup and compose. Lighting and
+    // texture coordinates run well past their ranges so the clamping gets hit too
+    macro_rules! shade_frame {
+        ($vector:ty) => {{
+            type V = $vector;
+            let texture: Vec<[u8; 3]> = (0..TEXTURE_SIZE * TEXTURE_SIZE)
+                .map(|indx| [(indx * 7) as u8, (indx * 13) as u8, (indx * 29) as u8])
+                .collect();
+            let mut frame = Vec::new();
+            for y in 0..HEIGHT {
+                for x in (0..WIDTH).step_by(LANES) {
+                    let lanes = std::cmp::min(LANES, WIDTH - x);
+                    let lane_x = V::splat(x as f32) + V::new(0.0, 1.0, 2.0, 3.0);
+                    let u = V::splat(0.1 + y as f32 * 0.031) + lane_x * V::splat(0.0137);
+                    let v = V::splat(0.7 - y as f32 * 0.029) - lane_x * V::splat(0.0091);
+                    let w = V::splat(1.0) - u - v;
+
+                    let z = V::splat(1.0) / V::interp(u, v, w, 0.2, 0.05, 0.11);
+                    let actual_z = (z - V::splat(0.5)) * V::splat(0.05);
+                    let old_z = V::from_array([0.4, 0.3, 0.9, 0.2]);
+                    let in_range = actual_z.ge(V::splat(0.0)).and(actual_z.le(V::splat(1.0)));
+                    let to_draw =
+                        actual_z.lt(old_z).and(in_range).bitmask() & V::first_lanes(lanes);
+
+                    let light = V::interp(u, v, w, 400.0, -30.0, 120.5).saturate();
+                    let indices = V::texel_indices(
+                        z * V::interp(u, v, w, 3.0, 29.5, -7.25),
+                        z * V::interp(u, v, w, -11.0, 4.5, 18.0),
+                        TEXTURE_SIZE as f32,
+                    );
+                    let texel = |channel: usize| {
+                        V::new(
+                            texture[indices[0] as usize & (TEXTURE_SIZE * TEXTURE_SIZE - 1)]
+                                [channel] as f32,
+                            texture[indices[1] as usize & (TEXTURE_SIZE * TEXTURE_SIZE - 1)]
+                                [channel] as f32,
+                            texture[indices[2] as usize & (TEXTURE_SIZE * TEXTURE_SIZE - 1)]
+                                [channel] as f32,
+                            texture[indices[3] as usize & (TEXTURE_SIZE * TEXTURE_SIZE - 1)]
+                                [channel] as f32,
+                        )
+                    };
+                    let r = texel(0).compose(light).trunc_i32();
+                    let g = texel(1).compose(light).trunc_i32();
+                    let b = texel(2).compose(light).trunc_i32();
+
+                    let new_z = actual_z.to_array();
+                    for lane in 0..LANES {
+                        if to_draw & (1 << lane) != 0 {
+                            frame.push(Some((new_z[lane].to_bits(), r[lane], g[lane], b[lane])));
+                        } else {
+                            frame.push(None);
+                        }
+                    }
+                }
+            }
+            frame
+        }};
+    }
+
+    #[test]
+    fn backends_shade_identical_frames() {
+        let native = shade_frame!(F32x4);
+        let scalar = shade_frame!(scalar::F32x4);
+        assert_eq!(native, scalar);
+        // Make sure the frame isn't all skipped or all drawn
+        assert!(native.iter().any(Option::is_some));
+        assert!(native.iter().any(Option::is_none));
+    }
+
+    #[test]
+    fn compose_matches_integer_compose() {
+        for texel in 0..=255 {
+            let light = F32x4::new(0.0, 1.0, 128.0, 255.0);
+            let composed = F32x4::splat(texel as f32).compose(light).trunc_i32();
+            for (lane, light) in [0, 1, 128, 255].iter().enumerate() {
+                assert_eq!(composed[lane], texel * light / 255);
+            }
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use super::gfx::{self, Color, Texture};
+use crate::camera::CameraData;
+use crate::rendermath::Vec3;
+
+// Up in the world is -y
+#[derive(Clone, Debug)]
+pub enum Sky {
+    Solid(Color),
+    Gradient {
+        zenith: Color,
+        horizon: Color,
+        ground: Color,
+    },
+    // Texture ids in CubeFace order: +x, +y, +z, -x, -y, -z
+    Skybox([u16; 6]),
+}
+// The sky as it was drawn this frame, for fogging towards whatever is behind each pixel
+pub struct SkyView<'a> {
+    pub sky: &'a Sky,
+    // None if the sky was a solid color or hasn't been drawn
+    pub rays: Option<&'a SkyRays>,
+    pub textures: &'a [Texture],
+}
+// World space view direction through every pixel center, worked out once per frame.
+// Direction through (x, y) is origin + x * dx + y * dy, not normalized
+#[derive(Clone, Copy, Debug)]
+pub struct SkyRays {
+    origin: Vec3,
+    dx: Vec3,
+    dy: Vec3,
+}
+
+impl Sky {
+    pub fn default_gradient() -> Self {
+        Self::Gradient {
+            zenith: Color::new(84, 140, 222, 255),
+            horizon: Color::new(196, 218, 240, 255),
+            ground: Color::new(142, 150, 160, 255),
+        }
+    }
+    pub fn color(&self, direction: &Vec3, textures: &[Texture]) -> Color {
+        match self {
+            Self::Solid(color) => *color,
+            Self::Gradient {
+                zenith,
+                horizon,
+                ground,
+            } => {
+                let up = -direction.y() / direction.norm();
+                if up >= 0.0 {
+                    horizon.lerp(*zenith, up.sqrt())
+                } else {
+                    // The ground color comes in quickly so the horizon stays a thin band
+                    horizon.lerp(*ground, (-4.0 * up).min(1.0))
+                }
+            }
+            Self::Skybox(faces) => {
+                let (x, y, z) = (direction.x(), direction.y(), direction.z());
+                let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+                // (face, u, v) with u and v from -1 to 1
+                let (face, u, v) = if ax >= ay && ax >= az {
+                    if x > 0.0 {
+                        (0, -z / ax, y / ax)
+                    } else {
+                        (3, z / ax, y / ax)
+                    }
+                } else if ay >= az {
+                    if y > 0.0 {
+                        (1, x / ay, -z / ay)
+                    } else {
+                        (4, x / ay, z / ay)
+                    }
+                } else if z > 0.0 {
+                    (2, x / az, y / az)
+                } else {
+                    (5, -x / az, y / az)
+                };
+                match textures.get(faces[face] as usize) {
+                    Some(texture) => texture.sample(
+                        (u + 1.0) * 0.5 * gfx::MTEXCOORD,
+                        (v + 1.0) * 0.5 * gfx::MTEXCOORD,
+                    ),
+                    None => Color::new(0, 0, 0, 255),
+                }
+            }
+        }
+    }
+}
+
+impl<'a> SkyView<'a> {
+    // None if there's no telling, a gradient or skybox that wasn't drawn this frame
+    pub fn color_at(&self, x: usize, y: usize) -> Option<Color> {
+        match (self.rays, self.sky) {
+            (Some(rays), sky) => Some(sky.color(&rays.direction(x, y), self.textures)),
+            (None, Sky::Solid(color)) => Some(*color),
+            (None, _) => None,
+        }
+    }
+}
+
+impl SkyRays {
+    // Uses the perspective mapping even for orthographic cameras, so the sky still moves
+    // when looking around
+    pub fn new(camera_data: &CameraData) -> Self {
+        let focal = (camera_data.scale.max(1) as f32) * camera_data.near;
+        let to_world = camera_data.orientation;
+        Self {
+            origin: to_world.rotate(Vec3::new(
+                (0.5 - camera_data.center_x) / focal,
+                (0.5 - camera_data.center_y) / focal,
+                1.0,
+            )),
+            dx: to_world.rotate(Vec3::new(1.0 / focal, 0.0, 0.0)),
+            dy: to_world.rotate(Vec3::new(0.0, 1.0 / focal, 0.0)),
+        }
+    }
+    #[inline(always)]
+    pub fn direction(&self, x: usize, y: usize) -> Vec3 {
+        self.origin + self.dx * x as f32 + self.dy * y as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::rendermath::Point3D;
+
+    #[test]
+    fn gradient_follows_view_direction() {
+        let sky = Sky::default_gradient();
+        let (zenith, horizon) = match &sky {
+            Sky::Gradient {
+                zenith, horizon, ..
+            } => (*zenith, *horizon),
+            _ => unreachable!(),
+        };
+        let up = sky.color(&Vec3::new(0.0, -3.0, 0.0), &[]);
+        let level = sky.color(&Vec3::new(1.0, 0.0, 0.0), &[]);
+        assert_eq!((up.r, up.g, up.b), (zenith.r, zenith.g, zenith.b));
+        assert_eq!(
+            (level.r, level.g, level.b),
+            (horizon.r, horizon.g, horizon.b)
+        );
+    }
+    #[test]
+    fn center_ray_is_camera_forward() {
+        let mut camera = Camera::new(
+            Point3D::from_euc_coords(0, 0, 0),
+            (0.0, 0.0),
+            std::f32::consts::FRAC_PI_2,
+            10,
+            281,
+            141,
+        );
+        camera.translate_look(0.4, -0.3);
+        let forward = camera.data.orientation.rotate(Vec3::new(0.0, 0.0, 1.0));
+        let center = SkyRays::new(&camera.data).direction(140, 70).normalize();
+        assert!((center - forward).norm() < 0.0001);
+    }
+}
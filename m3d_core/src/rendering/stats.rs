@@ -0,0 +1,92 @@
+use std::ops::AddAssign;
+
+// Current time in ms, only differences between calls matter
+pub type Clock = fn() -> f64;
+
+// Counters for the last frame, reset when the renderer is cleared
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStats {
+    pub bundles_visited: u32,
+    // Outside the frustum or behind the depth pyramid
+    pub bundles_culled: u32,
+    // Triangles submitted to draw_triface
+    pub faces_emitted: u32,
+    pub triangles_backface_culled: u32,
+    pub raster: RasterCounters,
+    pub timings: StageTimings,
+}
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RasterCounters {
+    pub pixels_shaded: u64,
+    // Covered pixels that were behind what was already drawn
+    pub z_test_failures: u64,
+}
+// ms spent in each part of GameState::render, all 0 without a clock. raster only covers the
+// tiled flush at the end of the frame, anything drawn before that (immediate mode, or flushes
+// for the depth pyramid) counts towards geometry
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StageTimings {
+    pub update: f64,
+    pub sky: f64,
+    pub geometry: f64,
+    pub raster: f64,
+    pub post: f64,
+    pub total: f64,
+}
+
+impl FrameStats {
+    pub const NAMES: [&'static str; 12] = [
+        "bundles visited",
+        "bundles culled",
+        "faces emitted",
+        "triangles backface culled",
+        "pixels shaded",
+        "z-test failures",
+        "update ms",
+        "sky ms",
+        "geometry ms",
+        "raster ms",
+        "post ms",
+        "total ms",
+    ];
+    // Same order as NAMES
+    pub fn values(&self) -> [f64; 12] {
+        [
+            self.bundles_visited as f64,
+            self.bundles_culled as f64,
+            self.faces_emitted as f64,
+            self.triangles_backface_culled as f64,
+            self.raster.pixels_shaded as f64,
+            self.raster.z_test_failures as f64,
+            self.timings.update,
+            self.timings.sky,
+            self.timings.geometry,
+            self.timings.raster,
+            self.timings.post,
+            self.timings.total,
+        ]
+    }
+}
+
+impl AddAssign for RasterCounters {
+    fn add_assign(&mut self, rhs: Self) {
+        self.pixels_shaded += rhs.pixels_shaded;
+        self.z_test_failures += rhs.z_test_failures;
+    }
+}
+
+// wasm32-unknown-unknown has no clock in std, the host has to pass one in there
+#[cfg(not(target_arch = "wasm32"))]
+pub fn system_clock() -> f64 {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_secs_f64() * 1000.0
+}
+pub fn default_clock() -> Option<Clock> {
+    #[cfg(not(target_arch = "wasm32"))]
+    return Some(system_clock);
+    #[cfg(target_arch = "wasm32")]
+    return None;
+}
@@ -0,0 +1,113 @@
+use crate::error::{Error, Result};
+
+// Texture packs are what build.rs bundles into images.txt, one texture per line in base 16.
+// Each line starts with a u16 palette size and that many RGBA colors, followed by runs of
+// (length, palette index). Lengths of 255 or more are written as 255 and then a u16
+struct Base16Reader<'a> {
+    to_read: &'a [u8],
+    index: usize,
+    line: usize,
+}
+impl<'a> Base16Reader<'a> {
+    fn new(input: &'a str, line: usize) -> Self {
+        Self {
+            to_read: input.as_bytes(),
+            index: 0,
+            line,
+        }
+    }
+    fn error(&self, reason: &'static str) -> Error {
+        Error::TextureDecode {
+            line: self.line,
+            reason,
+        }
+    }
+    fn lookup_char(&self, input: u8) -> Result<u8> {
+        match input {
+            b'0'..=b'9' => Ok(input - b'0'),
+            b'A'..=b'F' => Ok(input - b'A' + 10),
+            _ => Err(self.error("not a base 16 digit")),
+        }
+    }
+    fn read_u8(&mut self, reason: &'static str) -> Result<u8> {
+        if self.index + 2 > self.to_read.len() {
+            return Err(self.error(reason));
+        }
+        let to_return = 16 * self.lookup_char(self.to_read[self.index])?
+            + self.lookup_char(self.to_read[self.index + 1])?;
+        self.index += 2;
+        Ok(to_return)
+    }
+    fn read_u16(&mut self, reason: &'static str) -> Result<u16> {
+        let first = self.read_u8(reason)? as u16;
+        let second = self.read_u8(reason)? as u16;
+        Ok(first * 256 + second)
+    }
+    fn has_next(&self) -> bool {
+        self.index + 1 < self.to_read.len()
+    }
+}
+
+// RGBA bytes for every texture in the pack, ready for GameState::new
+pub fn decode(text: &str) -> Result<Vec<u8>> {
+    let mut texture_array: Vec<u8> = Vec::new();
+
+    for (indx, texture_line) in text.split('\n').enumerate() {
+        let texture_line = texture_line.trim_end();
+        if texture_line.is_empty() {
+            continue;
+        }
+        let mut texture_reader = Base16Reader::new(texture_line, indx + 1);
+        let num_colors = texture_reader.read_u16("missing palette size")?;
+        let mut colors: Vec<[u8; 4]> = Vec::with_capacity(num_colors as usize);
+        for _ in 0..num_colors {
+            let mut color = [0; 4];
+            for channel in color.iter_mut() {
+                *channel = texture_reader.read_u8("palette is cut short")?;
+            }
+            colors.push(color);
+        }
+
+        while texture_reader.has_next() {
+            let run_length = match texture_reader.read_u8("missing run length")? {
+                255 => texture_reader.read_u16("missing run length")?,
+                first => first as u16,
+            };
+            let run_color = texture_reader.read_u8("missing run color")? as usize;
+            let selected_color = colors
+                .get(run_color)
+                .ok_or_else(|| texture_reader.error("run color isn't in the palette"))?;
+
+            for _ in 0..run_length {
+                texture_array.extend_from_slice(selected_color);
+            }
+        }
+    }
+
+    Ok(texture_array)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_runs_and_reports_bad_lines() {
+        // Two colors, then 3 of the second and 1 of the first
+        let pack = "0002FF0000FF00FF00FF03010100";
+        assert_eq!(
+            decode(pack),
+            Ok(vec![
+                0, 255, 0, 255, 0, 255, 0, 255, 0, 255, 0, 255, 255, 0, 0, 255
+            ])
+        );
+        assert_eq!(
+            decode(&format!("{}\n0001FF0000FF0301", pack)),
+            Err(Error::TextureDecode {
+                line: 2,
+                reason: "run color isn't in the palette"
+            })
+        );
+        assert!(decode("00xz").is_err());
+    }
+}
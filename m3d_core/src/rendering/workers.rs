@@ -0,0 +1,115 @@
+// Worker pool for TileBins::draw on wasm32, where std can't spawn threads. The page starts
+// Web Workers on the same shared memory, sets up their stacks and thread locals and has each
+// one call run_worker (see client/worker.js), where it sleeps until there's work. The thread
+// handing out work is usually the page's main thread, which isn't allowed to block, so it
+// takes jobs alongside the workers and then spins until the last one is finished
+use std::arch::wasm32;
+use std::cell::UnsafeCell;
+use std::hint;
+use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
+
+// Bumped for every dispatch, idle workers sleep on it
+static GENERATION: AtomicU32 = AtomicU32::new(0);
+// The generation workers are allowed to join, CLOSED between dispatches
+static OPEN: AtomicU32 = AtomicU32::new(CLOSED);
+const CLOSED: u32 = u32::MAX;
+// Next job to hand out and how many have finished
+static NEXT: AtomicU32 = AtomicU32::new(0);
+static DONE: AtomicU32 = AtomicU32::new(0);
+// Workers that have joined the current generation and haven't left yet
+static ACTIVE: AtomicU32 = AtomicU32::new(0);
+static RUNNING: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Clone, Copy)]
+struct Job {
+    run: &'static (dyn Fn(usize) + Sync),
+    count: u32,
+}
+// Only written by for_each while no worker can be reading it, see there
+struct JobSlot(UnsafeCell<Option<Job>>);
+unsafe impl Sync for JobSlot {}
+static JOB: JobSlot = JobSlot(UnsafeCell::new(None));
+
+// Workers that have called run_worker so far
+pub fn running() -> usize {
+    RUNNING.load(SeqCst) as usize
+}
+
+// Calls run(0), run(1), ... run(count - 1) spread over this thread and the workers, and
+// returns once all of them have. Only one thread can be handing out work at a time
+pub fn for_each(count: usize, run: &(dyn Fn(usize) + Sync)) {
+    // The last dispatch didn't return until every worker had left, and OPEN isn't set to
+    // this generation until the job is written, so nothing is looking at JOB. The job
+    // doesn't really live for 'static, but it's cleared again before this returns
+    unsafe {
+        *JOB.0.get() = Some(Job {
+            run: std::mem::transmute::<&(dyn Fn(usize) + Sync), &'static (dyn Fn(usize) + Sync)>(
+                run,
+            ),
+            count: count as u32,
+        });
+    }
+    NEXT.store(0, SeqCst);
+    DONE.store(0, SeqCst);
+    let mut generation = GENERATION.load(SeqCst).wrapping_add(1);
+    if generation == CLOSED {
+        generation = 0;
+    }
+    OPEN.store(generation, SeqCst);
+    GENERATION.store(generation, SeqCst);
+    unsafe {
+        wasm32::memory_atomic_notify(GENERATION.as_ptr() as *mut i32, u32::MAX);
+    }
+
+    work(run, count as u32);
+    while DONE.load(SeqCst) < count as u32 {
+        hint::spin_loop();
+    }
+    // A worker only reads JOB if it joined before OPEN was closed, and it stays in ACTIVE
+    // until it's done with it
+    OPEN.store(CLOSED, SeqCst);
+    while ACTIVE.load(SeqCst) != 0 {
+        hint::spin_loop();
+    }
+    unsafe {
+        *JOB.0.get() = None;
+    }
+}
+
+// Takes jobs until there are none left
+fn work(run: &(dyn Fn(usize) + Sync), count: u32) {
+    loop {
+        let indx = NEXT.fetch_add(1, SeqCst);
+        if indx >= count {
+            return;
+        }
+        run(indx as usize);
+        DONE.fetch_add(1, SeqCst);
+    }
+}
+
+// Never returns. The calling worker has to have its own stack and thread locals already
+pub fn run_worker() -> ! {
+    RUNNING.fetch_add(1, SeqCst);
+    let mut seen = GENERATION.load(SeqCst);
+    loop {
+        // Returns straight away if the generation has already moved on, waking up for no
+        // reason is fine too
+        unsafe {
+            wasm32::memory_atomic_wait32(GENERATION.as_ptr() as *mut i32, seen as i32, -1);
+        }
+        let generation = GENERATION.load(SeqCst);
+        if generation == seen {
+            continue;
+        }
+        seen = generation;
+
+        ACTIVE.fetch_add(1, SeqCst);
+        if OPEN.load(SeqCst) == generation {
+            if let Some(job) = unsafe { *JOB.0.get() } {
+                work(job.run, job.count);
+            }
+        }
+        ACTIVE.fetch_sub(1, SeqCst);
+    }
+}
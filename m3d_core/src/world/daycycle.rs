@@ -0,0 +1,137 @@
+use crate::rendering::gfx::{Color, Light};
+use crate::rendering::sky::Sky;
+use crate::rendermath::Vec3;
+use std::f32::consts::PI;
+
+pub const HOURS_PER_DAY: f32 = 24.0;
+// 20 minutes of clock time per day
+pub const DEFAULT_DAY_LENGTH: usize = 20 * 60 * 1000;
+
+const SUN_COLOR: Color = Color::new(255, 255, 255, 255);
+const SUNSET_COLOR: Color = Color::new(255, 140, 70, 255);
+const MOON_COLOR: Color = Color::new(130, 150, 210, 255);
+const SUN_INTENSITY: f32 = 120.0;
+const MOON_INTENSITY: f32 = 45.0;
+
+// (zenith, horizon, ground) for full day, sunrise/sunset and night
+const DAY_SKY: [Color; 3] = [
+    Color::new(84, 140, 222, 255),
+    Color::new(196, 218, 240, 255),
+    Color::new(142, 150, 160, 255),
+];
+const SUNSET_SKY: [Color; 3] = [
+    Color::new(70, 90, 160, 255),
+    Color::new(245, 160, 110, 255),
+    Color::new(110, 95, 100, 255),
+];
+const NIGHT_SKY: [Color; 3] = [
+    Color::new(6, 8, 24, 255),
+    Color::new(28, 34, 62, 255),
+    Color::new(16, 18, 28, 255),
+];
+
+// Sun and moon go round in the x-y plane, rising at +x. The lights are the Far lights at
+// sun_light and moon_light in WorldData.lights
+pub struct DayCycle {
+    time: f32, // hours, 0 is midnight
+    pub day_length: usize,
+    pub paused: bool,
+    last_update: Option<usize>,
+    pub sun_light: usize,
+    pub moon_light: usize,
+}
+
+impl DayCycle {
+    pub fn new(time: f32, day_length: usize, sun_light: usize, moon_light: usize) -> Self {
+        Self {
+            time: time.rem_euclid(HOURS_PER_DAY),
+            day_length,
+            paused: false,
+            last_update: None,
+            sun_light,
+            moon_light,
+        }
+    }
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time.rem_euclid(HOURS_PER_DAY);
+    }
+    // curr_time is the same clock that's passed to GameState::render, in ms
+    pub fn advance(&mut self, curr_time: usize) {
+        if let Some(last_update) = self.last_update {
+            if !self.paused && self.day_length > 0 {
+                let elapsed = curr_time.saturating_sub(last_update) as f32;
+                self.set_time(self.time + HOURS_PER_DAY * elapsed / self.day_length as f32);
+            }
+        }
+        self.last_update = Some(curr_time);
+    }
+    // Direction towards the sun, the moon is always opposite
+    pub fn sun_direction(&self) -> Vec3 {
+        // 6:00 is sunrise, 12:00 is straight up (which is -y)
+        let angle = (self.time / HOURS_PER_DAY - 0.25) * 2.0 * PI;
+        Vec3::new(angle.cos(), -angle.sin(), 0.3).normalize()
+    }
+    // Sine of the sun's angle above the horizon
+    pub fn sun_elevation(&self) -> f32 {
+        -self.sun_direction().y()
+    }
+    // 1 when the sun is well up, 0 at night, with a short twilight either side of the horizon
+    fn daylight(&self) -> f32 {
+        ((self.sun_elevation() + 0.1) / 0.35).clamp(0.0, 1.0)
+    }
+    // How close the sun is to the horizon, peaks at sunrise and sunset
+    fn twilight(&self) -> f32 {
+        (1.0 - self.sun_elevation().abs() / 0.3).clamp(0.0, 1.0)
+    }
+    pub fn update_lights(&self, lights: &mut [Light]) {
+        let sun_direction = self.sun_direction();
+        let daylight = self.daylight();
+
+        if let Some(Light::Far(sun)) = lights.get_mut(self.sun_light) {
+            let color = SUN_COLOR.lerp(SUNSET_COLOR, self.twilight());
+            sun.set_color(color, (SUN_INTENSITY * daylight) as u32);
+            // Far light directions point the way the light travels
+            sun.set_direction(-sun_direction);
+        }
+        if let Some(Light::Far(moon)) = lights.get_mut(self.moon_light) {
+            moon.set_color(MOON_COLOR, (MOON_INTENSITY * (1.0 - daylight)) as u32);
+            moon.set_direction(sun_direction);
+        }
+    }
+    pub fn sky(&self) -> Sky {
+        let daylight = self.daylight();
+        let twilight = self.twilight();
+        let tint = |indx: usize| {
+            NIGHT_SKY[indx]
+                .lerp(DAY_SKY[indx], daylight)
+                .lerp(SUNSET_SKY[indx], twilight * 0.8)
+        };
+        Sky::Gradient {
+            zenith: tint(0),
+            horizon: tint(1),
+            ground: tint(2),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_wraps_and_sun_follows() {
+        let mut day_cycle = DayCycle::new(23.0, 24_000, 0, 1);
+        day_cycle.advance(1000);
+        day_cycle.advance(3000);
+        assert!((day_cycle.time() - 1.0).abs() < 0.0001);
+        assert!(day_cycle.sun_elevation() < 0.0);
+
+        day_cycle.set_time(12.0);
+        assert!(day_cycle.sun_elevation() > 0.9);
+        day_cycle.set_time(6.0);
+        assert!(day_cycle.sun_elevation().abs() < 0.0001);
+    }
+}
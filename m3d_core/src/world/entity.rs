@@ -0,0 +1,222 @@
+use crate::rendering::mesh::{Mesh, MeshInstance};
+use crate::rendermath::{Mat4, Point3D, Quaternion, RenderMatrices, Vec3};
+
+// Entities move in fixed steps so they behave the same at any frame rate
+pub const TICK_MS: usize = 50;
+// World units per tick, added to a particle's velocity every tick (+y is down)
+const GRAVITY: f32 = 0.08;
+// Radians per tick
+const ITEM_SPIN: f32 = 0.1;
+// After a long stall the ticks past this are dropped rather than all run in one frame
+const MAX_TICKS_PER_ADVANCE: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntityKind {
+    Mob,      // walks with its velocity and turns to face where it's going
+    Item,     // spins in place
+    Particle, // falls, and always faces the camera
+    Sign,     // doesn't move
+}
+
+#[derive(Clone, Debug)]
+pub struct Entity {
+    pub kind: EntityKind,
+    pub position: Vec3,          // center, in world units
+    pub orientation: Quaternion, // entity space -> world space
+    pub velocity: Vec3,          // world units per tick
+    pub half_extents: Vec3,      // bounding box around the center, in entity space
+    pub mesh: usize,             // index into Entities::meshes
+    pub ticks_left: Option<u32>, // removed when this runs out, None lives forever
+}
+
+impl Entity {
+    pub fn new(kind: EntityKind, position: Vec3, half_extents: Vec3, mesh: usize) -> Self {
+        Self {
+            kind,
+            position,
+            orientation: Quaternion::identity(),
+            velocity: Vec3::new(0.0, 0.0, 0.0),
+            half_extents,
+            mesh,
+            ticks_left: None,
+        }
+    }
+    pub fn tick(&mut self) {
+        match self.kind {
+            EntityKind::Mob => {
+                self.position = self.position + self.velocity;
+                if self.velocity.x() != 0.0 || self.velocity.z() != 0.0 {
+                    self.orientation =
+                        Quaternion::from_yaw_pitch(self.velocity.x().atan2(self.velocity.z()), 0.0);
+                }
+            }
+            EntityKind::Item => {
+                self.position = self.position + self.velocity;
+                self.orientation = Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), ITEM_SPIN)
+                    .mul(&self.orientation)
+                    .normalize();
+            }
+            EntityKind::Particle => {
+                self.velocity.set(1, self.velocity.y() + GRAVITY);
+                self.position = self.position + self.velocity;
+            }
+            EntityKind::Sign => {}
+        }
+        if let Some(ticks_left) = &mut self.ticks_left {
+            *ticks_left = ticks_left.saturating_sub(1);
+        }
+    }
+    pub fn expired(&self) -> bool {
+        self.ticks_left == Some(0)
+    }
+    // Entity space -> world space, particles are drawn with billboard_matrix instead
+    pub fn model_matrix(&self) -> Mat4 {
+        self.matrix_with(&self.orientation)
+    }
+    // Turned the same way as the camera, so a Mesh::quad faces it head on
+    pub fn billboard_matrix(&self, camera_orientation: &Quaternion) -> Mat4 {
+        self.matrix_with(camera_orientation)
+    }
+    fn matrix_with(&self, orientation: &Quaternion) -> Mat4 {
+        RenderMatrices::translation(self.position.x(), self.position.y(), self.position.z())
+            * orientation.to_matrix()
+    }
+    // In the same order as World::bundle_corners. A particle can face any way, so its box
+    // is a cube big enough for every turn
+    pub fn bounding_corners(&self) -> [Point3D; 8] {
+        let (orientation, half_extents) = match self.kind {
+            EntityKind::Particle => {
+                let radius = self.half_extents.norm();
+                (Quaternion::identity(), Vec3::new(radius, radius, radius))
+            }
+            _ => (self.orientation, self.half_extents),
+        };
+        let mut to_return = [Point3D::from_euc_coords(0, 0, 0); 8];
+        for (indx, corner) in to_return.iter_mut().enumerate() {
+            let sign = |bit: usize| if indx & bit == 0 { -1.0 } else { 1.0 };
+            let offset = Vec3::new(
+                sign(1) * half_extents.x(),
+                sign(2) * half_extents.y(),
+                sign(4) * half_extents.z(),
+            );
+            *corner = Point3D::from_vec3(self.position + orientation.rotate(offset));
+        }
+        to_return
+    }
+}
+
+// Everything in the world that isn't a block, ticked from GameState::render
+#[derive(Default)]
+pub struct Entities {
+    pub list: Vec<Entity>,
+    pub meshes: Vec<Mesh>,
+    // Never ticked
+    pub props: Vec<MeshInstance>,
+    last_update: Option<usize>,
+    leftover: usize, // ms since the last tick
+}
+
+impl Entities {
+    pub fn new() -> Self {
+        Self {
+            list: Vec::new(),
+            meshes: Vec::new(),
+            props: Vec::new(),
+            last_update: None,
+            leftover: 0,
+        }
+    }
+    // Returns the index to give Entity::new
+    pub fn add_mesh(&mut self, mesh: Mesh) -> usize {
+        self.meshes.push(mesh);
+        self.meshes.len() - 1
+    }
+    pub fn add(&mut self, entity: Entity) {
+        self.list.push(entity);
+    }
+    pub fn add_prop(&mut self, prop: MeshInstance) {
+        self.props.push(prop);
+    }
+    // curr_time is the same clock that's passed to GameState::render, in ms
+    pub fn advance(&mut self, curr_time: usize) {
+        if let Some(last_update) = self.last_update {
+            let elapsed = curr_time.saturating_sub(last_update) + self.leftover;
+            self.leftover = elapsed % TICK_MS;
+            for _ in 0..(elapsed / TICK_MS).min(MAX_TICKS_PER_ADVANCE) {
+                self.tick();
+            }
+        }
+        self.last_update = Some(curr_time);
+    }
+    pub fn tick(&mut self) {
+        for entity in self.list.iter_mut() {
+            entity.tick();
+        }
+        self.list.retain(|entity| !entity.expired());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_move_and_expire_entities() {
+        let mut entities = Entities::new();
+        let half_extents = Vec3::new(1.0, 1.0, 1.0);
+        let mut mob = Entity::new(EntityKind::Mob, Vec3::new(0.0, 0.0, 0.0), half_extents, 0);
+        mob.velocity = Vec3::new(1.0, 0.0, 0.0);
+        entities.add(mob);
+        let mut particle = Entity::new(
+            EntityKind::Particle,
+            Vec3::new(0.0, 0.0, 0.0),
+            half_extents,
+            0,
+        );
+        particle.ticks_left = Some(3);
+        entities.add(particle);
+        entities.add(Entity::new(
+            EntityKind::Sign,
+            Vec3::new(5.0, 5.0, 5.0),
+            half_extents,
+            0,
+        ));
+
+        // The first call only starts the clock, then 2.5 ticks go by and the half is kept
+        entities.advance(1000);
+        entities.advance(1000 + TICK_MS * 5 / 2);
+        assert_eq!(entities.list[0].position, Vec3::new(2.0, 0.0, 0.0));
+        let forward = entities.list[0]
+            .orientation
+            .rotate(Vec3::new(0.0, 0.0, 1.0));
+        assert!((forward - Vec3::new(1.0, 0.0, 0.0)).norm() < 0.001);
+        // Falling gets faster
+        assert!(entities.list[1].position.y() > 2.0 * GRAVITY);
+        assert_eq!(entities.list[2].position, Vec3::new(5.0, 5.0, 5.0));
+
+        assert_eq!(entities.list.len(), 3);
+        entities.advance(1000 + TICK_MS * 3);
+        assert_eq!(entities.list.len(), 2);
+        assert_eq!(entities.list[1].kind, EntityKind::Sign);
+    }
+
+    #[test]
+    fn bounding_corners_follow_orientation() {
+        let mut item = Entity::new(
+            EntityKind::Item,
+            Vec3::new(10.0, 0.0, 0.0),
+            Vec3::new(2.0, 1.0, 0.5),
+            0,
+        );
+        item.orientation =
+            Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), std::f32::consts::FRAC_PI_2);
+        // A quarter turn swaps which of x and z is long
+        for corner in item.bounding_corners().iter() {
+            assert!((corner.get(0) - 10.0).abs() < 0.5 + 0.001);
+            assert!((corner.get(2).abs() - 2.0).abs() < 0.001);
+        }
+        let corner = Point3D::from_vec3(Vec3::new(1.0, 2.0, 3.0));
+        let expected = item.position + item.orientation.rotate(Vec3::new(1.0, 2.0, 3.0));
+        assert!((corner.transform(&item.model_matrix()).xyz() - expected).norm() < 0.001);
+    }
+}
@@ -1,10 +1,14 @@
+use crate::rendering::gfx::FaceTexture;
+use crate::rendering::CubeFace;
+
 #[derive(Clone)]
 pub struct MaterialData {
     pub is_transparent: bool,
     pub is_solid: bool,
-    // Face ordering is the same as defined in
-    // CubeFace
-    pub texture: Option<[u16; 6]>,
+    // Sets of six faces ordered the same as CubeFace, each block draws one picked from its
+    // coordinates so it doesn't change between frames. List a set more than once to make it
+    // more likely. Empty for materials that aren't drawn
+    pub textures: &'static [[FaceTexture; 6]],
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -13,24 +17,114 @@ pub enum Material {
     Dirt,
     Grass,
 }
+
+const DIRT: FaceTexture = FaceTexture::new(1);
+const GRASS: FaceTexture = FaceTexture::new(0);
+// Turning the same texture round is enough to break up the repeats across a big area
+const DIRT_VARIANTS: [[FaceTexture; 6]; 4] = [
+    [DIRT; 6],
+    [DIRT.turned(1); 6],
+    [DIRT.turned(2); 6],
+    [DIRT.turned(3); 6],
+];
+// Only the top and bottom turn, the sides stay upright for the overhang
+const GRASS_VARIANTS: [[FaceTexture; 6]; 4] = [
+    grass_faces(0),
+    grass_faces(1),
+    grass_faces(2),
+    grass_faces(3),
+];
+const fn grass_faces(quarter_turns: u8) -> [FaceTexture; 6] {
+    let mut to_return = [GRASS; 6];
+    to_return[CubeFace::PlusY as usize] = GRASS.turned(quarter_turns);
+    to_return[CubeFace::MinusY as usize] = GRASS.turned(quarter_turns);
+    to_return
+}
+
+impl MaterialData {
+    // x, y and z are block coordinates
+    pub fn faces(&self, x: usize, y: usize, z: usize) -> Option<&'static [FaceTexture; 6]> {
+        if self.textures.is_empty() {
+            return None;
+        }
+        Some(&self.textures[variant_hash(x, y, z) as usize % self.textures.len()])
+    }
+}
+
+// Scrambles the coordinates so neighbouring blocks don't pick neighbouring variants
+fn variant_hash(x: usize, y: usize, z: usize) -> u32 {
+    let mut hash = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x7feb_352d);
+    hash ^= hash >> 15;
+    hash
+}
+
 impl Material {
+    pub const ALL: [Self; 3] = [Self::Empty, Self::Dirt, Self::Grass];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "empty" => Some(Self::Empty),
+            "dirt" => Some(Self::Dirt),
+            "grass" => Some(Self::Grass),
+            _ => None,
+        }
+    }
     pub const fn data(self) -> MaterialData {
         match self {
             Self::Empty => MaterialData {
                 is_transparent: true,
                 is_solid: false,
-                texture: None,
+                textures: &[],
             },
             Self::Dirt => MaterialData {
                 is_transparent: false,
                 is_solid: true,
-                texture: Some([1, 1, 1, 1, 1, 1]),
+                textures: &DIRT_VARIANTS,
             },
             Self::Grass => MaterialData {
                 is_transparent: false,
                 is_solid: true,
-                texture: Some([0, 0, 0, 0, 0, 0]),
+                textures: &GRASS_VARIANTS,
             },
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variants_are_stable_and_all_used() {
+        let data = Material::Dirt.data();
+        assert_eq!(data.faces(3, 4, 5), data.faces(3, 4, 5));
+        let mut used = [false; 4];
+        for x in 0..16 {
+            for z in 0..16 {
+                let faces = data.faces(x, 0, z).unwrap();
+                used[faces[0].quarter_turns as usize] = true;
+            }
+        }
+        assert_eq!(used, [true; 4]);
+        assert!(Material::Empty.data().faces(0, 0, 0).is_none());
+    }
+
+    #[test]
+    fn face_textures_turn_flip_and_crop() {
+        let texture = FaceTexture::new(0);
+        assert_eq!(texture.uv(0.25, 0.0), (0.25, 0.0));
+        // A quarter turn clockwise brings the texture's bottom left to the face's top left
+        assert_eq!(texture.turned(1).uv(0.0, 0.0), (0.0, 1.0));
+        assert_eq!(texture.turned(1).uv(1.0, 0.0), (0.0, 0.0));
+        assert_eq!(texture.turned(3).turned(1), texture);
+        assert_eq!(texture.flipped().uv(0.25, 0.5), (0.75, 0.5));
+        assert_eq!(
+            texture.sub_rect(0.5, 0.0, 1.0, 0.25).uv(0.5, 1.0),
+            (0.75, 0.25)
+        );
+    }
+}
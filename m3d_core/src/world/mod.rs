@@ -1,13 +1,23 @@
+pub mod daycycle;
+pub mod entity;
 pub mod material;
+pub mod worldfile;
 
+pub use daycycle::DayCycle;
+pub use entity::{Entities, Entity, EntityKind};
 pub use material::Material;
 
 use crate::camera::{Camera, CameraCache, UNITS_PER_BLOCK};
+use crate::error::{Error, Result};
 use crate::rendering::gfx::{Color, Light};
-use crate::rendering::{CubeFace, Renderer};
-use crate::rendermath::{Point3D, Vector};
+use crate::rendering::mesh::Mesh;
+use crate::rendering::{debug, CubeFace, Renderer};
+use crate::rendermath::{Mat4, Point3D, Vec3};
 
 const BLOCK_BUNDLE_SIZE: usize = 16;
+// Bundles drawn before the depth pyramid is built, everything after that is tested against it.
+// The nearest bundles hide the most, and building it once keeps the tiles flushed only once
+const OCCLUDER_BUNDLES: usize = 8;
 
 #[derive(Clone, Copy, Debug)]
 pub enum Shape {
@@ -40,6 +50,7 @@ pub struct World {
 pub struct WorldData {
     pub bundles: Vec<BlockBundle>,
     pub lights: Vec<Light>,
+    pub entities: Entities,
     pub world_bundle_size: usize,
     pub world_bundle_squared: usize,
     pub offset_x: usize,
@@ -49,27 +60,12 @@ pub struct WorldData {
 
 impl World {
     pub fn new(camera: Camera, world_bundle_size: usize) -> Self {
-        let offset_x = camera.data.position.x_coord() as usize / BLOCK_BUNDLE_SIZE;
-        let offset_y = camera.data.position.y_coord() as usize / BLOCK_BUNDLE_SIZE;
-        let offset_z = camera.data.position.z_coord() as usize / BLOCK_BUNDLE_SIZE;
+        let mut data = WorldData::new(world_bundle_size);
+        data.offset_x = camera.data.position.x_coord() as usize / BLOCK_BUNDLE_SIZE;
+        data.offset_y = camera.data.position.y_coord() as usize / BLOCK_BUNDLE_SIZE;
+        data.offset_z = camera.data.position.z_coord() as usize / BLOCK_BUNDLE_SIZE;
 
-        let num_bundles = world_bundle_size * world_bundle_size * world_bundle_size;
-        let mut bundles: Vec<BlockBundle> = Vec::with_capacity(num_bundles);
-        for _ in 0..num_bundles {
-            bundles.push(BlockBundle::new());
-        }
-        Self {
-            camera,
-            data: WorldData {
-                bundles,
-                lights: Vec::new(),
-                world_bundle_size,
-                world_bundle_squared: world_bundle_size * world_bundle_size,
-                offset_x,
-                offset_y,
-                offset_z,
-            },
-        }
+        Self { camera, data }
     }
     fn full_faces_list(
         bundle: &BlockBundle,
@@ -124,9 +120,17 @@ impl World {
         match block {
             Block::Full(block_data) => {
                 let material_data = block_data.material.data();
-                if material_data.is_transparent || material_data.texture.is_none() {
+                if material_data.is_transparent {
                     return;
                 }
+                let textures = match material_data.faces(
+                    base_x as usize / UNITS_PER_BLOCK + dx,
+                    base_y as usize / UNITS_PER_BLOCK + dy,
+                    base_z as usize / UNITS_PER_BLOCK + dz,
+                ) {
+                    Some(textures) => textures,
+                    None => return,
+                };
 
                 let full_faces = Self::full_faces_list(bundle, dx, dy, dz);
                 if full_faces[0].is_none() {
@@ -140,9 +144,9 @@ impl World {
                     (dz * UNITS_PER_BLOCK) as f32 + halfsides[2] + base_z,
                 );
                 let transform =
-                    &CameraCache::reverse_frustum(&mut camera.cache.reverse_frustum, &camera.data);
+                    *CameraCache::reverse_frustum(&mut camera.cache.reverse_frustum, &camera.data);
 
-                let calculate_lighting = |point: &Point3D, normal: &Vector| {
+                let calculate_lighting = |point: &Point3D, normal: &Vec3| {
                     let mut to_return = Color::zero();
                     for light in lights {
                         to_return.add(light.intensity(point, normal, 1));
@@ -160,7 +164,7 @@ impl World {
                             &halfsides,
                             &transform,
                             &calculate_lighting,
-                            material_data.texture.unwrap()[face as usize],
+                            &textures[face as usize],
                         );
                     } else {
                         break;
@@ -170,6 +174,19 @@ impl World {
             _ => {}
         }
     }
+    fn bundle_corners(x: f32, y: f32, z: f32, bundle_size: f32) -> [Point3D; 8] {
+        [
+            Point3D::from_euc_coords_float(x, y, z),
+            Point3D::from_euc_coords_float(bundle_size + x, y, z),
+            Point3D::from_euc_coords_float(x, bundle_size + y, z),
+            Point3D::from_euc_coords_float(bundle_size + x, bundle_size + y, z),
+            Point3D::from_euc_coords_float(x, y, bundle_size + z),
+            Point3D::from_euc_coords_float(bundle_size + x, y, bundle_size + z),
+            Point3D::from_euc_coords_float(x, bundle_size + y, bundle_size + z),
+            Point3D::from_euc_coords_float(bundle_size + x, bundle_size + y, bundle_size + z),
+        ]
+    }
+    // Returns whether anything in the bundle was sent to the renderer
     pub fn draw_bundle(
         world_data: &WorldData,
         camera: &mut Camera,
@@ -177,7 +194,7 @@ impl World {
         v: usize,
         w: usize,
         renderer: &mut Renderer,
-    ) {
+    ) -> bool {
         let bundle_size = (BLOCK_BUNDLE_SIZE * UNITS_PER_BLOCK) as f32;
 
         let x = (u + world_data.offset_x) as f32 * bundle_size;
@@ -190,47 +207,31 @@ impl World {
             w + world_data.offset_z,
         ) {
             if bundle.to_draw <= 0 {
-                return;
+                return false;
             }
+            renderer.stats_mut().bundles_visited += 1;
 
-            if !(Camera::in_frustum(
-                &Point3D::from_euc_coords_float(x, y, z),
-                &mut camera.cache,
-                &camera.data,
-            ) || Camera::in_frustum(
-                &Point3D::from_euc_coords_float(bundle_size + x, y, z),
-                &mut camera.cache,
-                &camera.data,
-            ) || Camera::in_frustum(
-                &Point3D::from_euc_coords_float(bundle_size + x, bundle_size + y, z),
-                &mut camera.cache,
-                &camera.data,
-            ) || Camera::in_frustum(
-                &Point3D::from_euc_coords_float(bundle_size + x, y, bundle_size + z),
-                &mut camera.cache,
-                &camera.data,
-            ) || Camera::in_frustum(
-                &Point3D::from_euc_coords_float(x, bundle_size + y, z),
-                &mut camera.cache,
-                &camera.data,
-            ) || Camera::in_frustum(
-                &Point3D::from_euc_coords_float(x, bundle_size + y, z),
-                &mut camera.cache,
-                &camera.data,
-            ) || Camera::in_frustum(
-                &Point3D::from_euc_coords_float(bundle_size + x, bundle_size + y, z),
-                &mut camera.cache,
-                &camera.data,
-            ) || Camera::in_frustum(
-                &Point3D::from_euc_coords_float(x, bundle_size + y, bundle_size + z),
-                &mut camera.cache,
-                &camera.data,
-            ) || Camera::in_frustum(
-                &Point3D::from_euc_coords_float(bundle_size + x, bundle_size + y, bundle_size + z),
-                &mut camera.cache,
-                &camera.data,
-            )) {
-                return;
+            let corners = Self::bundle_corners(x, y, z, bundle_size);
+            if !corners
+                .iter()
+                .any(|corner| Camera::in_frustum(corner, &mut camera.cache, &camera.data))
+            {
+                renderer.stats_mut().bundles_culled += 1;
+                return false;
+            }
+            if let Some(bounds) = Camera::screen_bounds(&corners, &mut camera.cache, &camera.data) {
+                if renderer.is_occluded(bounds) {
+                    renderer.stats_mut().bundles_culled += 1;
+                    return false;
+                }
+            }
+            if renderer.debug_bundles() {
+                renderer.write_box(
+                    &corners,
+                    &mut camera.cache,
+                    &camera.data,
+                    debug::BUNDLE_BOUNDS_COLOR,
+                );
             }
 
             for indz in 0..BLOCK_BUNDLE_SIZE {
@@ -251,19 +252,152 @@ impl World {
                     }
                 }
             }
+            return true;
         }
+        false
     }
-    pub fn draw_all(world_data: &WorldData, camera: &mut Camera, renderer: &mut Renderer) {
+    // Nearest bundle first, so the depth pyramid fills up with good occluders early
+    fn bundle_order(world_data: &WorldData, camera: &Camera) -> Vec<(usize, usize, usize)> {
+        let bundle_size = (BLOCK_BUNDLE_SIZE * UNITS_PER_BLOCK) as f32;
+        let camera_position = camera.data.position.xyz();
+
+        let mut to_return = Vec::with_capacity(world_data.bundles.len());
         for indx in 0..world_data.world_bundle_size {
             for indy in 0..world_data.world_bundle_size {
                 for indz in 0..world_data.world_bundle_size {
-                    Self::draw_bundle(world_data, camera, indx, indy, indz, renderer);
+                    to_return.push((indx, indy, indz));
                 }
             }
         }
+        let distance = |&(u, v, w): &(usize, usize, usize)| {
+            let center = Vec3::new(
+                ((u + world_data.offset_x) as f32 + 0.5) * bundle_size,
+                ((v + world_data.offset_y) as f32 + 0.5) * bundle_size,
+                ((w + world_data.offset_z) as f32 + 0.5) * bundle_size,
+            );
+            (center - camera_position).norm2()
+        };
+        to_return.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+        to_return
+    }
+    // After the bundles, so entities behind terrain get culled by the depth pyramid
+    pub fn draw_entities(world_data: &WorldData, camera: &mut Camera, renderer: &mut Renderer) {
+        let transform =
+            *CameraCache::reverse_frustum(&mut camera.cache.reverse_frustum, &camera.data);
+        let lights = &world_data.lights;
+        let calculate_lighting = |point: &Point3D, normal: &Vec3| {
+            let mut to_return = Color::zero();
+            for light in lights {
+                to_return.add(light.intensity(point, normal, 1));
+            }
+            to_return
+        };
+
+        let entities = &world_data.entities;
+        for prop in entities.props.iter() {
+            if let Some(mesh) = entities.meshes.get(prop.mesh) {
+                Self::draw_mesh_instance(
+                    camera,
+                    renderer,
+                    mesh,
+                    &prop.transform,
+                    &prop.bounding_corners(mesh),
+                    &transform,
+                    &calculate_lighting,
+                );
+            }
+        }
+        for entity in entities.list.iter() {
+            if let Some(mesh) = entities.meshes.get(entity.mesh) {
+                let model = match entity.kind {
+                    EntityKind::Particle => entity.billboard_matrix(&camera.data.orientation),
+                    _ => entity.model_matrix(),
+                };
+                Self::draw_mesh_instance(
+                    camera,
+                    renderer,
+                    mesh,
+                    &model,
+                    &entity.bounding_corners(),
+                    &transform,
+                    &calculate_lighting,
+                );
+            }
+        }
+    }
+    fn draw_mesh_instance<LightingCalculator: Fn(&Point3D, &Vec3) -> Color>(
+        camera: &mut Camera,
+        renderer: &mut Renderer,
+        mesh: &Mesh,
+        model: &Mat4,
+        corners: &[Point3D; 8],
+        transform: &Mat4,
+        calculate_lighting: &LightingCalculator,
+    ) {
+        if Camera::box_outside_frustum(corners, &mut camera.cache, &camera.data) {
+            return;
+        }
+        if let Some(bounds) = Camera::screen_bounds(corners, &mut camera.cache, &camera.data) {
+            if renderer.is_occluded(bounds) {
+                return;
+            }
+        }
+        if renderer.debug_bundles() {
+            renderer.write_box(
+                corners,
+                &mut camera.cache,
+                &camera.data,
+                debug::ENTITY_BOUNDS_COLOR,
+            );
+        }
+        renderer.draw_mesh(
+            &mut camera.cache.to_screen_space,
+            &camera.data,
+            mesh,
+            model,
+            transform,
+            calculate_lighting,
+        );
+    }
+    pub fn draw_all(world_data: &WorldData, camera: &mut Camera, renderer: &mut Renderer) {
+        renderer.set_lights(
+            &world_data.lights,
+            CameraCache::reverse_frustum(&mut camera.cache.reverse_frustum, &camera.data),
+        );
+        let mut drawn = 0;
+        for (indx, indy, indz) in Self::bundle_order(world_data, camera) {
+            if Self::draw_bundle(world_data, camera, indx, indy, indz, renderer) {
+                drawn += 1;
+                if drawn == OCCLUDER_BUNDLES {
+                    renderer.build_depth_pyramid();
+                }
+            }
+        }
+        Self::draw_entities(world_data, camera, renderer);
+        if renderer.debug_lights() {
+            renderer.write_light_markers(&world_data.lights, &mut camera.cache, &camera.data);
+        }
     }
 }
 impl WorldData {
+    // world_bundle_size bundles along each axis, starting at the origin
+    pub fn new(world_bundle_size: usize) -> Self {
+        let num_bundles = world_bundle_size * world_bundle_size * world_bundle_size;
+        let mut bundles: Vec<BlockBundle> = Vec::with_capacity(num_bundles);
+        for _ in 0..num_bundles {
+            bundles.push(BlockBundle::new());
+        }
+        Self {
+            bundles,
+            lights: Vec::new(),
+            entities: Entities::new(),
+            world_bundle_size,
+            world_bundle_squared: world_bundle_size * world_bundle_size,
+            offset_x: 0,
+            offset_y: 0,
+            offset_z: 0,
+        }
+    }
     pub fn get_bundle(
         &self,
         bundle_x: usize,
@@ -331,18 +465,22 @@ impl WorldData {
         );
         Some((block, bundle))
     }
-    pub fn set(&mut self, x: usize, y: usize, z: usize, value: Block) {
-        if let Some(bundle) = self.get_bundle_mut(
+    pub fn set(&mut self, x: usize, y: usize, z: usize, value: Block) -> Result<()> {
+        match self.get_bundle_mut(
             x / BLOCK_BUNDLE_SIZE,
             y / BLOCK_BUNDLE_SIZE,
             z / BLOCK_BUNDLE_SIZE,
         ) {
-            bundle.set(
-                x % BLOCK_BUNDLE_SIZE,
-                y % BLOCK_BUNDLE_SIZE,
-                z % BLOCK_BUNDLE_SIZE,
-                value,
-            )
+            Some(bundle) => {
+                bundle.set(
+                    x % BLOCK_BUNDLE_SIZE,
+                    y % BLOCK_BUNDLE_SIZE,
+                    z % BLOCK_BUNDLE_SIZE,
+                    value,
+                );
+                Ok(())
+            }
+            None => Err(Error::OutOfBounds { x, y, z }),
         }
     }
 }
@@ -368,6 +506,15 @@ impl Block {
 }
 
 impl Shape {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "block" => Some(Self::Block),
+            "vplank" => Some(Self::VPlank),
+            "hplank" => Some(Self::HPlank),
+            "mini" => Some(Self::Mini),
+            _ => None,
+        }
+    }
     pub fn halfsides(&self) -> [f32; 3] {
         match self {
             Self::Block => [2.0, 2.0, 2.0],
@@ -0,0 +1,178 @@
+use super::{Block, BlockData, Material, Shape, WorldData};
+use crate::error::{Error, Result};
+use crate::rendering::gfx::{Color, FarLight, Light, NearLight};
+use crate::rendermath::{Point3D, Vec3};
+
+const HEADER: &str = "m3d_world 1";
+const DEFAULT_SIZE: usize = 10;
+// All size^3 bundles are allocated up front, so a typo shouldn't be able to ask for more
+// memory than there is. 16 is 256 blocks along each side and tens of MB already
+const MAX_SIZE: usize = 16;
+
+// Plain text, one command per line after the header, # starts a comment:
+//   size n                                 bundles along each axis (1 to 16), goes first
+//   block x y z material [shape]           coordinates in blocks
+//   fill x0 y0 z0 x1 y1 z1 material [shape]  every block in the box, corners included
+//   near_light r g b intensity x y z       position in world units
+//   far_light r g b intensity dx dy dz     direction the light travels
+// Materials and shapes are lower case names, shape defaults to block
+pub fn import(input: &str) -> Result<WorldData> {
+    let mut lines = input
+        .lines()
+        .enumerate()
+        .map(|(indx, line)| (indx + 1, line.split('#').next().unwrap_or("").trim()))
+        .filter(|(_, line)| !line.is_empty());
+
+    match lines.next() {
+        Some((_, HEADER)) => {}
+        Some((line, _)) => return Err(parse_error(line, "expected the m3d_world 1 header")),
+        None => return Err(parse_error(1, "empty world file")),
+    }
+
+    let mut world_data: Option<WorldData> = None;
+    for (line, text) in lines {
+        let fields = text.split_whitespace().collect::<Vec<&str>>();
+        let command = fields[0];
+        let args = &fields[1..];
+
+        if command == "size" {
+            if world_data.is_some() {
+                return Err(parse_error(line, "size has to come before everything else"));
+            }
+            let size = parse_args::<usize>(line, args, 1)?[0];
+            if size == 0 {
+                return Err(parse_error(line, "size has to be at least 1"));
+            }
+            if size > MAX_SIZE {
+                return Err(parse_error(line, "size is too big"));
+            }
+            world_data = Some(WorldData::new(size));
+            continue;
+        }
+        let world_data = world_data.get_or_insert_with(|| WorldData::new(DEFAULT_SIZE));
+
+        match command {
+            "block" | "fill" => {
+                let corners = if command == "block" { 3 } else { 6 };
+                if args.len() < corners + 1 || args.len() > corners + 2 {
+                    return Err(parse_error(line, "wrong number of fields"));
+                }
+                let coords = parse_args::<usize>(line, &args[..corners], corners)?;
+                let material = Material::from_name(args[corners])
+                    .ok_or_else(|| parse_error(line, "unknown material"))?;
+                let shape = match args.get(corners + 1) {
+                    Some(name) => {
+                        Shape::from_name(name).ok_or_else(|| parse_error(line, "unknown shape"))?
+                    }
+                    None => Shape::Block,
+                };
+                let (min, max) = if command == "block" {
+                    (&coords[..3], &coords[..3])
+                } else {
+                    (&coords[..3], &coords[3..])
+                };
+                for x in min[0].min(max[0])..=min[0].max(max[0]) {
+                    for y in min[1].min(max[1])..=min[1].max(max[1]) {
+                        for z in min[2].min(max[2])..=min[2].max(max[2]) {
+                            let block = match material {
+                                Material::Empty => Block::Empty,
+                                _ => Block::Full(BlockData::new(shape, material)),
+                            };
+                            world_data
+                                .set(x, y, z, block)
+                                .map_err(|_| parse_error(line, "block is outside the world"))?;
+                        }
+                    }
+                }
+            }
+            "near_light" | "far_light" => {
+                let values = parse_args::<f32>(line, args, 7)?;
+                let channel = |value: f32| value.clamp(0.0, 255.0) as u8;
+                let color = Color::new(
+                    channel(values[0]),
+                    channel(values[1]),
+                    channel(values[2]),
+                    255,
+                );
+                let intensity = values[3].max(0.0) as u32;
+                world_data.lights.push(if command == "near_light" {
+                    Light::Near(NearLight::new(
+                        color,
+                        intensity,
+                        Point3D::from_euc_coords_float(values[4], values[5], values[6]),
+                    ))
+                } else {
+                    Light::Far(FarLight::new(
+                        color,
+                        intensity,
+                        Vec3::new(values[4], values[5], values[6]),
+                    ))
+                });
+            }
+            _ => return Err(parse_error(line, "unknown command")),
+        }
+    }
+    Ok(world_data.unwrap_or_else(|| WorldData::new(DEFAULT_SIZE)))
+}
+
+fn parse_error(line: usize, reason: &'static str) -> Error {
+    Error::WorldParse { line, reason }
+}
+fn parse_args<T: std::str::FromStr>(line: usize, args: &[&str], count: usize) -> Result<Vec<T>> {
+    if args.len() != count {
+        return Err(parse_error(line, "wrong number of fields"));
+    }
+    args.iter()
+        .map(|arg| {
+            arg.parse::<T>()
+                .map_err(|_| parse_error(line, "couldn't parse a number"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imports_blocks_and_lights() {
+        let world_data = import(
+            "m3d_world 1
+            # a 3x3 floor with a post in the middle
+            size 2
+            fill 0 1 0 2 1 2 grass
+            block 1 0 1 dirt vplank
+            near_light 255 0 0 3500 5 5 -10
+            far_light 255 255 255 120 0 1 0",
+        )
+        .unwrap();
+        assert_eq!(world_data.world_bundle_size, 2);
+        assert_eq!(world_data.lights.len(), 2);
+        assert!(world_data.get(2, 1, 2).unwrap().0.is_occluder());
+        assert!(matches!(
+            world_data.get(1, 0, 1).unwrap().0,
+            Block::Full(BlockData {
+                shape: Shape::VPlank,
+                material: Material::Dirt
+            })
+        ));
+        assert!(!world_data.get(3, 1, 3).unwrap().0.is_occluder());
+
+        assert_eq!(
+            import("m3d_world 1\nsize 1\nblock 16 0 0 dirt").err(),
+            Some(Error::WorldParse {
+                line: 3,
+                reason: "block is outside the world"
+            })
+        );
+        assert_eq!(
+            import("m3d_world 1\nsize 1000").err(),
+            Some(Error::WorldParse {
+                line: 2,
+                reason: "size is too big"
+            })
+        );
+        assert!(import("m3d_world 1\nblock 0 0 0 cheese").is_err());
+        assert!(import("block 0 0 0 dirt").is_err());
+    }
+}
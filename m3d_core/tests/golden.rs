@@ -0,0 +1,189 @@
+use m3d_core::imagefile;
+use m3d_core::rendering::raster::RasterMode;
+use m3d_core::rendering::texturepack;
+use m3d_core::world::worldfile;
+use m3d_core::GameState;
+use std::{env, fs};
+
+// Fixed scenes rendered headlessly and compared against the reference frames in tests/golden.
+// Run with M3D_UPDATE_GOLDEN=1 to write new references after a change that's meant to alter
+// the output, and look at them before committing. On a mismatch the frame that was rendered
+// and a diff (reference in gray, differing pixels in red) are written to the test tmp dir
+
+const WIDTH: usize = 224;
+const HEIGHT: usize = 112;
+// Per channel, small enough to catch a texture or lighting change but not float noise
+const CHANNEL_TOLERANCE: u8 = 4;
+// Pixels allowed past the tolerance, for edges that land on the other side of a pixel center
+const MAX_BAD_PIXELS: usize = WIDTH * HEIGHT / 500;
+
+struct Scene {
+    name: &'static str,
+    // None is GameState::new's floor
+    world: Option<&'static str>,
+    position: (f32, f32, f32),
+    look: (f32, f32),
+    time: f32,
+}
+
+const SINGLE_CUBE: Scene = Scene {
+    name: "single_cube",
+    world: Some(
+        "m3d_world 1
+        size 1
+        block 5 5 5 dirt
+        near_light 255 255 255 3000 14 10 12",
+    ),
+    position: (18.0, 16.0, 14.0),
+    look: (0.5, -0.6),
+    time: 12.0,
+};
+const CHECKER_FLOOR: Scene = Scene {
+    name: "checker_floor",
+    world: None,
+    position: (290.0, -50.0, 35.0),
+    look: (0.3, -1.0),
+    time: 12.0,
+};
+// At midnight so the near light is the only thing lighting the walls
+const LIT_CORNER: Scene = Scene {
+    name: "lit_corner",
+    world: Some(
+        "m3d_world 1
+        size 1
+        fill 0 8 0 9 8 9 grass
+        fill 0 0 9 9 7 9 dirt
+        fill 9 0 0 9 7 8 dirt
+        near_light 255 200 120 2500 30 24 30",
+    ),
+    position: (8.0, 20.0, 8.0),
+    look: (0.8, -0.3),
+    time: 0.0,
+};
+// The wall runs from behind the near plane to well past it
+const NEAR_PLANE_CLIP: Scene = Scene {
+    name: "near_plane_clip",
+    world: Some(
+        "m3d_world 1
+        size 1
+        fill 0 0 10 15 15 10 dirt
+        near_light 255 255 255 2500 30 30 30",
+    ),
+    position: (30.0, 30.0, 37.0),
+    look: (0.5, 0.0),
+    time: 12.0,
+};
+
+fn render(scene: &Scene) -> Vec<u8> {
+    let texture_string = fs::read_to_string("images.txt").expect("Cannot open images.txt");
+    let texture_array = texturepack::decode(&texture_string).unwrap();
+    let mut game_state = match scene.world {
+        Some(world) => GameState::with_world(
+            WIDTH,
+            HEIGHT,
+            &texture_array,
+            worldfile::import(world).unwrap(),
+        ),
+        None => GameState::new(WIDTH, HEIGHT, &texture_array),
+    }
+    .unwrap();
+    game_state.set_raster_mode(RasterMode::Immediate);
+    game_state.set_day_length(0);
+    game_state.set_time_of_day(scene.time);
+    let (x, y, z) = scene.position;
+    game_state.set_camera_position(x, y, z);
+    game_state.set_camera_look(scene.look.0, scene.look.1);
+    game_state.render(0);
+    game_state.get_pixels().to_vec()
+}
+
+// Number of pixels with a channel off by more than the tolerance, and an image showing them
+fn compare(reference: &[u8], actual: &[u8]) -> (usize, Vec<u8>) {
+    let mut bad_pixels = 0;
+    let mut diff = Vec::with_capacity(reference.len());
+    for (expected, pixel) in reference.chunks_exact(4).zip(actual.chunks_exact(4)) {
+        let difference = (0..3)
+            .map(|channel| expected[channel].abs_diff(pixel[channel]))
+            .max()
+            .unwrap();
+        if difference > CHANNEL_TOLERANCE {
+            bad_pixels += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            let gray = (expected[0] as u16 + expected[1] as u16 + expected[2] as u16) / 6;
+            diff.extend_from_slice(&[gray as u8, gray as u8, gray as u8, 255]);
+        }
+    }
+    (bad_pixels, diff)
+}
+
+fn check_scene(scene: &Scene) {
+    let actual = render(scene);
+    let reference_path = format!("tests/golden/{}.ppm", scene.name);
+    if env::var_os("M3D_UPDATE_GOLDEN").is_some() {
+        fs::write(
+            &reference_path,
+            imagefile::encode_ppm(&actual, WIDTH, HEIGHT),
+        )
+        .unwrap();
+        return;
+    }
+
+    let reference = fs::read(&reference_path)
+        .ok()
+        .and_then(|data| imagefile::decode_ppm(&data))
+        .unwrap_or_else(|| {
+            panic!(
+                "{} is missing or unreadable, run with M3D_UPDATE_GOLDEN=1",
+                reference_path
+            )
+        });
+    assert_eq!(
+        (reference.0, reference.1),
+        (WIDTH, HEIGHT),
+        "{} is the wrong size",
+        reference_path
+    );
+
+    let (bad_pixels, diff) = compare(&reference.2, &actual);
+    if bad_pixels > MAX_BAD_PIXELS {
+        let out_dir = format!("{}/golden", env!("CARGO_TARGET_TMPDIR"));
+        fs::create_dir_all(&out_dir).unwrap();
+        let actual_path = format!("{}/{}.actual.ppm", out_dir, scene.name);
+        let diff_path = format!("{}/{}.diff.ppm", out_dir, scene.name);
+        fs::write(&actual_path, imagefile::encode_ppm(&actual, WIDTH, HEIGHT)).unwrap();
+        fs::write(&diff_path, imagefile::encode_ppm(&diff, WIDTH, HEIGHT)).unwrap();
+        panic!(
+            "{}: {} pixels differ from the reference (at most {} allowed), see {} and {}",
+            scene.name, bad_pixels, MAX_BAD_PIXELS, actual_path, diff_path
+        );
+    }
+}
+
+#[test]
+fn single_cube() {
+    check_scene(&SINGLE_CUBE);
+}
+#[test]
+fn checker_floor() {
+    check_scene(&CHECKER_FLOOR);
+}
+#[test]
+fn lit_corner() {
+    check_scene(&LIT_CORNER);
+}
+#[test]
+fn near_plane_clip() {
+    check_scene(&NEAR_PLANE_CLIP);
+}
+#[test]
+fn compare_only_flags_pixels_past_the_tolerance() {
+    let reference = vec![100; 4 * 3];
+    let mut actual = reference.clone();
+    actual[0] += CHANNEL_TOLERANCE;
+    actual[6] -= CHANNEL_TOLERANCE + 1;
+    let (bad_pixels, diff) = compare(&reference, &actual);
+    assert_eq!(bad_pixels, 1);
+    assert_eq!(&diff[4..8], &[255, 0, 0, 255]);
+    assert_eq!(&diff[..4], &[50, 50, 50, 255]);
+}
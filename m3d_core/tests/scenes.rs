@@ -0,0 +1,188 @@
+use m3d_core::rendering::antialias::AntiAliasing;
+use m3d_core::rendering::gfx::{Color, Light, NearLight};
+use m3d_core::rendering::mesh::Mesh;
+use m3d_core::rendering::raster::{LightingQuality, RasterMode};
+use m3d_core::rendering::sky::Sky;
+use m3d_core::rendering::texturepack;
+use m3d_core::rendermath::{Point3D, Vec3};
+use m3d_core::world::{Entity, EntityKind, WorldData};
+use m3d_core::GameState;
+use std::fs;
+
+// Behaviour that only shows up when a whole frame is rendered, checked by rendering the same
+// scene two ways and comparing. Exact frames are pinned down in golden.rs instead
+
+fn load_textures() -> Vec<u8> {
+    let texture_string = fs::read_to_string("images.txt").expect("Cannot open images.txt");
+    texturepack::decode(&texture_string).unwrap()
+}
+
+// GameState::new's floor seen from above at an angle, rendered once after configure
+fn floor(
+    width: usize,
+    height: usize,
+    texture_array: &[u8],
+    configure: impl FnOnce(&mut GameState),
+) -> GameState {
+    let mut game_state = GameState::new(width, height, texture_array).unwrap();
+    configure(&mut game_state);
+    game_state.translate_camera(290, -50, 35);
+    game_state.rotate_camera(0.3, -1.0);
+    game_state.render(0);
+    game_state
+}
+
+// Only what's in world_data, with the camera at (30, 30, 0) looking along +z. Nothing is
+// rendered yet so entities can be added first
+fn open_space(
+    width: usize,
+    height: usize,
+    texture_array: &[u8],
+    world_data: WorldData,
+) -> GameState {
+    let mut game_state = GameState::with_world(width, height, texture_array, world_data).unwrap();
+    game_state.set_camera_position(30.0, 30.0, 0.0);
+    game_state.set_camera_look(0.0, 0.0);
+    game_state
+}
+
+#[test]
+fn tiled_matches_immediate() {
+    let texture_array = load_textures();
+    let render = |raster_mode| {
+        floor(1265, 632, &texture_array, |game_state| {
+            game_state.set_raster_mode(raster_mode)
+        })
+        .get_pixels()
+        .to_vec()
+    };
+    let immediate = render(RasterMode::Immediate);
+    assert!(immediate == render(RasterMode::Tiled { threads: 1 }));
+    assert!(immediate == render(RasterMode::Tiled { threads: 4 }));
+}
+
+#[test]
+fn per_pixel_lighting_close_to_per_vertex() {
+    let texture_array = load_textures();
+    let render = |lighting_quality| {
+        floor(1265, 632, &texture_array, |game_state| {
+            game_state.set_lighting_quality(lighting_quality)
+        })
+        .get_pixels()
+        .to_vec()
+    };
+    let per_vertex = render(LightingQuality::PerVertex);
+    let per_pixel = render(LightingQuality::PerPixel);
+    // Same coverage and roughly the same colors, per pixel just has sharper light falloff
+    let total_difference: u64 = per_vertex
+        .iter()
+        .zip(per_pixel.iter())
+        .map(|(a, b)| (*a as i32 - *b as i32).unsigned_abs() as u64)
+        .sum();
+    assert!(per_vertex != per_pixel);
+    assert!(total_difference < 8 * per_vertex.len() as u64);
+}
+
+#[test]
+fn anti_aliasing_keeps_output_size() {
+    let texture_array = load_textures();
+    for anti_aliasing in [AntiAliasing::Supersample(2), AntiAliasing::Fxaa] {
+        let mut game_state = floor(320, 160, &texture_array, |_| {});
+        let aliased = game_state.get_pixels().to_vec();
+
+        game_state.set_anti_aliasing(anti_aliasing);
+        game_state.render(0);
+        let smoothed = game_state.get_pixels();
+        assert_eq!(aliased.len(), smoothed.len());
+        assert!(aliased != smoothed);
+    }
+}
+
+#[test]
+fn custom_sky_outlasts_the_day_cycle() {
+    let texture_array = load_textures();
+    // Nothing in the world, so the whole frame is sky
+    let mut game_state = open_space(64, 32, &texture_array, WorldData::new(1));
+    let color = Color::new(1, 2, 3, 255);
+    game_state.set_sky(Sky::Gradient {
+        zenith: color,
+        horizon: color,
+        ground: color,
+    });
+    game_state.set_day_length(1000);
+    for time in [0, 250, 500] {
+        game_state.render(time);
+        assert_eq!(game_state.get_pixels()[..3], [1, 2, 3]);
+    }
+
+    game_state.use_day_cycle_sky();
+    game_state.render(750);
+    assert_ne!(game_state.get_pixels()[..3], [1, 2, 3]);
+}
+
+#[test]
+fn frame_stats_match_between_raster_modes() {
+    let texture_array = load_textures();
+    let render = |raster_mode| {
+        *floor(640, 320, &texture_array, |game_state| {
+            game_state.set_raster_mode(raster_mode)
+        })
+        .frame_stats()
+    };
+    let immediate = render(RasterMode::Immediate);
+    let tiled = render(RasterMode::Tiled { threads: 4 });
+    assert!(immediate.faces_emitted > immediate.triangles_backface_culled);
+    assert!(immediate.raster.pixels_shaded > 0);
+    assert_eq!(immediate.raster, tiled.raster);
+    assert_eq!(immediate.bundles_visited, tiled.bundles_visited);
+}
+
+#[test]
+fn entities_are_culled_outside_the_frustum() {
+    let texture_array = load_textures();
+    let faces_emitted = |z: f32| {
+        let mut game_state = open_space(320, 160, &texture_array, WorldData::new(1));
+        let entities = game_state.entities_mut();
+        let half_extents = Vec3::new(2.0, 2.0, 2.0);
+        let mesh = entities.add_mesh(Mesh::cuboid(half_extents, 0));
+        entities.add(Entity::new(
+            EntityKind::Mob,
+            Vec3::new(30.0, 30.0, z),
+            half_extents,
+            mesh,
+        ));
+        game_state.render(0);
+        game_state.frame_stats().faces_emitted
+    };
+    assert_eq!(faces_emitted(40.0), 12);
+    assert_eq!(faces_emitted(-40.0), 0);
+}
+
+#[test]
+fn per_pixel_lighting_uses_vertex_normals() {
+    let texture_array = load_textures();
+    let render = |normal: Vec3| {
+        let mut world_data = WorldData::new(1);
+        world_data.lights.push(Light::Near(NearLight::new(
+            Color::new(255, 255, 255, 255),
+            3000,
+            Point3D::from_euc_coords_float(30.0, 30.0, 20.0),
+        )));
+        let mut game_state = open_space(320, 160, &texture_array, world_data);
+        game_state.set_lighting_quality(LightingQuality::PerPixel);
+        // Faces the camera and the light whatever its normals say
+        let mut mesh = Mesh::quad(10.0, 10.0, 0);
+        mesh.normals = vec![normal; mesh.positions.len()];
+        let entities = game_state.entities_mut();
+        let mesh = entities.add_mesh(mesh);
+        entities.add(Entity::new(
+            EntityKind::Sign,
+            Vec3::new(30.0, 30.0, 40.0),
+            Vec3::new(10.0, 10.0, 0.1),
+            mesh,
+        ));
+        game_state.render(0);
+        game_state.get_pixels().to_vec()
+    };
+    assert!(render(Vec3::new(0.0, 0.0, -1.0)) != render(Vec3::new(0.0, 0.0, 1.0)));
+}
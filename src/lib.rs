@@ -3,45 +3,111 @@
 mod wasm_interopt;
 extern crate m3d_core;
 
+use wasm_interopt::{check, core_error, game_state, status, Status, Uint8Array};
+
+// Imported from the page as env.performance_now, std has no clock on wasm32-unknown-unknown
+#[cfg(target_arch = "wasm32")]
+#[link(wasm_import_module = "env")]
+extern "C" {
+    fn performance_now() -> f64;
+}
+#[cfg(target_arch = "wasm32")]
+fn js_clock() -> f64 {
+    unsafe { performance_now() }
+}
+
+/// Owned, give it back to destroy_game_state. Null if tex is null or the game state can't be
+/// made, see get_last_error
+///
+/// # Safety
+/// tex has to be null or a live Uint8Array handle. It's only read, the caller still owns it
+/// and frees it as usual
 #[no_mangle]
 pub unsafe extern "C" fn make_game_state(
     width: usize,
     height: usize,
-    tex: *mut wasm_interopt::Uint8Array,
+    tex: *mut Uint8Array,
 ) -> *mut m3d_core::GameState {
-    let owned_textures = Box::from_raw(tex);
-    let texture_array = owned_textures.get_data();
+    let textures = match Uint8Array::from_ptr(tex) {
+        Ok(textures) => textures,
+        Err(_) => return std::ptr::null_mut(),
+    };
+
+    let game_state = match m3d_core::GameState::new(width, height, textures.as_slice()) {
+        Ok(game_state) => game_state,
+        Err(error) => {
+            core_error(error);
+            return std::ptr::null_mut();
+        }
+    };
+    #[cfg(target_arch = "wasm32")]
+    let game_state = {
+        let mut game_state = game_state;
+        game_state.set_clock(Some(js_clock));
+        game_state
+    };
 
-    Box::into_raw(Box::new(m3d_core::GameState::new(
-        width,
-        height,
-        &texture_array,
-    )))
+    Box::into_raw(Box::new(game_state))
 }
 
+/// Any handles into the game state have to be freed before this
+///
+/// # Safety
+/// input has to be null or come from make_game_state, and isn't usable afterwards
 #[no_mangle]
-pub extern "C" fn get_pixel_data(
-    input: *mut m3d_core::GameState,
-) -> *mut wasm_interopt::Uint8Array {
-    unsafe { wasm_interopt::Uint8Array::new((*input).get_mut_pixels()) }
+pub unsafe extern "C" fn destroy_game_state(input: *mut m3d_core::GameState) -> u32 {
+    status(game_state(input).map(|_| drop(Box::from_raw(input))))
 }
 
+/// Turns the text of a texture pack (images.txt) into the RGBA bytes make_game_state wants.
+/// Owned, null if text is null or isn't a valid pack
+///
+/// # Safety
+/// text has to be null or a live Uint8Array handle, it's only read
 #[no_mangle]
-pub extern "C" fn render_game(input: *mut m3d_core::GameState, curr_time: usize) {
-    unsafe {
-        (*input).render(curr_time);
+pub unsafe extern "C" fn decode_texture_pack(text: *mut Uint8Array) -> *mut Uint8Array {
+    let decoded = Uint8Array::from_ptr(text).and_then(|text| {
+        let text = std::str::from_utf8(text.as_slice())
+            .map_err(|_| wasm_interopt::fail(Status::InvalidArgument, "not UTF-8".to_string()))?;
+        m3d_core::rendering::texturepack::decode(text).map_err(core_error)
+    });
+    match decoded {
+        Ok(decoded) => Uint8Array::owned(decoded),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+// Borrowed handle to the frame, free it once it's been wrapped. The frame moves when the
+// output resolution changes (set_anti_aliasing), so fetch it again after that. Null for a null
+// game state
+#[no_mangle]
+pub extern "C" fn get_pixel_data(input: *mut m3d_core::GameState) -> *mut Uint8Array {
+    match unsafe { game_state(input) } {
+        Ok(game_state) => Uint8Array::borrowed(game_state.get_mut_pixels()),
+        Err(_) => std::ptr::null_mut(),
     }
 }
 
+// Everything below that takes a game state returns a Status (or a documented placeholder
+// value) when it's null
+
+#[no_mangle]
+pub extern "C" fn render_game(input: *mut m3d_core::GameState, curr_time: usize) -> u32 {
+    unsafe { status(game_state(input).map(|game_state| game_state.render(curr_time))) }
+}
+
 #[no_mangle]
 pub extern "C" fn translate_camera(
     input: *mut m3d_core::GameState,
     trans_x: isize,
     trans_y: isize,
     trans_z: isize,
-) {
+) -> u32 {
     unsafe {
-        (*input).translate_camera(trans_x, trans_y, trans_z);
+        status(
+            game_state(input)
+                .map(|game_state| game_state.translate_camera(trans_x, trans_y, trans_z)),
+        )
     }
 }
 
@@ -50,12 +116,166 @@ pub extern "C" fn rotate_camera(
     input: *mut m3d_core::GameState,
     d_rotation: f32,
     d_inclination: f32,
-) {
+) -> u32 {
+    unsafe {
+        status(
+            game_state(input).map(|game_state| game_state.rotate_camera(d_rotation, d_inclination)),
+        )
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn roll_camera(input: *mut m3d_core::GameState, d_roll: f32) -> u32 {
+    unsafe { status(game_state(input).map(|game_state| game_state.roll_camera(d_roll))) }
+}
+
+#[no_mangle]
+pub extern "C" fn set_per_pixel_lighting(input: *mut m3d_core::GameState, per_pixel: bool) -> u32 {
+    let lighting_quality = if per_pixel {
+        m3d_core::rendering::raster::LightingQuality::PerPixel
+    } else {
+        m3d_core::rendering::raster::LightingQuality::PerVertex
+    };
+    unsafe {
+        status(
+            game_state(input).map(|game_state| game_state.set_lighting_quality(lighting_quality)),
+        )
+    }
+}
+
+// mode is 0 for off, 1 for supersampling by factor on each axis, 2 for fxaa
+#[no_mangle]
+pub extern "C" fn set_anti_aliasing(
+    input: *mut m3d_core::GameState,
+    mode: u32,
+    factor: usize,
+) -> u32 {
+    let anti_aliasing = match mode {
+        1 => m3d_core::rendering::antialias::AntiAliasing::Supersample(factor),
+        2 => m3d_core::rendering::antialias::AntiAliasing::Fxaa,
+        _ => m3d_core::rendering::antialias::AntiAliasing::Off,
+    };
+    unsafe {
+        status(game_state(input).map(|game_state| game_state.set_anti_aliasing(anti_aliasing)))
+    }
+}
+
+// view is 0 for off, 1 for wireframe, 2 for normals, 3 for depth, 4 for overdraw
+#[no_mangle]
+pub extern "C" fn set_debug_view(input: *mut m3d_core::GameState, view: u32) -> u32 {
+    let debug_view = match view {
+        1 => m3d_core::rendering::debug::DebugView::Wireframe,
+        2 => m3d_core::rendering::debug::DebugView::Normals,
+        3 => m3d_core::rendering::debug::DebugView::Depth,
+        4 => m3d_core::rendering::debug::DebugView::Overdraw,
+        _ => m3d_core::rendering::debug::DebugView::Off,
+    };
+    unsafe { status(game_state(input).map(|game_state| game_state.set_debug_view(debug_view))) }
+}
+
+#[no_mangle]
+pub extern "C" fn set_debug_overlays(
+    input: *mut m3d_core::GameState,
+    bundles: bool,
+    lights: bool,
+) -> u32 {
+    unsafe {
+        status(game_state(input).map(|game_state| game_state.set_debug_overlays(bundles, lights)))
+    }
+}
+
+// indx follows m3d_core::rendering::stats::FrameStats::NAMES: bundles visited, bundles
+// culled, faces emitted, triangles backface culled, pixels shaded, z-test failures, then
+// update, sky, geometry, raster, post and total ms. Out of range indices (or a null game
+// state) give -1
+#[no_mangle]
+pub extern "C" fn get_frame_stat(input: *mut m3d_core::GameState, indx: usize) -> f64 {
+    match unsafe { game_state(input) } {
+        Ok(game_state) => *game_state.frame_stats().values().get(indx).unwrap_or(&-1.0),
+        Err(_) => -1.0,
+    }
+}
+
+// Stages are fog towards the sky (on), then tonemap, color grade, gamma and vignette (off) to
+// begin with. Indices out of range give InvalidArgument
+#[no_mangle]
+pub extern "C" fn set_post_process_enabled(
+    input: *mut m3d_core::GameState,
+    indx: usize,
+    enabled: bool,
+) -> u32 {
+    unsafe {
+        status(game_state(input).and_then(|game_state| {
+            check(
+                game_state.set_post_process_enabled(indx, enabled),
+                "post process index out of range",
+            )
+        }))
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn move_post_process(
+    input: *mut m3d_core::GameState,
+    from: usize,
+    to: usize,
+) -> u32 {
     unsafe {
-        (*input).rotate_camera(d_rotation, d_inclination);
+        status(game_state(input).and_then(|game_state| {
+            check(
+                game_state.move_post_process(from, to),
+                "post process index out of range",
+            )
+        }))
+    }
+}
+
+// Hours since midnight, 0 to 24. NaN for a null game state
+#[no_mangle]
+pub extern "C" fn get_time_of_day(input: *mut m3d_core::GameState) -> f32 {
+    unsafe { game_state(input).map_or(f32::NAN, |game_state| game_state.time_of_day()) }
+}
+
+#[no_mangle]
+pub extern "C" fn set_time_of_day(input: *mut m3d_core::GameState, time: f32) -> u32 {
+    unsafe { status(game_state(input).map(|game_state| game_state.set_time_of_day(time))) }
+}
+
+#[no_mangle]
+pub extern "C" fn set_day_length(input: *mut m3d_core::GameState, day_length: usize) -> u32 {
+    unsafe { status(game_state(input).map(|game_state| game_state.set_day_length(day_length))) }
+}
+
+// threads is 0 to draw triangles as they're submitted, otherwise frames are drawn in tiles on
+// up to that many threads. Anything past 1 needs the threads build and workers from the page
+#[no_mangle]
+pub extern "C" fn set_raster_threads(input: *mut m3d_core::GameState, threads: usize) -> u32 {
+    let raster_mode = match threads {
+        0 => m3d_core::rendering::raster::RasterMode::Immediate,
+        _ => m3d_core::rendering::raster::RasterMode::Tiled { threads },
+    };
+    unsafe { status(game_state(input).map(|game_state| game_state.set_raster_mode(raster_mode))) }
+}
+
+// Memory for a worker's stack or thread locals, kept for the life of the page. Null if size
+// is 0 or align isn't a power of two
+#[cfg(all(target_arch = "wasm32", feature = "threads"))]
+#[no_mangle]
+pub extern "C" fn alloc_worker_memory(size: usize, align: usize) -> *mut u8 {
+    match std::alloc::Layout::from_size_align(size, align) {
+        Ok(layout) if size > 0 => unsafe { std::alloc::alloc(layout) },
+        _ => std::ptr::null_mut(),
     }
 }
 
+// Where every worker the page starts ends up once its stack and thread locals are set up,
+// see client/worker.js. Never returns
+#[cfg(all(target_arch = "wasm32", feature = "threads"))]
+#[no_mangle]
+pub extern "C" fn worker_entry() {
+    m3d_core::rendering::workers::run_worker()
+}
+
 // tests
 #[no_mangle]
 pub extern "C" fn test_return_5() -> usize {
@@ -63,6 +283,56 @@ pub extern "C" fn test_return_5() -> usize {
 }
 
 #[no_mangle]
-pub extern "C" fn test_return_arr() -> *mut wasm_interopt::Uint8Array {
-    unsafe { wasm_interopt::Uint8Array::from_vec(vec![1, 2, 3, 4, 5]) }
+pub extern "C" fn test_return_arr() -> *mut Uint8Array {
+    Uint8Array::owned(vec![1, 2, 3, 4, 5])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_interopt::{
+        free_uint8_arr, get_array_length, get_last_error, is_array_owned, new_uint8_arr,
+    };
+
+    #[test]
+    fn handles_have_clear_ownership() {
+        unsafe {
+            let tex = new_uint8_arr(4 * 128 * 128 * 2);
+            let input = make_game_state(64, 32, tex);
+            assert!(!input.is_null());
+            assert!(is_array_owned(tex));
+            assert_eq!(free_uint8_arr(tex), Status::Ok as u32);
+
+            let pixels = get_pixel_data(input);
+            assert!(!is_array_owned(pixels));
+            assert_eq!(get_array_length(pixels), 4 * 64 * 32);
+            assert_eq!(free_uint8_arr(pixels), Status::Ok as u32);
+            assert_eq!(
+                set_post_process_enabled(input, 100, true),
+                Status::InvalidArgument as u32
+            );
+            assert_eq!(destroy_game_state(input), Status::Ok as u32);
+
+            let null = std::ptr::null_mut();
+            assert!(make_game_state(64, 32, std::ptr::null_mut()).is_null());
+            assert!(get_pixel_data(null).is_null());
+            assert_eq!(render_game(null, 0), Status::NullPointer as u32);
+            assert_eq!(destroy_game_state(null), Status::NullPointer as u32);
+            assert_eq!(
+                free_uint8_arr(std::ptr::null_mut()),
+                Status::NullPointer as u32
+            );
+
+            // One texture isn't enough for the default materials
+            let tex = new_uint8_arr(4 * 128 * 128);
+            assert!(make_game_state(64, 32, tex).is_null());
+            let message = get_last_error();
+            assert!(is_array_owned(message));
+            assert!(std::str::from_utf8((*message).as_slice())
+                .unwrap()
+                .contains("texture 1"));
+            free_uint8_arr(message);
+            free_uint8_arr(tex);
+        }
+    }
 }
@@ -1,57 +1,157 @@
 #![allow(clippy::not_unsafe_ptr_arg_deref)]
-//use std::mem;
+use m3d_core::Error;
+use std::cell::RefCell;
 
-#[repr(C)]
+// Returned by exports that can fail, instead of panicking across the boundary. The message
+// for the last failure can be fetched with get_last_error
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Ok = 0,
+    NullPointer = 1,
+    // Index out of range and so on
+    InvalidArgument = 2,
+    // One per m3d_core::Error variant
+    InvalidSize = 3,
+    TextureLength = 4,
+    MissingTexture = 5,
+    OutOfBounds = 6,
+    TextureDecode = 7,
+    WorldParse = 8,
+    MeshParse = 9,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+// Records the message and hands the status back
+pub fn fail(status: Status, message: String) -> Status {
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = message);
+    status
+}
+pub fn core_error(error: Error) -> Status {
+    let status = match error {
+        Error::InvalidSize { .. } => Status::InvalidSize,
+        Error::TextureLength { .. } => Status::TextureLength,
+        Error::MissingTexture { .. } => Status::MissingTexture,
+        Error::OutOfBounds { .. } => Status::OutOfBounds,
+        Error::TextureDecode { .. } => Status::TextureDecode,
+        Error::WorldParse { .. } => Status::WorldParse,
+        Error::MeshParse { .. } => Status::MeshParse,
+    };
+    fail(status, error.to_string())
+}
+pub fn check(ok: bool, message: &str) -> Result<(), Status> {
+    if ok {
+        Ok(())
+    } else {
+        Err(fail(Status::InvalidArgument, message.to_string()))
+    }
+}
+pub fn status(result: Result<(), Status>) -> u32 {
+    match result {
+        Ok(()) => Status::Ok as u32,
+        Err(status) => status as u32,
+    }
+}
+
+// Handle to a byte buffer that JS can read and write through get_array_data.
+// Owned handles hold their data and free it with the handle. Borrowed handles point into
+// memory something else owns (the frame of a GameState, say): they're only valid until that
+// owner changes or goes away, and freeing one only frees the handle.
+// Every handle passed out, either kind, has to be given back to free_uint8_arr exactly once
 pub struct Uint8Array {
     pub data: *mut u8,
     pub length: usize,
     data_owner: Option<Vec<u8>>,
 }
 impl Uint8Array {
-    pub fn new(buf: &mut [u8]) -> *mut Self {
-        let len = buf.len();
-        let ptr = buf.as_mut_ptr();
-        //mem::forget(buf);
+    pub fn borrowed(buf: &mut [u8]) -> *mut Self {
         let to_return = Self {
-            data: ptr,
-            length: len,
+            data: buf.as_mut_ptr(),
+            length: buf.len(),
             data_owner: None,
         };
         Box::into_raw(Box::new(to_return))
     }
-    pub unsafe fn from_vec(mut input: Vec<u8>) -> *mut Self {
-        //let mut owner = Box::new(input);
-        let length = input.len();
-        let ptr = input.as_mut_ptr();
+    pub fn owned(mut input: Vec<u8>) -> *mut Self {
         let to_return = Self {
-            data: ptr,
-            length,
+            data: input.as_mut_ptr(),
+            length: input.len(),
             data_owner: Some(input),
         };
         Box::into_raw(Box::new(to_return))
     }
-    pub fn get_data(self) -> Vec<u8> {
-        self.data_owner.unwrap()
+    pub fn is_owned(&self) -> bool {
+        self.data_owner.is_some()
+    }
+    pub fn as_slice(&self) -> &[u8] {
+        match &self.data_owner {
+            Some(data) => data,
+            None => unsafe { std::slice::from_raw_parts(self.data, self.length) },
+        }
+    }
+    /// # Safety
+    /// input has to be null or a live handle from one of the functions above
+    pub unsafe fn from_ptr<'a>(input: *mut Self) -> Result<&'a mut Self, Status> {
+        input
+            .as_mut()
+            .ok_or_else(|| fail(Status::NullPointer, "buffer is null".to_string()))
     }
 }
 
+/// # Safety
+/// input has to be null or a live pointer from make_game_state
+pub unsafe fn game_state<'a>(
+    input: *mut m3d_core::GameState,
+) -> Result<&'a mut m3d_core::GameState, Status> {
+    input
+        .as_mut()
+        .ok_or_else(|| fail(Status::NullPointer, "game state is null".to_string()))
+}
+
+/// Null for a null handle
+///
+/// # Safety
+/// input has to be null or a live Uint8Array handle
 #[no_mangle]
 pub unsafe extern "C" fn get_array_data(input: *mut Uint8Array) -> *mut u8 {
-    (*input).data
+    Uint8Array::from_ptr(input).map_or(std::ptr::null_mut(), |array| array.data)
 }
 
+/// 0 for a null handle
+///
+/// # Safety
+/// input has to be null or a live Uint8Array handle
 #[no_mangle]
 pub unsafe extern "C" fn get_array_length(input: *mut Uint8Array) -> usize {
-    (*input).length
+    Uint8Array::from_ptr(input).map_or(0, |array| array.length)
+}
+
+/// # Safety
+/// input has to be null or a live Uint8Array handle
+#[no_mangle]
+pub unsafe extern "C" fn is_array_owned(input: *mut Uint8Array) -> bool {
+    Uint8Array::from_ptr(input).is_ok_and(|array| array.is_owned())
+}
+
+/// # Safety
+/// input has to be null or a live Uint8Array handle, and isn't usable afterwards. Borrowed
+/// data is left alone
+#[no_mangle]
+pub unsafe extern "C" fn free_uint8_arr(input: *mut Uint8Array) -> u32 {
+    status(Uint8Array::from_ptr(input).map(|_| drop(Box::from_raw(input))))
 }
 
+// Owned and zeroed
 #[no_mangle]
-pub unsafe extern "C" fn free_uint8_arr(input: *mut Uint8Array) {
-    drop(Box::from_raw(input));
+pub extern "C" fn new_uint8_arr(length: usize) -> *mut Uint8Array {
+    Uint8Array::owned(vec![0; length])
 }
 
+// Owned UTF-8 copy of the message for the last failure, empty if nothing's failed yet
 #[no_mangle]
-pub unsafe extern "C" fn new_uint8_arr(length: usize) -> *mut Uint8Array {
-    let new_vec: Vec<u8> = vec![0; length];
-    Uint8Array::from_vec(new_vec)
+pub extern "C" fn get_last_error() -> *mut Uint8Array {
+    LAST_ERROR.with(|last_error| Uint8Array::owned(last_error.borrow().as_bytes().to_vec()))
 }